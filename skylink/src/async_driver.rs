@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use crossbeam_channel::{unbounded, Receiver, Sender, TryRecvError};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use wg_2024::controller::{DroneCommand, DroneEvent};
+use wg_2024::drone::Drone;
+use wg_2024::network::NodeId;
+use wg_2024::packet::Packet;
+use crate::drone::{SkyLinkDrone, Step};
+
+/// Steps a busy drone takes before letting the other tasks of the executor run.
+const STEPS_BEFORE_YIELD: usize = 32;
+/// Longest an idle drone sleeps: the channels that aren't async (e.g. the one of `with_command_channel`)
+/// can't wake it up, so they're checked at least this often.
+const SIDE_CHANNEL_POLL: Duration = Duration::from_millis(20);
+
+/// A SkyLinkDrone driven by an async task, for networks where clients and servers are async too.
+/// The drone is the same one `run` drives on a thread: its packets and commands come from tokio channels
+/// and are handled, one `step` at a time, by the usual code.
+pub struct AsyncDrone {
+    drone: SkyLinkDrone,
+    packet_in: Option<Sender<Packet>>, //Dropped once the neighbours are gone and the drone is crashing, so that it stops.
+    command_in: Sender<DroneCommand>,
+    packet_recv: Option<UnboundedReceiver<Packet>>,
+    controller_recv: Option<UnboundedReceiver<DroneCommand>>,
+    outboxes: Vec<Outbox<Packet>>,
+    events: Outbox<DroneEvent>,
+}
+
+//What the drone sends on a channel of its own, waiting to be moved to the async one.
+struct Outbox<T> {
+    recv: Option<Receiver<T>>, //Dropped when the async side is gone, so that the drone can't send anymore.
+    send: UnboundedSender<T>,
+}
+
+impl<T> Outbox<T> {
+    fn new(send: UnboundedSender<T>) -> (Sender<T>, Outbox<T>) {
+        let (drone_send, recv) = unbounded();
+        (drone_send, Outbox { recv: Some(recv), send })
+    }
+
+    //Returns false once the drone dropped its end (e.g. after a RemoveSender), so that the async one is dropped too.
+    fn forward(&mut self) -> bool {
+        let Some(recv) = self.recv.as_ref() else {
+            return true;
+        };
+        loop {
+            match recv.try_recv() {
+                Ok(item) => {
+                    if self.send.send(item).is_err() {
+                        self.recv = None;
+                        return true;
+                    }
+                },
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+    }
+}
+
+impl AsyncDrone {
+    /// Same as `SkyLinkDrone::new`, with async channels.
+    pub fn new(
+        id: NodeId,
+        controller_send: UnboundedSender<DroneEvent>,
+        controller_recv: UnboundedReceiver<DroneCommand>,
+        packet_recv: UnboundedReceiver<Packet>,
+        packet_send: HashMap<NodeId, UnboundedSender<Packet>>,
+        pdr: f32,
+    ) -> Self {
+        let (packet_in, drone_packet_recv) = unbounded();
+        let (command_in, drone_command_recv) = unbounded();
+        let (drone_event_send, events) = Outbox::new(controller_send);
+        let mut drone_packet_send = HashMap::new();
+        let mut outboxes = Vec::new();
+        for (id, send) in packet_send {
+            let (drone_send, outbox) = Outbox::new(send);
+            drone_packet_send.insert(id, drone_send);
+            outboxes.push(outbox);
+        }
+
+        AsyncDrone {
+            drone: SkyLinkDrone::new(id, drone_event_send, drone_command_recv, drone_packet_recv, drone_packet_send, pdr),
+            packet_in: Some(packet_in),
+            command_in,
+            packet_recv: Some(packet_recv),
+            controller_recv: Some(controller_recv),
+            outboxes,
+            events,
+        }
+    }
+
+    /// Gives the drone to `configure`, to set it up with the builders of `SkyLinkDrone` (seed, checks, links...).
+    pub fn configure(mut self, configure: impl FnOnce(SkyLinkDrone) -> SkyLinkDrone) -> Self {
+        self.drone = configure(self.drone);
+        self
+    }
+
+    pub fn get_drone(&self) -> &SkyLinkDrone {
+        &self.drone
+    }
+
+    /// Runs the drone until it crashes and stops, as `SkyLinkDrone::run` does on its thread.
+    /// The neighbours added later with `DroneCommand::AddSender` get their packets on the (blocking) channel of the command.
+    /// An idle drone still wakes up every `SIDE_CHANNEL_POLL` (20ms) to check the channels that aren't async:
+    /// about 50 wakeups per second for each drone, even when the network is quiet.
+    pub async fn run(mut self) {
+        loop {
+            let mut steps = 0;
+            let step = loop {
+                match self.drone.step() {
+                    Step::Busy => {
+                        steps += 1;
+                        if steps % STEPS_BEFORE_YIELD == 0 {
+                            self.forward();
+                            tokio::task::yield_now().await;
+                        }
+                    },
+                    step => break step,
+                }
+            };
+            self.forward();
+            let wait = match step {
+                Step::Idle(wait) => wait.min(SIDE_CHANNEL_POLL),
+                _ => return,
+            };
+            self.wait_input(wait).await;
+        }
+    }
+
+    //Waits until a packet or a command arrives, or for `wait` at most, and gives it to the drone.
+    async fn wait_input(&mut self, wait: Duration) {
+        let packet_recv = self.packet_recv.as_mut();
+        let controller_recv = self.controller_recv.as_mut();
+        tokio::select! {
+            biased;
+            command = recv(controller_recv) => match command {
+                Some(command) => {
+                    let _ = self.command_in.send(command);
+                },
+                //The drone keeps its own channel, or it would keep waking up on the disconnected one.
+                None => self.controller_recv = None,
+            },
+            packet = recv(packet_recv) => match packet {
+                Some(packet) => {
+                    if let Some(packet_in) = self.packet_in.as_ref() {
+                        let _ = packet_in.send(packet);
+                    }
+                },
+                None => self.packet_recv = None,
+            },
+            _ = tokio::time::sleep(wait) => {},
+        }
+        //Like a drone on its thread, a crashing drone stops once no neighbour can send to it anymore.
+        if self.packet_recv.is_none() && self.drone.is_crashing() {
+            self.packet_in = None;
+        }
+    }
+
+    fn forward(&mut self) {
+        self.outboxes.retain_mut(Outbox::forward);
+        self.events.forward();
+    }
+}
+
+//Receives from the channel, or never if there's no channel anymore.
+async fn recv<T>(recv: Option<&mut UnboundedReceiver<T>>) -> Option<T> {
+    match recv {
+        Some(recv) => recv.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Makes the items of a blocking channel available to async tasks, e.g. the commands of a controller that runs on a thread.
+/// It must be called inside a tokio runtime, which runs the forwarding on its blocking threads.
+pub fn forward_to_async<T: Send + 'static>(recv: Receiver<T>) -> UnboundedReceiver<T> {
+    let (send, async_recv) = unbounded_channel();
+    tokio::task::spawn_blocking(move || {
+        for item in recv {
+            if send.send(item).is_err() {
+                break;
+            }
+        }
+    });
+    async_recv
+}
+
+/// Makes the items of an async channel available to blocking code, e.g. the events for a controller that runs on a thread.
+/// It must be called inside a tokio runtime, which runs the forwarding as a task.
+pub fn forward_from_async<T: Send + 'static>(mut recv: UnboundedReceiver<T>) -> Receiver<T> {
+    let (send, blocking_recv) = unbounded();
+    tokio::spawn(async move {
+        while let Some(item) = recv.recv().await {
+            if send.send(item).is_err() {
+                break;
+            }
+        }
+    });
+    blocking_recv
+}
@@ -0,0 +1,32 @@
+//! Replays the capture of a drone and tells whether it behaves the same way:
+//! `cargo run --bin replay -- captures/drone_11.jsonl`
+use std::env;
+use std::process::ExitCode;
+use skylink::{replay, Capture};
+
+fn main() -> ExitCode {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("Usage: replay <capture file>");
+        return ExitCode::FAILURE;
+    };
+    let capture = match Capture::read_from(&path) {
+        Ok(capture) => capture,
+        Err(error) => {
+            eprintln!("Can't read {}: {}", path, error);
+            return ExitCode::FAILURE;
+        },
+    };
+
+    let report = replay(&capture, |drone| drone);
+    println!("Drone {}: {} records replayed", capture.header.snapshot.id, capture.records.len());
+    if report.is_identical() {
+        println!("Identical.");
+        return ExitCode::SUCCESS;
+    }
+    for difference in report.differences.iter() {
+        println!("Output {}:", difference.index);
+        println!("  expected {:?}", difference.expected.as_ref().map(|record| &record.captured));
+        println!("  got      {:?}", difference.actual.as_ref().map(|record| &record.captured));
+    }
+    ExitCode::FAILURE
+}
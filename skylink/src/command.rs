@@ -0,0 +1,24 @@
+use wg_2024::network::NodeId;
+use crate::link::LinkParams;
+use crossbeam_channel::Sender;
+use crate::fault::FaultProfile;
+use crate::stats::DroneStats;
+use crate::snapshot::DroneSnapshot;
+
+/// Commands understood only by a SkyLinkDrone, on top of the wg_2024 `DroneCommand`s, which can't be extended.
+/// They're received only if the drone was given a channel with `SkyLinkDrone::with_command_channel`.
+#[derive(Debug, Clone)]
+pub enum SkyLinkCommand {
+    /// Changes the timing of the link towards a neighbour.
+    SetLink(NodeId, LinkParams),
+    /// Changes the pdr of the link towards a neighbour, None brings it back to the drone's pdr.
+    SetLinkPdr(NodeId, Option<f32>),
+    /// Changes the faults injected by the drone, None makes it behave again.
+    SetFaultProfile(Option<FaultProfile>),
+    /// Changes the longest path_trace of the FloodRequests the drone forwards, None removes the limit.
+    SetFloodLimit(Option<usize>),
+    /// Asks the drone for a snapshot of its traffic statistics, which is sent back on the given channel.
+    GetStats(Sender<DroneStats>),
+    /// Asks the drone for a snapshot of its whole state, which is sent back on the given channel.
+    GetSnapshot(Sender<DroneSnapshot>),
+}
@@ -0,0 +1,103 @@
+use std::collections::{HashSet, VecDeque};
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+use wg_2024::network::NodeId;
+
+/// Capacity of the cache used by `SkyLinkDrone::new`.
+pub const DEFAULT_FLOOD_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(4096).unwrap();
+
+/// Remembers the floodings a drone already met, each one identified by its flood_id and initiator_id.
+///
+/// The cache holds at most `capacity` floodings, when it's full the oldest one is forgotten.
+/// It always remembers at least the last flooding: a drone that forgets everything would forward a flooding forever on a cycle.
+/// Optionally, a flooding is also forgotten once it's older than `max_age`.
+/// A flooding stays in flight only for the time it takes to cross the network, so as long as the
+/// capacity is bigger than the number of floodings running at the same time (and `max_age` longer
+/// than a flooding), the drone de-duplicates exactly like with an unbounded set.
+#[derive(Debug, Clone)]
+pub struct FloodCache {
+    capacity: NonZeroUsize,
+    max_age: Option<Duration>,
+    seen: HashSet<(u64, NodeId)>,
+    order: VecDeque<(u64, NodeId, Instant)>, //Same floodings of 'seen', from the oldest to the newest.
+}
+
+impl Default for FloodCache {
+    fn default() -> Self {
+        FloodCache::new(DEFAULT_FLOOD_CACHE_CAPACITY)
+    }
+}
+
+impl FloodCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        FloodCache {
+            capacity,
+            max_age: None,
+            seen: HashSet::with_capacity(capacity.get()),
+            order: VecDeque::with_capacity(capacity.get()),
+        }
+    }
+
+    /// Forgets the floodings older than `max_age`, even if the cache isn't full.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Adds a flooding to the cache, returning true if it wasn't there (like `HashSet::insert`).
+    /// `now` is read from the clock of the drone, the floodings get older only as that clock moves.
+    pub fn insert(&mut self, flood_id: u64, initiator_id: NodeId, now: Instant) -> bool {
+        self.expire(now);
+        if self.seen.contains(&(flood_id, initiator_id)) {
+            return false;
+        }
+        while self.order.len() >= self.capacity.get() {
+            self.forget_oldest();
+        }
+        self.seen.insert((flood_id, initiator_id));
+        self.order.push_back((flood_id, initiator_id, now));
+        true
+    }
+
+    pub fn contains(&self, flood_id: u64, initiator_id: NodeId) -> bool {
+        self.seen.contains(&(flood_id, initiator_id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn capacity(&self) -> NonZeroUsize {
+        self.capacity
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// The floodings in the cache, from the oldest to the newest.
+    pub fn floodings(&self) -> Vec<(u64, NodeId)> {
+        self.order.iter().map(|&(flood_id, initiator_id, _)| (flood_id, initiator_id)).collect()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        if let Some(max_age) = self.max_age {
+            while let Some(&(_, _, inserted)) = self.order.front() {
+                if now.duration_since(inserted) <= max_age {
+                    break;
+                }
+                self.forget_oldest();
+            }
+        }
+    }
+
+    fn forget_oldest(&mut self) {
+        if let Some((flood_id, initiator_id, _)) = self.order.pop_front() {
+            self.seen.remove(&(flood_id, initiator_id));
+        }
+    }
+}
@@ -1,5 +1,41 @@
+//The packets that can't be sent are given back in the Err, like the SendError of crossbeam, so the Err is as large as a Packet.
+#![allow(clippy::result_large_err)]
+
 mod drone;
 mod error;
 mod checks;
+mod event;
+mod flood_cache;
+mod command;
+mod link;
+mod scheduler;
+mod fault;
+mod stats;
+mod pool;
+mod capture;
+mod battery;
+mod snapshot;
+mod clock;
+mod random;
+mod transport;
+#[cfg(feature = "async")]
+mod async_driver;
 
-pub use drone::*;
\ No newline at end of file
+pub use drone::*;
+pub use checks::*;
+pub use event::*;
+pub use flood_cache::*;
+pub use command::*;
+pub use link::LinkParams;
+pub use scheduler::*;
+pub use fault::*;
+pub use stats::*;
+pub use pool::*;
+pub use capture::*;
+pub use battery::*;
+pub use snapshot::*;
+pub use clock::*;
+pub use random::*;
+pub use transport::*;
+#[cfg(feature = "async")]
+pub use async_driver::*;
\ No newline at end of file
@@ -0,0 +1,141 @@
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use crossbeam_channel::{unbounded, Receiver, Select, Sender};
+use crate::drone::{SkyLinkDrone, Step};
+
+/// Steps a worker gives to a busy drone before moving to the others.
+const STEPS_PER_TURN: usize = 32;
+
+/// A fixed number of threads running many drones, instead of a thread for each drone.
+/// Every worker waits on the channels of all its drones at once, and steps the ones that have work.
+pub struct DronePool {
+    workers: Vec<Sender<SkyLinkDrone>>,
+    handles: Vec<JoinHandle<()>>,
+    next: usize, //Worker that gets the next drone, they're given out in turn.
+}
+
+impl DronePool {
+    pub fn new(workers: usize) -> Self {
+        let mut pool = DronePool {
+            workers: Vec::new(),
+            handles: Vec::new(),
+            next: 0,
+        };
+        for _ in 0..workers.max(1) {
+            let (drone_send, drone_recv) = unbounded();
+            pool.workers.push(drone_send);
+            pool.handles.push(thread::spawn(move || work(drone_recv)));
+        }
+        pool
+    }
+
+    /// Starts running the drone on one of the workers.
+    pub fn add(&mut self, drone: SkyLinkDrone) {
+        let worker = self.next % self.workers.len();
+        self.next += 1;
+        //A worker only leaves once the pool is dropped, so it's still there to take the drone.
+        let _ = self.workers[worker].send(drone);
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Takes the handles of the worker threads: each one ends once the pool is dropped and all its drones stopped.
+    pub fn take_handles(&mut self) -> Vec<JoinHandle<()>> {
+        std::mem::take(&mut self.handles)
+    }
+
+    /// Waits until every drone of the pool has stopped (i.e. crashed and shut down).
+    pub fn join(mut self) {
+        let handles = self.take_handles();
+        drop(self);
+        for handle in handles {
+            let _ = handle.join();
+        }
+    }
+}
+
+struct Entry {
+    drone: SkyLinkDrone,
+    wake_at: Option<Instant>, //When the drone has to be stepped even if nothing arrives, None if never.
+}
+
+fn work(drone_recv: Receiver<SkyLinkDrone>) {
+    let mut drones: Vec<Entry> = Vec::new();
+    let mut pool_open = true;
+    loop {
+        if pool_open {
+            for drone in drone_recv.try_iter() {
+                drones.push(Entry { drone, wake_at: Some(Instant::now()) });
+            }
+        }
+
+        //The drones that have something planned by now are stepped, whether something arrived or not.
+        let now = Instant::now();
+        let mut i = 0;
+        while i < drones.len() {
+            if drones[i].wake_at.is_some_and(|wake_at| wake_at <= now) && !take_turn(&mut drones[i]) {
+                drones.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        if drones.is_empty() && !pool_open {
+            break;
+        }
+
+        let timeout = drones
+            .iter()
+            .filter_map(|entry| entry.wake_at)
+            .min()
+            .map_or(Duration::MAX, |wake_at| wake_at.saturating_duration_since(Instant::now()));
+        let ready = {
+            let mut select = Select::new();
+            for entry in drones.iter() {
+                entry.drone.register(&mut select);
+            }
+            if pool_open {
+                select.recv(&drone_recv);
+            }
+            if timeout == Duration::MAX {
+                Some(select.ready())
+            } else {
+                select.ready_timeout(timeout).ok()
+            }
+        };
+
+        match ready {
+            Some(index) if index < drones.len() * SkyLinkDrone::CHANNELS => {
+                let index = index / SkyLinkDrone::CHANNELS;
+                if !take_turn(&mut drones[index]) {
+                    drones.swap_remove(index);
+                }
+            },
+            Some(_) => {
+                //New drones are taken at the start of the loop. Only this worker takes from the channel,
+                //so if it's ready but empty the pool is gone.
+                pool_open = !drone_recv.is_empty();
+            },
+            None => {},
+        }
+    }
+}
+
+//Steps the drone until it has nothing left to do, or its turn is over. Returns false if the drone stopped.
+fn take_turn(entry: &mut Entry) -> bool {
+    for _ in 0..STEPS_PER_TURN {
+        match entry.drone.step() {
+            Step::Busy => {},
+            Step::Idle(wait) => {
+                entry.wake_at = Instant::now().checked_add(wait);
+                return true;
+            },
+            Step::Stopped => return false,
+        }
+    }
+    //Still busy, it's stepped again as soon as the others had their turn.
+    entry.wake_at = Some(Instant::now());
+    true
+}
@@ -0,0 +1,76 @@
+use std::ops::Range;
+
+/// Where a drone draws its random numbers: the drops, the jitter of the links and the faults it injects.
+/// The drone only asks for these few kinds of numbers, so that a test can decide every one of them.
+pub trait RandomSource: Send {
+    fn u8(&mut self, range: Range<u8>) -> u8;
+    fn u32(&mut self, range: Range<u32>) -> u32;
+    fn usize(&mut self, range: Range<usize>) -> usize;
+    /// A number between 0 and 1.
+    fn f32(&mut self) -> f32;
+    /// A number between 0 and 1.
+    fn f64(&mut self) -> f64;
+
+    /// State from which the same numbers can be drawn again, kept in the snapshots of the drone.
+    /// None if the source can't be restored.
+    fn state(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// The default source, seeded with `SkyLinkDrone::with_seed`.
+impl RandomSource for fastrand::Rng {
+    fn u8(&mut self, range: Range<u8>) -> u8 {
+        fastrand::Rng::u8(self, range)
+    }
+    fn u32(&mut self, range: Range<u32>) -> u32 {
+        fastrand::Rng::u32(self, range)
+    }
+    fn usize(&mut self, range: Range<usize>) -> usize {
+        fastrand::Rng::usize(self, range)
+    }
+    fn f32(&mut self) -> f32 {
+        fastrand::Rng::f32(self)
+    }
+    fn f64(&mut self) -> f64 {
+        fastrand::Rng::f64(self)
+    }
+    fn state(&self) -> Option<u64> {
+        Some(self.get_seed())
+    }
+}
+
+/// Draws always the same point of every range: with `at` 0 it's the lowest number, with 1 the highest one.
+/// E.g. a drone with `FixedRandom { at: 0.0 }` drops every fragment (if its pdr isn't 0), and with 1.0 it never does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedRandom {
+    pub at: f64,
+}
+
+impl FixedRandom {
+    fn pick(&self, range: Range<u64>) -> u64 {
+        if range.is_empty() {
+            return range.start;
+        }
+        let span = (range.end - range.start - 1) as f64;
+        range.start + (span * self.at.clamp(0.0, 1.0)).round() as u64
+    }
+}
+
+impl RandomSource for FixedRandom {
+    fn u8(&mut self, range: Range<u8>) -> u8 {
+        self.pick(range.start as u64..range.end as u64) as u8
+    }
+    fn u32(&mut self, range: Range<u32>) -> u32 {
+        self.pick(range.start as u64..range.end as u64) as u32
+    }
+    fn usize(&mut self, range: Range<usize>) -> usize {
+        self.pick(range.start as u64..range.end as u64) as usize
+    }
+    fn f32(&mut self) -> f32 {
+        self.at.clamp(0.0, 1.0) as f32
+    }
+    fn f64(&mut self) -> f64 {
+        self.at.clamp(0.0, 1.0)
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+use wg_2024::packet::{Packet, PacketType};
+
+/// Optional queue of the packets received by the drone, which lets control packets (Acks, Nacks,
+/// FloodRequests and FloodResponses) overtake the fragments waiting to be forwarded.
+///
+/// After `fairness` control packets in a row, a waiting fragment gets its turn, so fragments are never starved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriorityScheduler {
+    control: VecDeque<Packet>,
+    fragments: VecDeque<Packet>,
+    fairness: u32,
+    control_in_a_row: u32,
+}
+
+impl PriorityScheduler {
+    pub fn new(fairness: u32) -> Self {
+        PriorityScheduler {
+            control: VecDeque::new(),
+            fragments: VecDeque::new(),
+            fairness: fairness.max(1),
+            control_in_a_row: 0,
+        }
+    }
+
+    pub fn push(&mut self, packet: Packet) {
+        match packet.pack_type {
+            PacketType::MsgFragment(_) => self.fragments.push_back(packet),
+            _ => self.control.push_back(packet),
+        }
+    }
+
+    /// Takes the next packet to handle.
+    pub fn pop(&mut self) -> Option<Packet> {
+        let fragment_turn = self.control_in_a_row >= self.fairness && !self.fragments.is_empty();
+        if !fragment_turn {
+            if let Some(packet) = self.control.pop_front() {
+                self.control_in_a_row += 1;
+                return Some(packet);
+            }
+        }
+        self.control_in_a_row = 0;
+        self.fragments.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.control.len() + self.fragments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.control.is_empty() && self.fragments.is_empty()
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crossbeam_channel::{unbounded, Sender};
+use serde::{Deserialize, Serialize};
+use wg_2024::network::NodeId;
+use wg_2024::packet::{NackType, PacketType};
+use crate::command::SkyLinkCommand;
+
+/// Kind of a packet, without its content, used to group the statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PacketKind {
+    MsgFragment,
+    Ack,
+    Nack,
+    FloodRequest,
+    FloodResponse,
+}
+
+impl PacketKind {
+    pub fn of(pack_type: &PacketType) -> Self {
+        match pack_type {
+            PacketType::MsgFragment(_) => PacketKind::MsgFragment,
+            PacketType::Ack(_) => PacketKind::Ack,
+            PacketType::Nack(_) => PacketKind::Nack,
+            PacketType::FloodRequest(_) => PacketKind::FloodRequest,
+            PacketType::FloodResponse(_) => PacketKind::FloodResponse,
+        }
+    }
+}
+
+/// What happened to the packets of a neighbour, or of a packet kind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrafficCounters {
+    /// Packets sent, the ones created by the drone (nacks and flood responses) included.
+    pub forwarded: u64,
+    /// Fragments dropped because of the pdr.
+    pub dropped: u64,
+    /// Packets given to the Simulation Controller, since they couldn't be sent.
+    pub shortcut: u64,
+    /// Fragments refused with a nack, for each `NackType`.
+    pub nacked: NackCounters,
+}
+
+/// Nacks created by the drone, for each `NackType`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct NackCounters {
+    pub error_in_routing: u64,
+    pub destination_is_drone: u64,
+    pub dropped: u64,
+    pub unexpected_recipient: u64,
+}
+
+impl NackCounters {
+    fn count(&mut self, nack_type: &NackType) {
+        match nack_type {
+            NackType::ErrorInRouting(_) => self.error_in_routing += 1,
+            NackType::DestinationIsDrone => self.destination_is_drone += 1,
+            NackType::Dropped => self.dropped += 1,
+            NackType::UnexpectedRecipient(_) => self.unexpected_recipient += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.error_in_routing + self.destination_is_drone + self.dropped + self.unexpected_recipient
+    }
+
+    fn sum(&self, other: &NackCounters) -> NackCounters {
+        NackCounters {
+            error_in_routing: self.error_in_routing + other.error_in_routing,
+            destination_is_drone: self.destination_is_drone + other.destination_is_drone,
+            dropped: self.dropped + other.dropped,
+            unexpected_recipient: self.unexpected_recipient + other.unexpected_recipient,
+        }
+    }
+}
+
+/// Traffic handled by a drone since it started.
+/// The controller gets a copy with `SkyLinkCommand::GetStats`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DroneStats {
+    pub drone: NodeId,
+    /// Counters of the neighbours the packets were sent (or meant to be sent) to.
+    pub per_neighbour: HashMap<NodeId, TrafficCounters>,
+    pub per_kind: HashMap<PacketKind, TrafficCounters>,
+    /// Floodings met for the first time.
+    pub floods_seen: u64,
+    /// FloodRequests of floodings already met, answered with a FloodResponse.
+    pub floods_repeated: u64,
+    /// FloodRequests met for the first time, answered with a FloodResponse since their path_trace was at the limit.
+    pub floods_limited: u64,
+    /// Charge left in the battery, if the drone has one.
+    pub battery: Option<f32>,
+}
+
+impl DroneStats {
+    pub fn new(drone: NodeId) -> Self {
+        DroneStats {
+            drone,
+            ..DroneStats::default()
+        }
+    }
+
+    /// Counters summed over every packet kind.
+    pub fn total(&self) -> TrafficCounters {
+        self.per_kind.values().fold(TrafficCounters::default(), |total, counters| TrafficCounters {
+            forwarded: total.forwarded + counters.forwarded,
+            dropped: total.dropped + counters.dropped,
+            shortcut: total.shortcut + counters.shortcut,
+            nacked: total.nacked.sum(&counters.nacked),
+        })
+    }
+
+    fn update(&mut self, neighbour: Option<NodeId>, pack_type: &PacketType, update: impl Fn(&mut TrafficCounters)) {
+        update(self.per_kind.entry(PacketKind::of(pack_type)).or_default());
+        if let Some(neighbour) = neighbour {
+            update(self.per_neighbour.entry(neighbour).or_default());
+        }
+    }
+
+    pub(crate) fn count_forwarded(&mut self, neighbour: NodeId, pack_type: &PacketType) {
+        self.update(Some(neighbour), pack_type, |counters| counters.forwarded += 1);
+    }
+
+    pub(crate) fn count_dropped(&mut self, neighbour: Option<NodeId>, pack_type: &PacketType) {
+        self.update(neighbour, pack_type, |counters| counters.dropped += 1);
+    }
+
+    pub(crate) fn count_shortcut(&mut self, neighbour: Option<NodeId>, pack_type: &PacketType) {
+        self.update(neighbour, pack_type, |counters| counters.shortcut += 1);
+    }
+
+    pub(crate) fn count_nack(&mut self, neighbour: Option<NodeId>, pack_type: &PacketType, nack_type: &NackType) {
+        self.update(neighbour, pack_type, |counters| counters.nacked.count(nack_type));
+    }
+}
+
+/// Asks every drone for its statistics and waits for the answers, up to `timeout` in total.
+/// Drones that don't answer in time (e.g. because they crashed) are left out.
+pub fn collect_stats(command_send: &HashMap<NodeId, Sender<SkyLinkCommand>>, timeout: Duration) -> HashMap<NodeId, DroneStats> {
+    let (reply_send, reply_recv) = unbounded();
+    let mut waiting = 0;
+    for sender in command_send.values() {
+        if sender.send(SkyLinkCommand::GetStats(reply_send.clone())).is_ok() {
+            waiting += 1;
+        }
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut stats = HashMap::new();
+    while stats.len() < waiting {
+        match reply_recv.recv_deadline(deadline) {
+            Ok(drone_stats) => {
+                stats.insert(drone_stats.drone, drone_stats);
+            },
+            Err(_timeout) => break,
+        }
+    }
+    stats
+}
@@ -0,0 +1,141 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use crossbeam_channel::Sender;
+use wg_2024::controller::DroneEvent;
+use wg_2024::network::NodeId;
+use wg_2024::packet::Packet;
+
+/// Where a drone puts what leaves it: the packets for its neighbours and the events for the controller.
+/// By default they go on the channels given to `SkyLinkDrone::new`.
+pub trait Transport: Send {
+    /// Gives the packet to the neighbour, or gives it back if the neighbour can't be reached.
+    fn send_packet(&mut self, neighbour: NodeId, packet: Packet) -> Result<(), Packet>;
+
+    /// Tells the controller about the event. If the controller is gone the event is lost, and the drone keeps working.
+    fn send_event(&mut self, event: DroneEvent);
+
+    /// Connects the drone to a neighbour, with the channel of a `DroneCommand::AddSender`.
+    fn add_neighbour(&mut self, neighbour: NodeId, sender: Sender<Packet>);
+
+    /// Disconnects the drone from a neighbour, returning false if it wasn't one.
+    fn remove_neighbour(&mut self, neighbour: NodeId) -> bool;
+
+    /// The neighbours the drone can send to, sorted.
+    fn neighbours(&self) -> Vec<NodeId>;
+
+    fn has_neighbour(&self, neighbour: NodeId) -> bool {
+        self.neighbours().contains(&neighbour)
+    }
+}
+
+/// The crossbeam channels of the wg_2024 protocol.
+pub struct ChannelTransport {
+    packet_send: HashMap<NodeId, Sender<Packet>>,
+    controller_send: Sender<DroneEvent>,
+}
+
+impl ChannelTransport {
+    pub fn new(packet_send: HashMap<NodeId, Sender<Packet>>, controller_send: Sender<DroneEvent>) -> Self {
+        ChannelTransport {
+            packet_send,
+            controller_send,
+        }
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn send_packet(&mut self, neighbour: NodeId, packet: Packet) -> Result<(), Packet> {
+        match self.packet_send.get(&neighbour) {
+            Some(sender) => sender.send(packet).map_err(|error| error.into_inner()),
+            None => Err(packet),
+        }
+    }
+
+    fn send_event(&mut self, event: DroneEvent) {
+        let _ = self.controller_send.send(event);
+    }
+
+    fn add_neighbour(&mut self, neighbour: NodeId, sender: Sender<Packet>) {
+        self.packet_send.insert(neighbour, sender);
+    }
+
+    fn remove_neighbour(&mut self, neighbour: NodeId) -> bool {
+        //Dropping the sender is what lets a crashing neighbour stop.
+        self.packet_send.remove(&neighbour).is_some()
+    }
+
+    fn neighbours(&self) -> Vec<NodeId> {
+        let mut neighbours = self.packet_send.keys().copied().collect::<Vec<NodeId>>();
+        neighbours.sort();
+        neighbours
+    }
+
+    fn has_neighbour(&self, neighbour: NodeId) -> bool {
+        self.packet_send.contains_key(&neighbour)
+    }
+}
+
+/// Keeps everything the drone sends, for a test to look at, without any channel.
+/// Every clone shares the same records, so a test keeps one and gives the other to the drone.
+#[derive(Clone, Default)]
+pub struct RecordingTransport(Arc<Mutex<Recorded>>);
+
+#[derive(Default)]
+struct Recorded {
+    neighbours: BTreeSet<NodeId>,
+    packets: Vec<(NodeId, Packet)>,
+    events: Vec<DroneEvent>,
+}
+
+impl RecordingTransport {
+    /// A transport that reaches the given neighbours.
+    pub fn new(neighbours: impl IntoIterator<Item = NodeId>) -> Self {
+        RecordingTransport(Arc::new(Mutex::new(Recorded {
+            neighbours: neighbours.into_iter().collect(),
+            ..Recorded::default()
+        })))
+    }
+
+    /// The packets sent since the last call, with the neighbour they were sent to.
+    pub fn take_packets(&self) -> Vec<(NodeId, Packet)> {
+        self.0.lock().map(|mut recorded| std::mem::take(&mut recorded.packets)).unwrap_or_default()
+    }
+
+    /// The events sent since the last call.
+    pub fn take_events(&self) -> Vec<DroneEvent> {
+        self.0.lock().map(|mut recorded| std::mem::take(&mut recorded.events)).unwrap_or_default()
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn send_packet(&mut self, neighbour: NodeId, packet: Packet) -> Result<(), Packet> {
+        let Ok(mut recorded) = self.0.lock() else {
+            return Err(packet);
+        };
+        if !recorded.neighbours.contains(&neighbour) {
+            return Err(packet);
+        }
+        recorded.packets.push((neighbour, packet));
+        Ok(())
+    }
+
+    fn send_event(&mut self, event: DroneEvent) {
+        if let Ok(mut recorded) = self.0.lock() {
+            recorded.events.push(event);
+        }
+    }
+
+    fn add_neighbour(&mut self, neighbour: NodeId, _sender: Sender<Packet>) {
+        if let Ok(mut recorded) = self.0.lock() {
+            recorded.neighbours.insert(neighbour);
+        }
+    }
+
+    fn remove_neighbour(&mut self, neighbour: NodeId) -> bool {
+        self.0.lock().is_ok_and(|mut recorded| recorded.neighbours.remove(&neighbour))
+    }
+
+    fn neighbours(&self) -> Vec<NodeId> {
+        self.0.lock().map(|recorded| recorded.neighbours.iter().copied().collect()).unwrap_or_default()
+    }
+}
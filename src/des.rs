@@ -0,0 +1,361 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
+use std::time::Duration;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use wg_2024::config::Config;
+use wg_2024::controller::{DroneCommand, DroneEvent};
+use wg_2024::drone::Drone;
+use wg_2024::network::NodeId;
+use wg_2024::packet::Packet;
+use crate::initializer::{parse_config, parse_options, SimulationOptions};
+use crate::skylink_drone::drone::{derive_seed, SkyLinkDrone, Step};
+use crate::skylink_drone::event::SkyLinkEvent;
+use crate::skylink_drone::link::LinkParams;
+use crate::skylink_drone::scheduler::PriorityScheduler;
+use crate::skylink_drone::clock::ManualClock;
+
+//Discrete-event simulation: every drone runs on the calling thread, packets cross the links in virtual time,
+//and every random choice comes from the seed, so the same inputs always give the same trace.
+
+/// Something that happened in the simulation, at `time` since it started.
+//The trace is mostly read through Debug, which the dead code lint doesn't count.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+    pub time: Duration,
+    pub record: Record,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum Record {
+    /// A packet reached a drone.
+    Received { drone: NodeId, from: NodeId, packet: Packet },
+    /// A packet reached a client or a server.
+    Delivered { host: NodeId, from: NodeId, packet: Packet },
+    /// A packet was sent to a node that isn't running (anymore), or a shortcut ended on a drone.
+    Lost { to: NodeId, from: NodeId, packet: Packet },
+    /// A drone told something to the controller.
+    Event { drone: NodeId, event: DroneEvent },
+    SkyLink(SkyLinkEvent),
+    Crashed { drone: NodeId },
+    PdrChanged { drone: NodeId, pdr: f32 },
+}
+
+enum Action {
+    Send { from: NodeId, to: NodeId, packet: Packet },
+    Deliver { from: NodeId, to: NodeId, packet: Packet },
+    Crash(NodeId),
+    SetPdr(NodeId, f32),
+}
+
+struct Scheduled {
+    time: Duration,
+    seq: u64, //Actions planned for the same time happen in the order they were planned.
+    action: Action,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+impl Eq for Scheduled {}
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.seq).cmp(&(other.time, other.seq))
+    }
+}
+
+//A drone of the simulation, with the other end of all its channels.
+struct SimDrone {
+    drone: SkyLinkDrone,
+    inbox: Option<Sender<Packet>>, //Dropped when the drone crashes, so that it can stop.
+    commands: Sender<DroneCommand>,
+    outboxes: BTreeMap<NodeId, Receiver<Packet>>, //What the drone sends to each neighbour.
+    events: Receiver<DroneEvent>,
+    skylink_events: Receiver<SkyLinkEvent>,
+    stopped: bool,
+}
+
+/// Runs a whole network on one thread, in virtual time.
+/// The drones are the usual `SkyLinkDrone`s, but their neighbours' channels are kept by the engine,
+/// which moves the packets across the links: the timing of the links declared in the options is
+/// applied in virtual time, the other links take no time at all.
+/// Clients and servers aren't simulated, the packets that reach them are only written in the trace.
+pub struct DesEngine {
+    now: Duration,
+    clock: ManualClock, //Moved with `now`, every drone reads the virtual time from a clone.
+    queue: BinaryHeap<Reverse<Scheduled>>,
+    next_seq: u64,
+    rng: fastrand::Rng,
+    seed: u64,
+    drones: BTreeMap<NodeId, SimDrone>,
+    hosts: BTreeSet<NodeId>,
+    links: HashMap<NodeId, HashMap<NodeId, LinkParams>>,
+    busy_until: HashMap<(NodeId, NodeId), Duration>, //When each link finishes transmitting what it was given.
+    trace: Vec<TraceEntry>,
+}
+
+impl DesEngine {
+    /// Reads the network and its options from the same file used by `initialize`.
+    /// Without a seed in the file, 0 is used, so that runs are still repeatable.
+    pub fn from_file(file: &str) -> Self {
+        DesEngine::new(parse_config(file), &parse_options(file))
+    }
+
+    pub fn new(config: Config, options: &SimulationOptions) -> Self {
+        let seed = options.seed.unwrap_or(0);
+        let mut engine = DesEngine {
+            now: Duration::ZERO,
+            clock: ManualClock::new(),
+            queue: BinaryHeap::new(),
+            next_seq: 0,
+            rng: fastrand::Rng::with_seed(seed),
+            seed,
+            drones: BTreeMap::new(),
+            hosts: BTreeSet::new(),
+            links: HashMap::new(),
+            busy_until: HashMap::new(),
+            trace: Vec::new(),
+        };
+        engine.hosts.extend(config.client.iter().map(|client| client.id));
+        engine.hosts.extend(config.server.iter().map(|server| server.id));
+        for id in config.drone.iter().map(|drone| drone.id).chain(engine.hosts.iter().copied()) {
+            engine.links.insert(id, options.links_of(id));
+        }
+
+        for drone in config.drone.into_iter() {
+            let (inbox_send, inbox_recv) = unbounded();
+            let (command_send, command_recv) = unbounded();
+            let (event_send, event_recv) = unbounded();
+            let (skylink_event_send, skylink_event_recv) = unbounded();
+            let mut packet_send = HashMap::new();
+            let mut outboxes = BTreeMap::new();
+            for neighbour in drone.connected_node_ids.iter() {
+                let (send, recv) = unbounded();
+                packet_send.insert(*neighbour, send);
+                outboxes.insert(*neighbour, recv);
+            }
+
+            //The timing of the links is up to the engine, and there's no real time to wait for a crash.
+            //Fault profiles and batteries are left out, since they depend on real time.
+            //The floodings remembered get older in virtual time.
+            let mut sky_link_drone = SkyLinkDrone::new(drone.id, event_send, command_recv, inbox_recv, packet_send, drone.pdr)
+                .with_seed(derive_seed(seed, drone.id))
+                .with_clock(engine.clock.clone())
+                .with_event_channel(skylink_event_send)
+                .with_link_pdrs(options.link_pdrs_of(drone.id))
+                .with_crash_deadline(None)
+                .with_flood_limit(options.flood_limit_of(drone.id))
+                .with_flood_cache(options.flood_cache());
+            if let Some(fairness) = options.scheduler_fairness {
+                sky_link_drone = sky_link_drone.with_scheduler(PriorityScheduler::new(fairness));
+            }
+
+            engine.drones.insert(drone.id, SimDrone {
+                drone: sky_link_drone,
+                inbox: Some(inbox_send),
+                commands: command_send,
+                outboxes,
+                events: event_recv,
+                skylink_events: skylink_event_recv,
+                stopped: false,
+            });
+        }
+        engine
+    }
+
+    /// Virtual time since the simulation started.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Everything that happened so far, in order.
+    pub fn trace(&self) -> &[TraceEntry] {
+        &self.trace
+    }
+
+    /// Packets that reached the given client or server so far.
+    pub fn delivered_to(&self, host: NodeId) -> Vec<&Packet> {
+        self.trace
+            .iter()
+            .filter_map(|entry| match &entry.record {
+                Record::Delivered { host: to, packet, .. } if *to == host => Some(packet),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Makes `from` (usually a client or a server) send the packet to its neighbour `to`, at virtual time `at`.
+    pub fn send(&mut self, at: Duration, from: NodeId, to: NodeId, packet: Packet) {
+        self.schedule(at, Action::Send { from, to, packet });
+    }
+
+    /// Crashes the drone at virtual time `at`: its neighbours remove it, and it stops once it's done.
+    pub fn crash(&mut self, at: Duration, drone: NodeId) {
+        self.schedule(at, Action::Crash(drone));
+    }
+
+    pub fn set_pdr(&mut self, at: Duration, drone: NodeId, pdr: f32) {
+        self.schedule(at, Action::SetPdr(drone, pdr));
+    }
+
+    /// Runs until nothing is left to happen.
+    pub fn run(&mut self) {
+        self.run_until(Duration::MAX);
+    }
+
+    /// Runs everything planned up to virtual time `until` (included).
+    pub fn run_until(&mut self, until: Duration) {
+        while self.queue.peek().is_some_and(|Reverse(next)| next.time <= until) {
+            let Some(Reverse(next)) = self.queue.pop() else {
+                break;
+            };
+            self.move_to(next.time);
+            match next.action {
+                Action::Send { from, to, packet } => {
+                    let arrival = self.arrival(from, to, &packet);
+                    self.schedule(arrival, Action::Deliver { from, to, packet });
+                },
+                Action::Deliver { from, to, packet } => self.deliver(from, to, packet),
+                Action::Crash(drone) => self.crash_now(drone),
+                Action::SetPdr(drone, pdr) => {
+                    if let Some(sim_drone) = self.drones.get(&drone) {
+                        let _ = sim_drone.commands.send(DroneCommand::SetPacketDropRate(pdr));
+                        self.record(Record::PdrChanged { drone, pdr });
+                        self.work(drone);
+                    }
+                },
+            }
+        }
+        if until != Duration::MAX {
+            self.move_to(self.now.max(until));
+        }
+    }
+
+    //Moves the virtual time, and the clock of the drones with it.
+    fn move_to(&mut self, time: Duration) {
+        self.clock.advance(time.saturating_sub(self.now));
+        self.now = time;
+    }
+
+    fn schedule(&mut self, time: Duration, action: Action) {
+        self.queue.push(Reverse(Scheduled {
+            time,
+            seq: self.next_seq,
+            action,
+        }));
+        self.next_seq += 1;
+    }
+
+    fn record(&mut self, record: Record) {
+        self.trace.push(TraceEntry {
+            time: self.now,
+            record,
+        });
+    }
+
+    fn deliver(&mut self, from: NodeId, to: NodeId, packet: Packet) {
+        if self.hosts.contains(&to) {
+            self.record(Record::Delivered { host: to, from, packet });
+            return;
+        }
+        let inbox = self.drones.get(&to).and_then(|sim_drone| sim_drone.inbox.clone());
+        match inbox {
+            Some(inbox) => {
+                self.record(Record::Received { drone: to, from, packet: packet.clone() });
+                let _ = inbox.send(packet);
+                self.work(to);
+            },
+            None => self.record(Record::Lost { to, from, packet }),
+        }
+    }
+
+    fn crash_now(&mut self, drone: NodeId) {
+        let Some(sim_drone) = self.drones.get(&drone) else {
+            return;
+        };
+        let _ = sim_drone.commands.send(DroneCommand::Crash);
+        let neighbours = sim_drone.outboxes.keys().copied().collect::<Vec<NodeId>>();
+        self.record(Record::Crashed { drone });
+        self.work(drone);
+        for neighbour in neighbours {
+            if let Some(sim_neighbour) = self.drones.get(&neighbour) {
+                let _ = sim_neighbour.commands.send(DroneCommand::RemoveSender(drone));
+                self.work(neighbour);
+            }
+        }
+        //Nobody can reach the drone anymore, so it handles what it has left and stops.
+        if let Some(sim_drone) = self.drones.get_mut(&drone) {
+            sim_drone.inbox = None;
+        }
+        self.work(drone);
+    }
+
+    //Steps the drone until it has nothing left to do, then plans the delivery of what it sent.
+    fn work(&mut self, id: NodeId) {
+        let Some(sim_drone) = self.drones.get_mut(&id) else {
+            return;
+        };
+        while !sim_drone.stopped {
+            match sim_drone.drone.step() {
+                Step::Busy => {},
+                //Only real time features (like a crash deadline) ask to wait, and the engine doesn't use them.
+                Step::Idle(_) => break,
+                Step::Stopped => sim_drone.stopped = true,
+            }
+        }
+
+        let mut sent = Vec::new();
+        for (neighbour, outbox) in sim_drone.outboxes.iter() {
+            sent.extend(outbox.try_iter().map(|packet| (*neighbour, packet)));
+        }
+        let events = sim_drone.events.try_iter().collect::<Vec<DroneEvent>>();
+        let skylink_events = sim_drone.skylink_events.try_iter().collect::<Vec<SkyLinkEvent>>();
+
+        for event in events {
+            if let DroneEvent::ControllerShortcut(packet) = &event {
+                //The engine is also the controller: the packet goes straight to its destination.
+                //A drone would send it back to the controller at the same instant, forever, so it's lost.
+                if let Some(destination) = packet.routing_header.hops.last().copied() {
+                    if self.hosts.contains(&destination) {
+                        self.schedule(self.now, Action::Deliver { from: id, to: destination, packet: packet.clone() });
+                    } else {
+                        self.record(Record::Lost { to: destination, from: id, packet: packet.clone() });
+                    }
+                }
+            }
+            self.record(Record::Event { drone: id, event });
+        }
+        for event in skylink_events {
+            self.record(Record::SkyLink(event));
+        }
+        for (neighbour, packet) in sent {
+            let arrival = self.arrival(id, neighbour, &packet);
+            self.schedule(arrival, Action::Deliver { from: id, to: neighbour, packet });
+        }
+    }
+
+    //When a packet sent now from `from` reaches `to`.
+    fn arrival(&mut self, from: NodeId, to: NodeId, packet: &Packet) -> Duration {
+        let params = self.links.get(&from).and_then(|links| links.get(&to)).copied().unwrap_or_default();
+        if params.is_instant() {
+            return self.now;
+        }
+        let start = self.busy_until.get(&(from, to)).map_or(self.now, |busy| self.now.max(*busy));
+        let transmitted = start.saturating_add(params.transmission_time(packet));
+        self.busy_until.insert((from, to), transmitted);
+        let jitter = params.jitter.mul_f64(self.rng.f64());
+        transmitted.saturating_add(params.delay).saturating_add(jitter)
+    }
+}
@@ -1,22 +1,296 @@
 use std::{fs, thread};
 use std::thread::JoinHandle;
 use std::collections::HashMap;
-use crossbeam_channel::unbounded;
-use wg_2024::config::Config;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use serde::{Deserialize, Deserializer};
+use wg_2024::config::{Config, Drone as ConfigDrone};
+use wg_2024::controller::{DroneCommand, DroneEvent};
 use wg_2024::drone::Drone;
-use crate::sim_control::SimulationControl;
-use crate::skylink_drone::drone::SkyLinkDrone;
+use wg_2024::network::NodeId;
+use wg_2024::packet::{NodeType, Packet};
+use crate::sim_control::{Checkpoint, NodeInfo, SimulationControl};
+use crate::logging::{TraceOptions, Tracing};
+use crate::skylink_drone::drone::{derive_seed, SkyLinkDrone, DEFAULT_CRASH_DEADLINE};
+use crate::skylink_drone::link::LinkParams;
+use crate::skylink_drone::fault::FaultProfile;
+use crate::skylink_drone::battery::BatteryModel;
+use crate::skylink_drone::pool::DronePool;
+use crate::skylink_drone::capture::capture_file;
+use crate::skylink_drone::scheduler::PriorityScheduler;
+use crate::skylink_drone::flood_cache::{FloodCache, DEFAULT_FLOOD_CACHE_CAPACITY};
+use crate::skylink_drone::snapshot::DroneSnapshot;
+use crate::skylink_drone::event::SkyLinkEvent;
+use crate::skylink_drone::command::SkyLinkCommand;
+use tracing::warn;
+
+/// Options of the simulation that aren't part of the wg_2024 config.
+/// They are read from the same file, as top-level keys placed before the first table, e.g. `seed = 42`.
+#[derive(Debug, Default, Deserialize)]
+pub struct SimulationOptions {
+    /// Master seed of the simulation, every drone derives its own seed from it.
+    /// If missing, a random one is picked (and written in the log, to replay the run).
+    pub seed: Option<u64>,
+    /// Timing of the links between nodes, links that aren't listed are instant.
+    #[serde(default)]
+    pub link: Vec<LinkOptions>,
+    /// If present, every drone handles control packets before fragments,
+    /// but lets a fragment through after this many control packets in a row.
+    pub scheduler_fairness: Option<u32>,
+    /// How long a crashed drone waits for its neighbours to drop their channels before stopping,
+    /// if missing `DEFAULT_CRASH_DEADLINE` is used.
+    pub crash_deadline_ms: Option<u64>,
+    /// If present, the drones run on a pool of this many threads, instead of a thread each.
+    pub workers: Option<usize>,
+    /// If present, every drone writes what it receives and sends in `<capture_dir>/drone_<id>.jsonl`,
+    /// to be replayed with the `replay` binary of the skylink crate.
+    pub capture_dir: Option<String>,
+    /// Outputs of the tracing, if missing nothing is traced.
+    pub trace: Option<TraceOptions>,
+    /// Longest path_trace of the FloodRequests forwarded by every drone, if missing the floodings aren't limited.
+    pub max_path_trace: Option<usize>,
+    /// Drones with their own limit, instead of `max_path_trace`.
+    #[serde(default)]
+    pub flood_limit: Vec<FloodLimitOptions>,
+    /// Drones that misbehave on purpose, the others are honest.
+    #[serde(default)]
+    pub fault: Vec<FaultOptions>,
+    /// Drones with a battery, the others never run out of energy.
+    #[serde(default)]
+    pub battery: Vec<BatteryOptions>,
+    /// How many floodings every drone remembers, if missing `DEFAULT_FLOOD_CACHE_CAPACITY`. 0 isn't valid.
+    pub flood_cache_capacity: Option<NonZeroUsize>,
+    /// After how long a drone forgets a flooding, if missing only the capacity makes it forget.
+    pub flood_cache_max_age_ms: Option<u64>,
+}
+
+/// Battery of a drone, declared in the config file as:
+/// ```toml
+/// [[battery]]
+/// drone = 3
+/// charge = 500
+/// per_packet = 1 # spent for every packet sent
+/// per_byte = 0.01 # spent for every byte of the fragments sent
+/// idle_drain = 0.5 # spent every second
+/// ```
+/// Missing costs take the default of `BatteryModel`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatteryOptions {
+    pub drone: NodeId,
+    pub charge: f32,
+    pub per_packet: Option<f32>,
+    pub per_byte: Option<f32>,
+    pub idle_drain: Option<f32>,
+}
+
+impl BatteryOptions {
+    pub fn model(&self) -> BatteryModel {
+        let default = BatteryModel::default();
+        BatteryModel {
+            initial_charge: self.charge,
+            per_packet: self.per_packet.unwrap_or(default.per_packet),
+            per_byte: self.per_byte.unwrap_or(default.per_byte),
+            idle_drain: self.idle_drain.unwrap_or(default.idle_drain),
+        }
+    }
+}
+
+/// Limit of the floodings of a single drone, declared in the config file as:
+/// ```toml
+/// [[flood_limit]]
+/// drone = 5
+/// max_path_trace = 3 # missing to let this drone flood without limits
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct FloodLimitOptions {
+    pub drone: NodeId,
+    pub max_path_trace: Option<usize>,
+}
+
+/// Timing of a link, declared in the config file as:
+/// ```toml
+/// [[link]]
+/// from = 1
+/// to = 2
+/// delay_ms = 20
+/// jitter_ms = 5
+/// bandwidth = 6400 # bytes per second, 0 isn't valid
+/// pdr = 0.1 # instead of the pdr of the drone
+/// ```
+/// The link behaves the same way in both directions, unless `one_way = true`.
+/// Delays and jitters longer than `MAX_LINK_DELAY_MS` aren't valid.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkOptions {
+    pub from: NodeId,
+    pub to: NodeId,
+    #[serde(default, deserialize_with = "link_delay")]
+    pub delay_ms: u64,
+    #[serde(default, deserialize_with = "link_delay")]
+    pub jitter_ms: u64,
+    pub bandwidth: Option<NonZeroU64>,
+    pub pdr: Option<f32>,
+    #[serde(default)]
+    pub one_way: bool,
+}
+
+/// Longest delay (or jitter) of a link in the config, a day: a packet that waits longer is as good as lost,
+/// and the time it's due at could not even be represented.
+pub const MAX_LINK_DELAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+fn link_delay<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let delay_ms = u64::deserialize(deserializer)?;
+    if delay_ms > MAX_LINK_DELAY_MS {
+        return Err(serde::de::Error::custom(format!("a link can't take more than {}ms, not {}ms", MAX_LINK_DELAY_MS, delay_ms)));
+    }
+    Ok(delay_ms)
+}
+
+impl LinkOptions {
+    pub fn params(&self) -> LinkParams {
+        LinkParams {
+            delay: Duration::from_millis(self.delay_ms),
+            jitter: Duration::from_millis(self.jitter_ms),
+            bandwidth: self.bandwidth,
+        }
+    }
+}
+
+/// Faults injected by a drone, declared in the config file as:
+/// ```toml
+/// [[fault]]
+/// drone = 2
+/// duplicate = 0.1 # probability of sending a fragment twice
+/// reorder = 0.1
+/// reorder_delay_ms = 50
+/// corrupt = 0.05
+/// misroute = 0.01
+/// drop_acks = 0.2
+/// delay_nacks = 0.5
+/// nack_delay_ms = 200
+/// ```
+/// Missing probabilities are 0, missing delays take the default of `FaultProfile`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FaultOptions {
+    pub drone: NodeId,
+    #[serde(default)]
+    pub duplicate: f32,
+    #[serde(default)]
+    pub reorder: f32,
+    pub reorder_delay_ms: Option<u64>,
+    #[serde(default)]
+    pub corrupt: f32,
+    #[serde(default)]
+    pub misroute: f32,
+    #[serde(default)]
+    pub drop_acks: f32,
+    #[serde(default)]
+    pub delay_nacks: f32,
+    pub nack_delay_ms: Option<u64>,
+}
+
+impl FaultOptions {
+    pub fn profile(&self) -> FaultProfile {
+        let default = FaultProfile::default();
+        FaultProfile {
+            duplicate: self.duplicate,
+            reorder: self.reorder,
+            reorder_delay: self.reorder_delay_ms.map_or(default.reorder_delay, Duration::from_millis),
+            corrupt: self.corrupt,
+            misroute: self.misroute,
+            drop_acks: self.drop_acks,
+            delay_nacks: self.delay_nacks,
+            nack_delay: self.nack_delay_ms.map_or(default.nack_delay, Duration::from_millis),
+        }
+    }
+}
+
+impl SimulationOptions {
+    pub fn crash_deadline(&self) -> Duration {
+        self.crash_deadline_ms.map_or(DEFAULT_CRASH_DEADLINE, Duration::from_millis)
+    }
+
+    /// File where the given drone writes its capture, if the drones are captured.
+    pub fn capture_of(&self, id: NodeId) -> Option<PathBuf> {
+        self.capture_dir.as_ref().map(|dir| Path::new(dir).join(format!("drone_{}.jsonl", id)))
+    }
+
+    /// Longest path_trace of the FloodRequests forwarded by the given drone, if it has a limit.
+    pub fn flood_limit_of(&self, id: NodeId) -> Option<usize> {
+        match self.flood_limit.iter().find(|limit| limit.drone == id) {
+            Some(limit) => limit.max_path_trace,
+            None => self.max_path_trace,
+        }
+    }
+
+    /// Battery of the given drone, if it has one.
+    pub fn battery_of(&self, id: NodeId) -> Option<BatteryModel> {
+        self.battery.iter().find(|battery| battery.drone == id).map(BatteryOptions::model)
+    }
+
+    /// Faults injected by the given drone, if it has any.
+    pub fn faults_of(&self, id: NodeId) -> Option<FaultProfile> {
+        self.fault.iter().find(|fault| fault.drone == id).map(FaultOptions::profile)
+    }
+
+    /// Timing of the links going from the given node to its neighbours.
+    pub fn links_of(&self, id: NodeId) -> HashMap<NodeId, LinkParams> {
+        let mut links = HashMap::new();
+        for link in self.link.iter() {
+            if link.from == id {
+                links.insert(link.to, link.params());
+            } else if link.to == id && !link.one_way {
+                links.insert(link.from, link.params());
+            }
+        }
+        links
+    }
+
+    /// Pdr of the links going from the given node to its neighbours, for the links that have their own.
+    pub fn link_pdrs_of(&self, id: NodeId) -> HashMap<NodeId, f32> {
+        let mut link_pdrs = HashMap::new();
+        for link in self.link.iter() {
+            if let Some(pdr) = link.pdr {
+                if link.from == id {
+                    link_pdrs.insert(link.to, pdr);
+                } else if link.to == id && !link.one_way {
+                    link_pdrs.insert(link.from, pdr);
+                }
+            }
+        }
+        link_pdrs
+    }
+
+    /// Cache of the floodings met, the same for every drone.
+    pub fn flood_cache(&self) -> FloodCache {
+        let mut flood_ids = FloodCache::new(self.flood_cache_capacity.unwrap_or(DEFAULT_FLOOD_CACHE_CAPACITY));
+        if let Some(max_age_ms) = self.flood_cache_max_age_ms {
+            flood_ids = flood_ids.with_max_age(Duration::from_millis(max_age_ms));
+        }
+        flood_ids
+    }
+}
 
 pub fn initialize(file: &str) -> (SimulationControl, Vec<JoinHandle<()>>) {
     let config = parse_config(file);
+    let options = parse_options(file);
+    let seed = options.seed.unwrap_or_else(|| fastrand::u64(..));
+    let crash_deadline = options.crash_deadline();
+    let mut pool = options.workers.map(DronePool::new);
+    //The tracing starts before the drones, so that nothing they do is missed.
+    let tracing = start_tracing(&options);
     let mut handles = Vec::new();
     //I'll return the handles of the threads, and join them to the main thread.
 
     let mut command_send = HashMap::new();
+    let mut skylink_command_send = HashMap::new();
     //This will be given to the Sim Contr to command the drones.
     let (event_send, event_recv) = unbounded();
     //I create the channel, the 'send' will be given to every drone,
     //while the 'recv' will go to the Sim contr.
+    let (skylink_event_send, skylink_event_recv) = unbounded();
+    //Same for the events that only our drones send.
 
     let mut packet_senders = HashMap::new();
     let mut packet_receivers = HashMap::new();
@@ -48,40 +322,240 @@ pub fn initialize(file: &str) -> (SimulationControl, Vec<JoinHandle<()>>) {
     for client in config.client.iter() {
         network_graph.insert(client.id, client.connected_drone_ids.clone());
     }
+    //The Simulation Controller keeps track of every node, and can't cut off the clients and servers.
+    let mut nodes = HashMap::new();
+    nodes.extend(config.drone.iter().map(|drone| (drone.id, NodeInfo::new(NodeType::Drone, Some(drone.pdr)))));
+    nodes.extend(config.client.iter().map(|client| (client.id, NodeInfo::new(NodeType::Client, None))));
+    nodes.extend(config.server.iter().map(|server| (server.id, NodeInfo::new(NodeType::Server, None))));
 
-    for drone in config.drone.into_iter() {
+    for drone in config.drone.iter() {
         //Adding the sender to this drone to the senders of the Sim Contr.
         let (contr_send, contr_recv) = unbounded();
         command_send.insert(drone.id, contr_send);
-
-        //Give the drone a copy of the sender of events to the Sim Contr.
-        let node_event_send = event_send.clone();
+        let (skylink_contr_send, skylink_contr_recv) = unbounded();
+        skylink_command_send.insert(drone.id, skylink_contr_send);
 
         //Take the channels necessary to this drone.
-        let drone_recv = packet_receivers.remove(&drone.id).unwrap();
-        let drone_send = drone
-            .connected_node_ids
-            .into_iter()
-            .map(|id| (id, packet_senders[&id].clone()))
-            .collect();
-
-        //create the thread of the drone, and add it to a Vec to be pushed afterward
-        handles.push(thread::spawn(move || {
-            let mut drone = SkyLinkDrone::new(drone.id, node_event_send, contr_recv, drone_recv, drone_send, drone.pdr);
-
-            drone.run();
-        }));
+        let channels = DroneChannels {
+            event_send: event_send.clone(),
+            command_recv: contr_recv,
+            packet_recv: packet_receivers.remove(&drone.id).unwrap(),
+            packet_send: drone.connected_node_ids.iter().map(|id| (*id, packet_senders[id].clone())).collect(),
+            skylink_event_send: skylink_event_send.clone(),
+            skylink_command_recv: skylink_contr_recv,
+        };
+        let mut drone = build_drone(DroneOrigin::Config(drone), &options, seed, channels);
+
+        match pool.as_mut() {
+            //With a pool, the drone shares one of its threads with other drones.
+            Some(pool) => pool.add(drone),
+            //Otherwise, create the thread of the drone, and add it to a Vec to be pushed afterward
+            None => handles.push(thread::spawn(move || {
+                drone.run();
+            })),
+        }
         //This will probably need to be changed based on the
         //implementation of other groups drones in our network.
     }
 
 
-    let sim_contr = SimulationControl::new(command_send, event_recv, event_send, packet_senders, network_graph);
+    if let Some(pool) = pool.as_mut() {
+        handles.extend(pool.take_handles());
+    }
+    let mut sim_contr = SimulationControl::new(command_send, event_recv, event_send, packet_senders, network_graph)
+        .with_seed(seed)
+        .with_skylink_channels(skylink_command_send, skylink_event_recv, skylink_event_send)
+        .with_nodes(nodes)
+        .with_crash_deadline(Some(crash_deadline));
+    if let Some(pool) = pool {
+        sim_contr = sim_contr.with_pool(pool);
+    }
+    if let Some(tracing) = tracing {
+        sim_contr = sim_contr.with_tracing(tracing);
+    }
+
+    (sim_contr, handles)
+}
+
+fn start_tracing(options: &SimulationOptions) -> Option<Tracing> {
+    options.trace.as_ref().and_then(|trace| match Tracing::install(trace) {
+        Ok(tracing) => Some(tracing),
+        Err(error) => {
+            //Without a subscriber there's nobody to give a warning to.
+            eprintln!("Can't trace the simulation: {}", error);
+            None
+        },
+    })
+}
+
+fn with_capture(drone: SkyLinkDrone, options: &SimulationOptions) -> SkyLinkDrone {
+    let Some(path) = options.capture_of(drone.get_id()) else {
+        return drone;
+    };
+    match capture_file(&path) {
+        Ok(file) => drone.with_capture(file),
+        Err(error) => {
+            warn!(drone = drone.get_id(), path = %path.display(), error = %error, "capture not started");
+            drone
+        },
+    }
+}
+
+/// What a drone of the network is built from.
+pub enum DroneOrigin<'a> {
+    /// A drone of the config, which starts from scratch.
+    Config(&'a ConfigDrone),
+    /// A drone resumed from a checkpoint, which keeps what its snapshot has (links, faults, battery, flood limit).
+    Snapshot(&'a DroneSnapshot),
+}
+
+/// The ends of the channels that a drone of the network keeps.
+pub struct DroneChannels {
+    pub event_send: Sender<DroneEvent>,
+    pub command_recv: Receiver<DroneCommand>,
+    pub packet_recv: Receiver<Packet>,
+    pub packet_send: HashMap<NodeId, Sender<Packet>>,
+    pub skylink_event_send: Sender<SkyLinkEvent>,
+    pub skylink_command_recv: Receiver<SkyLinkCommand>,
+}
+
+/// Builds a drone with everything the options give it, ready to run.
+/// `seed` is the master seed of the simulation, a restored drone keeps the seed of its snapshot instead.
+pub fn build_drone(origin: DroneOrigin, options: &SimulationOptions, seed: u64, channels: DroneChannels) -> SkyLinkDrone {
+    let DroneChannels { event_send, command_recv, packet_recv, packet_send, skylink_event_send, skylink_command_recv } = channels;
+    let mut drone = match origin {
+        DroneOrigin::Config(config_drone) => {
+            let mut drone = SkyLinkDrone::new(config_drone.id, event_send, command_recv, packet_recv, packet_send, config_drone.pdr)
+                .with_seed(derive_seed(seed, config_drone.id))
+                .with_links(options.links_of(config_drone.id))
+                .with_link_pdrs(options.link_pdrs_of(config_drone.id))
+                .with_flood_limit(options.flood_limit_of(config_drone.id))
+                .with_flood_cache(options.flood_cache());
+            if let Some(fairness) = options.scheduler_fairness {
+                drone = drone.with_scheduler(PriorityScheduler::new(fairness));
+            }
+            if let Some(faults) = options.faults_of(config_drone.id) {
+                drone = drone.with_fault_profile(faults);
+            }
+            if let Some(battery) = options.battery_of(config_drone.id) {
+                drone = drone.with_battery(battery);
+            }
+            drone
+        },
+        DroneOrigin::Snapshot(snapshot) => {
+            //The options change the size and the age of the flood cache, not what it remembers.
+            let mut snapshot = snapshot.clone();
+            if let Some(capacity) = options.flood_cache_capacity {
+                snapshot.flood_ids.capacity = capacity;
+            }
+            if let Some(max_age_ms) = options.flood_cache_max_age_ms {
+                snapshot.flood_ids.max_age = Some(Duration::from_millis(max_age_ms));
+            }
+            SkyLinkDrone::restore(&snapshot, event_send, command_recv, packet_recv, packet_send)
+        },
+    };
+    drone = drone
+        .with_event_channel(skylink_event_send)
+        .with_command_channel(skylink_command_recv)
+        .with_crash_deadline(Some(options.crash_deadline()));
+    with_capture(drone, options)
+}
+
+/// Starts a new simulation from a checkpoint: every drone is restored with new channels and goes on from its snapshot.
+/// Resuming the same checkpoint twice gives two simulations which don't share anything.
+/// The clients and servers get new channels too, as in `initialize`.
+/// The options apply as in `initialize`, except what the drones keep in their snapshots (links, faults, batteries, flood limits).
+/// The size and age of the flood caches are the ones of the options, if they're given, but the floodings remembered are kept.
+pub fn resume(checkpoint: &Checkpoint, options: &SimulationOptions) -> (SimulationControl, Vec<JoinHandle<()>>) {
+    let crash_deadline = options.crash_deadline();
+    let mut pool = options.workers.map(DronePool::new);
+    let tracing = start_tracing(options);
+    let mut handles = Vec::new();
+
+    let mut command_send = HashMap::new();
+    let mut skylink_command_send = HashMap::new();
+    let (event_send, event_recv) = unbounded();
+    let (skylink_event_send, skylink_event_recv) = unbounded();
+
+    let mut packet_senders = HashMap::new();
+    let mut packet_receivers = HashMap::new();
+    for id in checkpoint.network_graph.keys() {
+        let (send, recv) = unbounded();
+        packet_senders.insert(*id, send);
+        packet_receivers.insert(*id, recv);
+    }
+
+    let mut nodes = HashMap::new();
+    for id in checkpoint.network_graph.keys() {
+        let kind = match checkpoint.node_kinds.get(id) {
+            Some(kind) => *kind,
+            None if checkpoint.drones.contains_key(id) => NodeType::Drone,
+            None => continue,
+        };
+        let pdr = checkpoint.drones.get(id).map(|snapshot| snapshot.pdr as f32 / 100.0);
+        nodes.insert(*id, NodeInfo::new(kind, pdr));
+    }
+
+    let mut ids = checkpoint.drones.keys().copied().collect::<Vec<NodeId>>();
+    ids.sort();
+    for id in ids {
+        let snapshot = &checkpoint.drones[&id];
+        //A snapshot of a drone that isn't in the network anymore isn't resumed.
+        let Some(drone_recv) = packet_receivers.remove(&id) else {
+            continue;
+        };
+        let (contr_send, contr_recv) = unbounded();
+        command_send.insert(id, contr_send);
+        let (skylink_contr_send, skylink_contr_recv) = unbounded();
+        skylink_command_send.insert(id, skylink_contr_send);
+
+        //The drone keeps the neighbours it had, as long as they're still in the network.
+        let channels = DroneChannels {
+            event_send: event_send.clone(),
+            command_recv: contr_recv,
+            packet_recv: drone_recv,
+            packet_send: snapshot.neighbours
+                .iter()
+                .filter_map(|neighbour| packet_senders.get(neighbour).map(|send| (*neighbour, send.clone())))
+                .collect(),
+            skylink_event_send: skylink_event_send.clone(),
+            skylink_command_recv: skylink_contr_recv,
+        };
+        let mut drone = build_drone(DroneOrigin::Snapshot(snapshot), options, checkpoint.seed, channels);
+        match pool.as_mut() {
+            Some(pool) => pool.add(drone),
+            None => handles.push(thread::spawn(move || {
+                drone.run();
+            })),
+        }
+    }
+
+    if let Some(pool) = pool.as_mut() {
+        handles.extend(pool.take_handles());
+    }
+    let resumed = command_send.len();
+    let mut sim_contr = SimulationControl::new(command_send, event_recv, event_send, packet_senders, checkpoint.network_graph.clone())
+        .with_seed(checkpoint.seed)
+        .with_skylink_channels(skylink_command_send, skylink_event_recv, skylink_event_send)
+        .with_nodes(nodes)
+        .with_crash_deadline(Some(crash_deadline));
+    if let Some(pool) = pool {
+        sim_contr = sim_contr.with_pool(pool);
+    }
+    if let Some(tracing) = tracing {
+        sim_contr = sim_contr.with_tracing(tracing);
+    }
+    sim_contr.log.push(format!("resumed {} drones from a checkpoint", resumed));
 
     (sim_contr, handles)
 }
 
-fn parse_config(file: &str) -> Config {
+pub fn parse_config(file: &str) -> Config {
+    let file_str = fs::read_to_string(file).unwrap();
+    toml::from_str(&file_str).unwrap()
+}
+
+pub fn parse_options(file: &str) -> SimulationOptions {
     let file_str = fs::read_to_string(file).unwrap();
     toml::from_str(&file_str).unwrap()
 }
\ No newline at end of file
@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use serde::Deserialize;
+use tracing::{Dispatch, Subscriber};
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Filter used when the config doesn't give one.
+pub const DEFAULT_TRACE_FILTER: &str = "info";
+/// Lines kept for the GUI, the oldest ones are forgotten.
+const GUI_TRACE_LINES: usize = 1000;
+
+/// Where the spans and events of drones and Simulation Controller go, declared in the config file as:
+/// ```toml
+/// [trace]
+/// filter = "info,SkyLinksProg::skylink_drone::drone=debug" # same syntax as RUST_LOG
+/// console = true
+/// json_file = "trace.jsonl"
+/// gui = true
+/// ```
+/// Every output gets what passes the filter, which can be changed while the simulation runs.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TraceOptions {
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub console: bool,
+    /// File where every event is written as a JSON line, with the fields of its spans.
+    pub json_file: Option<String>,
+    #[serde(default)]
+    pub gui: bool,
+}
+
+/// Last lines of tracing, kept for the GUI.
+#[derive(Clone, Default)]
+pub struct GuiTrace(Arc<Mutex<VecDeque<String>>>);
+
+impl GuiTrace {
+    pub fn lines(&self) -> Vec<String> {
+        self.0.lock().map(|lines| lines.iter().cloned().collect()).unwrap_or_default()
+    }
+}
+
+impl Write for GuiTrace {
+    //The formatter writes a whole event at once.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Ok(mut lines) = self.0.lock() {
+            lines.push_back(String::from_utf8_lossy(buf).trim_end().to_string());
+            while lines.len() > GUI_TRACE_LINES {
+                lines.pop_front();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for GuiTrace {
+    type Writer = GuiTrace;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// The subscriber of the whole program, installed once at the start.
+pub struct Tracing {
+    filter: reload::Handle<EnvFilter, Registry>,
+    gui: Option<GuiTrace>,
+}
+
+impl Tracing {
+    /// Installs the subscriber with the given outputs.
+    /// It fails if the filter isn't valid, the JSON file can't be created, or a subscriber was already installed.
+    pub fn install(options: &TraceOptions) -> Result<Tracing, String> {
+        let (tracing, subscriber) = Tracing::build(options)?;
+        subscriber.try_init().map_err(|error| error.to_string())?;
+        Ok(tracing)
+    }
+
+    /// Same as `install`, but the subscriber is only given back, to be used where the caller wants
+    /// (e.g. on one thread with `tracing::dispatcher::with_default`) instead of by the whole program.
+    pub fn scoped(options: &TraceOptions) -> Result<(Tracing, Dispatch), String> {
+        let (tracing, subscriber) = Tracing::build(options)?;
+        Ok((tracing, Dispatch::new(subscriber)))
+    }
+
+    fn build(options: &TraceOptions) -> Result<(Tracing, impl Subscriber + Send + Sync), String> {
+        let filter = EnvFilter::try_new(options.filter.as_deref().unwrap_or(DEFAULT_TRACE_FILTER))
+            .map_err(|error| format!("invalid trace filter: {}", error))?;
+        let (filter, filter_handle) = reload::Layer::new(filter);
+
+        let json_file = match options.json_file.as_ref() {
+            Some(path) => Some(File::create(path).map_err(|error| format!("can't create {}: {}", path, error))?),
+            None => None,
+        };
+        let gui = options.gui.then(GuiTrace::default);
+
+        let subscriber = Registry::default()
+            .with(filter)
+            .with(options.console.then(fmt::layer))
+            .with(json_file.map(|file| fmt::layer().json().with_writer(Mutex::new(file))))
+            .with(gui.clone().map(|gui| fmt::layer().with_ansi(false).with_writer(gui)));
+
+        Ok((Tracing {
+            filter: filter_handle,
+            gui,
+        }, subscriber))
+    }
+
+    /// Replaces the filter of every output, e.g. with `warn,SkyLinksProg::sim_control=debug`.
+    pub fn set_filter(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|error| format!("invalid trace filter: {}", error))?;
+        self.filter.reload(filter).map_err(|error| error.to_string())
+    }
+
+    pub fn get_filter(&self) -> String {
+        self.filter.with_current(|filter| filter.to_string()).unwrap_or_default()
+    }
+
+    /// The lines for the GUI, if it's one of the outputs.
+    pub fn gui(&self) -> Option<&GuiTrace> {
+        self.gui.as_ref()
+    }
+}
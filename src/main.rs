@@ -1,17 +1,29 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex, PoisonError};
 use crate::test::test_bench::*;
 use crate::initializer::initialize;
+use crate::sim_control::EventPump;
 
 mod sim_app;
 mod sim_control;
 mod initializer;
+mod des;
+mod topology;
+mod logging;
 mod skylink_drone;
 mod test;
 
 fn main() {
     // println!("Hello, world!");
 
+    //The tests named on the command line run instead, e.g. `cargo run -- test_step test_des_crash`.
+    let names: Vec<String> = std::env::args().skip(1).collect();
+    if !names.is_empty() {
+        for name in names {
+            test::run_test(&name);
+        }
+        return;
+    }
+
 
     // Put this to true if you want to use tests
     // or to false if you want to use the Sim Contr application.
@@ -29,15 +41,70 @@ fn main() {
         // test_tree_flood();
          test_drone_commands();
         // test_busy_network();
+        // test_custom_checks();
+        // test_seeded_drops();
+        // test_drop_events();
+        // test_flood_cache_bounded();
+        // test_flood_cache_in_flight();
+        // test_arbitrary_packets();
+        // test_link_timing();
+        // test_config_links();
+        // test_link_pdr();
+        // test_priority_scheduler();
+        // test_fault_duplicate();
+        // test_fault_reorder();
+        // test_fault_corrupt();
+        // test_fault_misroute();
+        // test_fault_drop_acks();
+        // test_fault_delay_nacks();
+        // test_drone_stats();
+        // test_crash_deadline();
+        // test_crash_spamming_neighbour();
+        // test_crash_clean();
+        // test_crash_deadline_drain();
+        // test_step();
+        // test_worker_pool();
+        // test_pool_crash_links();
+        // test_pool_closed_channel();
+        // test_config_pool();
+        // test_des_deterministic();
+        // test_des_virtual_time();
+        // test_des_crash();
+        // test_des_shortcut_to_drone();
+        // test_des_flood_cache_age();
+        // test_capture_replay();
+        // test_capture_replay_diverges();
+        // test_tracing();
+        // test_flood_limit();
+        // test_flood_limit_command();
+        // test_battery_depletion();
+        // test_battery_cost();
+        // test_battery_once_per_packet();
+        // test_battery_idle_drain();
+        // test_async_drones(); //Needs the async feature.
+        // test_snapshot_restore();
+        // test_checkpoint_resume();
+        // test_checkpoint_no_answer();
+        // test_detached_drone();
+        // test_event_pump();
+        // test_controller_shortcut();
+        // test_topology_changes();
+        // test_client_link();
+        // test_unsafe_changes();
+        // test_node_registry();
 
         
 
     } else {
         let (sim_contr, handles) = initialize("inputs/input_generic_fragment_forward.toml");
-        let mut pass = Rc::new(RefCell::new(sim_contr));
-        pass.borrow_mut().crash_drone(2);
+        let pass = Arc::new(Mutex::new(sim_contr));
+        //The events of the drones reach the log while the GUI runs.
+        let event_pump = EventPump::start(pass.clone());
+        if let Err(error) = pass.lock().unwrap_or_else(PoisonError::into_inner).crash_drone(2) {
+            println!("Drone 2 didn't crash: {}", error);
+        }
         sim_app::run_simulation_gui(pass.clone());
-
+        drop(event_pump);
 
 
         for handle in handles.into_iter() {
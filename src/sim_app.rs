@@ -1,12 +1,15 @@
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
 use eframe::egui::{self, Color32, Context, TextureHandle, Vec2};
 use eframe::{App, Frame, NativeOptions};
-use crate::sim_control::SimulationControl;
+use wg_2024::network::NodeId;
+use wg_2024::packet::NodeType;
+use crate::sim_control::{NodeState, SimulationControl};
 
 struct Drone {
     id: String,
+    node: NodeId,
     position: Vec2,
     is_crashed: bool,
     pdr: f32,
@@ -21,29 +24,38 @@ pub struct SimulationApp {
     selected_drone: Option<usize>,
     dragging_drone: Option<usize>, // Track which drone is being dragged
     show_connection_dialog: bool,
-    new_drone_index: Option<usize>,
-    sim_contr: Rc<RefCell<SimulationControl>>,
+    new_drone_pdr: f32, //Pdr and position of the drone being added, until the controller spawns it.
+    new_drone_position: Vec2,
+    sim_contr: Arc<Mutex<SimulationControl>>, //Shared with the EventPump, which fills its log.
     connection_selections: Vec<bool>,
     log_panel_width: f32,        // Width of the log panel
     control_panel_width: f32,   // Width of the control panel
+    trace_filter: Option<String>, // Filter of the tracing being edited, None if the simulation isn't traced
 }
 
 impl SimulationApp {
-    fn new(sim_contr: Rc<RefCell<SimulationControl>>) -> Self {
-        let network_graph = sim_contr.borrow().network_graph.clone();
+    fn new(sim_contr: Arc<Mutex<SimulationControl>>) -> Self {
+        let (network_graph, trace_filter, drones) = {
+            let sim_contr = sim_contr.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut node_ids = sim_contr.network_graph.keys().copied().collect::<Vec<NodeId>>();
+            node_ids.sort();
+            let drones = node_ids.into_iter().enumerate().map(|(index, node_id)| {
+                //The registry of the controller tells what the node is.
+                let node = sim_contr.get_node(node_id);
+                Drone {
+                    id: node_label(node_id, node.map(|node| node.kind)),
+                    node: node_id,
+                    position: Vec2::new(100.0 + (index as f32) * 100.0, 100.0),
+                    is_crashed: node.is_some_and(|node| node.state != NodeState::Running),
+                    pdr: node.and_then(|node| node.pdr).unwrap_or(0.0),
+                }
+            }).collect::<Vec<Drone>>();
+            (sim_contr.network_graph.clone(), sim_contr.get_trace_filter(), drones)
+        };
 
-        let mut drones = Vec::new();
         let mut drone_map = HashMap::new();
-
-        for node_id in network_graph.keys() {
-            let index = drones.len();
-            drones.push(Drone {
-                id: format!("drone{}", node_id),
-                position: Vec2::new(100.0 + (index as f32) * 100.0, 100.0),
-                is_crashed: false,
-                pdr: 0.0,
-            });
-            drone_map.insert(node_id.clone(), index);
+        for (index, drone) in drones.iter().enumerate() {
+            drone_map.insert(drone.node, index);
         }
 
 
@@ -66,15 +78,35 @@ impl SimulationApp {
             selected_drone: None,
             dragging_drone: None,
             show_connection_dialog: false,
-            new_drone_index: None,
+            new_drone_pdr: 0.0,
+            new_drone_position: Vec2::ZERO,
             connection_selections: vec![false; network_graph.len()],
             sim_contr,
             log_panel_width: 200.0,    // Default guess for the left panel width
             control_panel_width: 200.0, // Default guess for the right panel width
+            trace_filter,
         }
     }
 
 
+    //The lock is held only for a moment, the EventPump needs it for every event.
+    fn sim_contr(&self) -> MutexGuard<'_, SimulationControl> {
+        self.sim_contr.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    //Crashes and pdrs can change from the controller, e.g. when a battery runs out.
+    fn sync_nodes(&mut self) {
+        let sim_contr = self.sim_contr.lock().unwrap_or_else(PoisonError::into_inner);
+        for drone in self.drones.iter_mut() {
+            if let Some(node) = sim_contr.get_node(drone.node) {
+                drone.is_crashed = node.state != NodeState::Running;
+                if let Some(pdr) = node.pdr {
+                    drone.pdr = pdr;
+                }
+            }
+        }
+    }
+
     fn load_drone_image(&mut self, ctx: &Context) {
         if self.drone_texture.is_none() {
             let image_data = include_bytes!("drone.png");
@@ -183,10 +215,30 @@ impl SimulationApp {
         });
     }
 
+    fn render_trace(&mut self, ui: &mut egui::Ui) {
+        let Some(trace_filter) = self.trace_filter.as_mut() else {
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.label("Trace filter:");
+            ui.text_edit_singleline(trace_filter);
+            if ui.button("Apply").clicked() {
+                self.sim_contr.lock().unwrap_or_else(PoisonError::into_inner).set_trace_filter(trace_filter);
+            }
+        });
+        let lines = self.sim_contr().trace_lines();
+        egui::ScrollArea::vertical()
+            .id_source("trace")
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for line in lines.iter() {
+                    ui.monospace(line);
+                }
+            });
+    }
+
     fn handle_ui_controls(&mut self, ui: &mut egui::Ui) {
         if ui.button("Add Drone").clicked() {
-            let new_id = format!("Drone{}", self.drones.len() + 1);
-
             // Get the current window size
             let window_size = ui.available_size();
 
@@ -194,22 +246,11 @@ impl SimulationApp {
             let random_x = base_x + fastrand::f32() * 100.0 - 50.0; // Add small random offsets
             let random_y = window_size.y / 2.0 + fastrand::f32() * 100.0 - 50.0;
 
-            let new_drone = Drone {
-                id: new_id.clone(),
-                position: Vec2::new(random_x, random_y),
-                is_crashed: false,
-                pdr: 0.0, // Temporary default value
-            };
-
-            self.drones.push(new_drone);
-            let new_index = self.drones.len() - 1;
-            self.new_drone_index = Some(new_index);
-
-            // Extend connection_selections to match new drones length
-            self.connection_selections.push(false);
-
+            //The drone is shown once the controller spawned it, with the id it got.
+            self.new_drone_position = Vec2::new(random_x, random_y);
+            self.new_drone_pdr = 0.0;
+            self.connection_selections = vec![false; self.drones.len()];
             self.show_connection_dialog = true;
-            self.log.push(format!("{} added", new_id));
         }
     }
 
@@ -224,14 +265,12 @@ impl SimulationApp {
     }
 
     fn render_connection_dialog(&mut self, ui: &mut egui::Ui) {
-        if self.show_connection_dialog && self.new_drone_index.is_some() {
+        if self.show_connection_dialog {
             egui::Window::new("Connect New Drone")
                 .collapsible(false)
                 .show(ui.ctx(), |ui| {
                     ui.label("Select drones to connect the new drone to:");
 
-                    let new_drone_index = self.new_drone_index.unwrap();
-
                     // Ensure connection_selections is the right length
                     if self.connection_selections.len() != self.drones.len() {
                         self.connection_selections = vec![false; self.drones.len()];
@@ -239,44 +278,70 @@ impl SimulationApp {
 
                     // Input field for PDR
                     ui.label("Enter PDR value:");
-                    if let Some(new_drone) = self.drones.get_mut(new_drone_index) {
-                        ui.add(egui::DragValue::new(&mut new_drone.pdr).speed(0.1));
-                    }
+                    ui.add(egui::DragValue::new(&mut self.new_drone_pdr).speed(0.1));
 
                     for (idx, drone) in self.drones.iter().enumerate() {
-                        if idx != new_drone_index {
-                            ui.horizontal(|ui| {
-                                ui.checkbox(&mut self.connection_selections[idx], &drone.id);
-                            });
-                        }
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut self.connection_selections[idx], &drone.id);
+                        });
                     }
 
                     if ui.button("Confirm Connections").clicked() {
-                        for (idx, &is_selected) in self.connection_selections.iter().enumerate() {
-                            if is_selected && idx != new_drone_index {
-                                self.connections.push((new_drone_index, idx));
-                                self.log.push(format!(
-                                    "Connected {} to {}",
-                                    self.drones[new_drone_index].id,
-                                    self.drones[idx].id
-                                ));
-                            }
-                        }
+                        self.spawn_drone();
 
                         // Reset selections
                         self.connection_selections = vec![false; self.drones.len()];
                         self.show_connection_dialog = false;
-                        self.new_drone_index = None;
                     }
                 });
         }
     }
 
+    //The controller spawns the drone, which is shown only if it's really in the network.
+    fn spawn_drone(&mut self) {
+        let selected = self.connection_selections.iter()
+            .enumerate()
+            .filter(|(_, &is_selected)| is_selected)
+            .map(|(idx, _)| idx)
+            .collect::<Vec<usize>>();
+        let neighbours = selected.iter().map(|&idx| self.drones[idx].node).collect::<Vec<NodeId>>();
+
+        let spawned = {
+            let mut sim_contr = self.sim_contr();
+            sim_contr.spawn_drone(self.new_drone_pdr, neighbours)
+                .map(|node_id| (node_id, sim_contr.get_node(node_id).map(|node| (node.kind, node.pdr))))
+        };
+        match spawned {
+            Ok((node_id, node)) => {
+                let new_drone = Drone {
+                    id: node_label(node_id, node.map(|(kind, _)| kind)),
+                    node: node_id,
+                    position: self.new_drone_position,
+                    is_crashed: false,
+                    pdr: node.and_then(|(_, pdr)| pdr).unwrap_or(self.new_drone_pdr),
+                };
+                self.log.push(format!("{} added", new_drone.id));
+                self.drones.push(new_drone);
+                let new_drone_index = self.drones.len() - 1;
+                for idx in selected {
+                    self.connections.push((new_drone_index, idx));
+                    self.log.push(format!(
+                        "Connected {} to {}",
+                        self.drones[new_drone_index].id,
+                        self.drones[idx].id
+                    ));
+                }
+            },
+            Err(error) => self.log.push(format!("Drone not added: {}", error)),
+        }
+    }
+
 }
 
 impl App for SimulationApp {
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
         self.load_drone_image(ctx);
+        self.sync_nodes();
 
         egui::CentralPanel::default().show(ctx, |ui| {
             if let Some(texture) = self.drone_texture.clone() {
@@ -302,7 +367,16 @@ impl App for SimulationApp {
             self.handle_selection(ui);
         });
 
-        let sim_control_log_vec = &self.sim_contr.borrow().log;
+        if self.trace_filter.is_some() {
+            egui::TopBottomPanel::bottom("trace_panel")
+                .min_height(100.0)
+                .max_height(400.0)
+                .resizable(true)
+                .show(ctx, |ui| {
+                    ui.label("Trace:");
+                    self.render_trace(ui);
+                });
+        }
 
         egui::TopBottomPanel::bottom("bottom_panel")
             .min_height(100.0) // Minimum height
@@ -311,18 +385,37 @@ impl App for SimulationApp {
             .show_separator_line(true)
             .show(ctx, |ui| {
                 ui.label("Simulation controller log:");
-                egui::ScrollArea::vertical().show(ui, |ui| {
-                    for message in sim_control_log_vec {
-                        ui.label(message); // Display each message
-                    }
-                });
+                let row_height = ui.text_style_height(&egui::TextStyle::Body);
+                let rows = self.sim_contr().log.len();
+                //Only the lines on screen are drawn, the log grows with every packet.
+                egui::ScrollArea::vertical()
+                    .id_source("sim_control_log")
+                    .stick_to_bottom(true)
+                    .show_rows(ui, row_height, rows, |ui, range| {
+                        let lines = self.sim_contr().log.get(range).map(<[String]>::to_vec).unwrap_or_default();
+                        for message in lines {
+                            ui.label(message); // Display each message
+                        }
+                    });
             });
 
+        //The events and the traced lines arrive from the drones' threads, not from the user.
+        ctx.request_repaint_after(Duration::from_millis(200));
     }
 }
 
 
-pub fn run_simulation_gui(sim_contr: Rc<RefCell<SimulationControl>>) {
+//e.g. client1, drone11 or server100, or node7 if the controller doesn't know what the node is.
+fn node_label(node_id: NodeId, kind: Option<NodeType>) -> String {
+    match kind {
+        Some(NodeType::Client) => format!("client{}", node_id),
+        Some(NodeType::Drone) => format!("drone{}", node_id),
+        Some(NodeType::Server) => format!("server{}", node_id),
+        None => format!("node{}", node_id),
+    }
+}
+
+pub fn run_simulation_gui(sim_contr: Arc<Mutex<SimulationControl>>) {
     let options = NativeOptions::default();
     eframe::run_native(
         "SkyLink Simulation",
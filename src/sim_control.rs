@@ -1,170 +1,931 @@
 use crossbeam_channel::{select, unbounded, Receiver, Sender};
 use std::thread::JoinHandle;
 use std::collections::HashMap;
-use std::thread;
+use std::{fmt, fs, io, thread};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use wg_2024::controller::{DroneCommand, DroneEvent};
 use wg_2024::controller::DroneCommand::{AddSender, RemoveSender};
 use wg_2024::drone::*;
 use wg_2024::network::NodeId;
-use wg_2024::packet::Packet;
-use crate::skylink_drone::drone::SkyLinkDrone;
+use wg_2024::packet::{NodeType, Packet, PacketType};
+use crate::skylink_drone::drone::{derive_seed, SkyLinkDrone};
+use crate::skylink_drone::event::SkyLinkEvent;
+use crate::skylink_drone::command::SkyLinkCommand;
+use crate::skylink_drone::link::LinkParams;
+use crate::skylink_drone::fault::FaultProfile;
+use crate::skylink_drone::stats::{collect_stats, DroneStats, PacketKind};
+use crate::skylink_drone::snapshot::{collect_snapshots, DroneSnapshot};
+use crate::skylink_drone::pool::DronePool;
+use crate::logging::Tracing;
+use crate::topology::{check_change, Violation};
+use tracing::{debug, info, warn};
 
 pub struct SimulationControl{
     node_send: HashMap<NodeId, Sender<DroneCommand>>,
+    endpoint_send: HashMap<NodeId, Sender<DroneCommand>>, //Clients and servers that take AddSender and RemoveSender, to follow the changes of their links.
     node_recv: Receiver<DroneEvent>,
     channel_for_drone: Sender<DroneEvent>, // questo serve così ogni volta che creo un nuovo drone, quando gli devo dare il channel per comunicare con il drone, mi limito a clonare questo
     all_sender_packets: HashMap<NodeId, Sender<Packet>>, //hashmap con tutti i sender packet così puoi clonarli nel spawn
     pub(crate) network_graph: HashMap<NodeId, Vec<NodeId>>,
+    nodes: HashMap<NodeId, NodeInfo>, //Every node the controller knows, including the drones that crashed.
     pub(crate) log: Vec<String>,
+    seed: u64, //Master seed of the simulation, every drone derives its seed from this one.
+    skylink_send: HashMap<NodeId, Sender<SkyLinkCommand>>, //Commands of our drones that don't fit in a DroneCommand.
+    skylink_recv: Receiver<SkyLinkEvent>, //Events of our drones that don't fit in a DroneEvent.
+    channel_for_skylink_events: Sender<SkyLinkEvent>,
+    dropped_packets: HashMap<NodeId, u64>, //How many fragments every drone dropped.
+    battery: HashMap<NodeId, f32>, //Last charge reported by the drones with a battery.
+    shortcuts: HashMap<NodeId, ShortcutCounters>, //Packets the drones sent through the controller, by destination.
+    pool: Option<DronePool>, //If present, new drones run here instead of on a thread each.
+    tracing: Option<Tracing>, //The subscriber of the tracing, if the config installs one.
+    crash_deadline: Option<Duration>, //Given to the drones spawned, None if they wait for their neighbours as long as it takes.
+}
+
+/// Packets that reached the Simulation Controller through `DroneEvent::ControllerShortcut`, for one destination.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShortcutCounters {
+    /// Handed to the destination.
+    pub delivered: u64,
+    /// Lost because the destination isn't in the network, or doesn't receive anymore.
+    pub undeliverable: u64,
+}
+
+/// What the Simulation Controller knows about a node of the network.
+#[derive(Debug)]
+pub struct NodeInfo {
+    pub kind: NodeType,
+    /// Pdr of a drone, as last set by the controller. None for clients and servers, or if it was never told.
+    pub pdr: Option<f32>,
+    pub state: NodeState,
+    handle: Option<JoinHandle<()>>, //Only for the drones the controller spawned on a thread of their own.
+}
+
+impl NodeInfo {
+    pub fn new(kind: NodeType, pdr: Option<f32>) -> Self {
+        NodeInfo {
+            kind,
+            pdr,
+            state: NodeState::Running,
+            handle: None,
+        }
+    }
+}
+
+/// Where a node is in its life. Clients and servers are always running, as far as the controller knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    Running,
+    /// The drone got the crash command, it's still handling the packets it has.
+    Crashing,
+    Stopped,
+}
+
+/// Why the Simulation Controller refused to change the network, which is then left as it was.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TopologyError {
+    /// There's no node with this id in the network (anymore, if it was a drone that crashed).
+    UnknownNode(NodeId),
+    /// Only drones take this command, and the node is a client or a server.
+    NotADrone(NodeId),
+    SelfLink(NodeId),
+    /// The same neighbour was given twice.
+    DuplicateNeighbour(NodeId),
+    AlreadyLinked(NodeId, NodeId),
+    NotLinked(NodeId, NodeId),
+    /// A pdr is a probability, between 0 and 1.
+    InvalidPdr(f32),
+    /// The node doesn't receive anymore, e.g. a drone that already stopped.
+    Unreachable(NodeId),
+    /// Every id is already taken.
+    NoFreeId,
+    /// The client or server has no channel to be told that its links change.
+    NoCommandChannel(NodeId),
+    /// The change would break a rule of the protocol on the shape of the network.
+    Unsafe(Violation),
+}
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopologyError::UnknownNode(id) => write!(f, "node {} isn't in the network", id),
+            TopologyError::NotADrone(id) => write!(f, "node {} isn't a drone", id),
+            TopologyError::SelfLink(id) => write!(f, "node {} can't be linked to itself", id),
+            TopologyError::DuplicateNeighbour(id) => write!(f, "node {} is given twice as neighbour", id),
+            TopologyError::AlreadyLinked(a, b) => write!(f, "nodes {} and {} are already linked", a, b),
+            TopologyError::NotLinked(a, b) => write!(f, "nodes {} and {} aren't linked", a, b),
+            TopologyError::InvalidPdr(pdr) => write!(f, "pdr {} isn't between 0 and 1", pdr),
+            TopologyError::Unreachable(id) => write!(f, "node {} doesn't receive anymore", id),
+            TopologyError::NoFreeId => write!(f, "every id is already taken"),
+            TopologyError::NoCommandChannel(id) => write!(f, "node {} can't be told that its links change", id),
+            TopologyError::Unsafe(violation) => write!(f, "{}", violation),
+        }
+    }
+}
+
+impl std::error::Error for TopologyError {}
+
+fn link(graph: &mut HashMap<NodeId, Vec<NodeId>>, a: NodeId, b: NodeId) {
+    for (id, neighbour) in [(a, b), (b, a)] {
+        let links = graph.entry(id).or_default();
+        if !links.contains(&neighbour) {
+            links.push(neighbour);
+        }
+    }
+}
+
+fn unlink(graph: &mut HashMap<NodeId, Vec<NodeId>>, a: NodeId, b: NodeId) {
+    for (id, neighbour) in [(a, b), (b, a)] {
+        if let Some(links) = graph.get_mut(&id) {
+            links.retain(|link| *link != neighbour);
+        }
+    }
+}
+
+//Takes the node out of the graph, with every link to it.
+fn unlink_all(graph: &mut HashMap<NodeId, Vec<NodeId>>, id: NodeId) {
+    if let Some(neighbours) = graph.remove(&id) {
+        for neighbour in neighbours {
+            if let Some(links) = graph.get_mut(&neighbour) {
+                links.retain(|link| *link != id);
+            }
+        }
+    }
+}
+
+fn check_pdr(pdr: f32) -> Result<(), TopologyError> {
+    if (0.0..=1.0).contains(&pdr) {
+        Ok(())
+    } else {
+        Err(TopologyError::InvalidPdr(pdr))
+    }
+}
+
+/// Lines kept in the log of the Simulation Controller, the oldest ones are forgotten.
+const MAX_LOG_LINES: usize = 10_000;
+
+/// Times the running drones are asked for their snapshot before a checkpoint is refused.
+const CHECKPOINT_ATTEMPTS: u32 = 3;
+/// How long a checkpoint waits for the snapshots at each attempt.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Why the Simulation Controller couldn't take a checkpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckpointError {
+    /// These drones are running, as far as the controller knows, but they never sent their snapshot.
+    NoAnswer(Vec<NodeId>),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::NoAnswer(ids) => write!(f, "drones {:?} didn't send their snapshot", ids),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+/// State of the drones of a simulation, taken with `SimulationControl::checkpoint`.
+/// `initializer::resume` starts a new simulation from it, as many times as needed: every resumed simulation
+/// goes on independently, so two of them can be used to compare what happens with different commands.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub seed: u64,
+    /// Graph of the network, with the clients and servers, but without the drones that already stopped.
+    pub network_graph: HashMap<NodeId, Vec<NodeId>>,
+    /// Kinds of the nodes, as given by the config.
+    #[serde(default)]
+    pub node_kinds: HashMap<NodeId, NodeType>,
+    pub drones: HashMap<NodeId, DroneSnapshot>,
+}
+
+impl Checkpoint {
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string(self)?)
+    }
+
+    pub fn read_from(path: &Path) -> io::Result<Checkpoint> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
 }
 
 impl SimulationControl{
     pub fn new(node_send: HashMap<NodeId, Sender<DroneCommand>>, node_recv: Receiver<DroneEvent>, channel_for_drone :Sender<DroneEvent> , all_sender_packets: HashMap<NodeId, Sender<Packet>>, network_graph: HashMap<NodeId, Vec<NodeId>>)->Self{
+        //A random seed until the options give one, the log tells it anyway so that the run can be replayed.
+        let seed = fastrand::u64(..);
+        //Until it's given the channels of the SkyLink drones, the controller only hears from the ones it spawns.
+        let (channel_for_skylink_events, skylink_recv) = unbounded();
+        //Without the config, only the drones are known for sure.
+        let nodes = node_send.keys().map(|id| (*id, NodeInfo::new(NodeType::Drone, None))).collect();
         SimulationControl{
             node_send,
+            endpoint_send: HashMap::new(),
             node_recv,
             channel_for_drone,
             all_sender_packets,
             network_graph,
-            log: Vec::new(),
+            nodes,
+            log: vec![format!("simulation seed: {}", seed)],
+            seed,
+            skylink_send: HashMap::new(),
+            skylink_recv,
+            channel_for_skylink_events,
+            dropped_packets: HashMap::new(),
+            battery: HashMap::new(),
+            shortcuts: HashMap::new(),
+            pool: None,
+            tracing: None,
+            crash_deadline: None,
         }
     }
 
-    pub fn run(&mut self){
-        loop{
-            select! {
-            recv(self.node_recv) -> e =>{
-                    if let Ok(event) = e {
-                        self.add_to_log(event);
-                    }
-                }
-            }
+    /// Sets the master seed of the simulation, the drones spawned derive their seed from it.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        //The first line of the log is always the seed.
+        self.log[0] = format!("simulation seed: {}", seed);
+        self
+    }
+
+    /// Gives the channels of the SkyLink drones: where their `SkyLinkCommand`s go, where their `SkyLinkEvent`s
+    /// arrive, and the sender of that channel, cloned for the drones spawned.
+    pub fn with_skylink_channels(mut self, skylink_send: HashMap<NodeId, Sender<SkyLinkCommand>>, skylink_recv: Receiver<SkyLinkEvent>, channel_for_skylink_events: Sender<SkyLinkEvent>) -> Self {
+        self.skylink_send = skylink_send;
+        self.skylink_recv = skylink_recv;
+        self.channel_for_skylink_events = channel_for_skylink_events;
+        self
+    }
+
+    /// Gives the channels where the clients and servers take `AddSender` and `RemoveSender`, as the drones do.
+    /// Without one, the links of a client or server can't be changed, since it wouldn't know.
+    pub fn with_endpoint_channels(mut self, endpoint_send: HashMap<NodeId, Sender<DroneCommand>>) -> Self {
+        self.endpoint_send.extend(endpoint_send);
+        self
+    }
+
+    /// Makes the drones spawned run on the pool, instead of on a thread each.
+    pub fn with_pool(mut self, pool: DronePool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Tells what the nodes are, usually from the config. The clients and servers must be here,
+    /// so that the changes of the network can't cut them off.
+    pub fn with_nodes(mut self, nodes: HashMap<NodeId, NodeInfo>) -> Self {
+        self.nodes.extend(nodes);
+        self
+    }
+
+    pub fn get_node(&self, id: NodeId) -> Option<&NodeInfo> {
+        self.nodes.get(&id)
+    }
+
+    /// The thread of a drone spawned by the controller, to join it once it stopped.
+    /// The threads of the drones in the config are the ones `initialize` returns.
+    pub fn take_handle(&mut self, id: NodeId) -> Option<JoinHandle<()>> {
+        self.nodes.get_mut(&id).and_then(|node| node.handle.take())
+    }
+
+    fn node_kinds(&self) -> HashMap<NodeId, NodeType> {
+        self.nodes.iter().map(|(id, node)| (*id, node.kind)).collect()
+    }
+
+    //The drone is in the registry and still takes commands.
+    fn running_drone(&self, id: NodeId) -> Result<(), TopologyError> {
+        match self.nodes.get(&id) {
+            Some(node) if node.kind != NodeType::Drone => Err(TopologyError::NotADrone(id)),
+            Some(node) if node.state != NodeState::Running => Err(TopologyError::Unreachable(id)),
+            Some(_) => Ok(()),
+            //Nodes that aren't in the registry are still in the graph if the controller wasn't told about them.
+            None if self.network_graph.contains_key(&id) => Err(TopologyError::NotADrone(id)),
+            None => Err(TopologyError::UnknownNode(id)),
+        }
+    }
+
+    pub fn with_tracing(mut self, tracing: Tracing) -> Self {
+        self.tracing = Some(tracing);
+        self
+    }
+
+    /// Sets the crash deadline of the drones spawned from now on, usually the one of the options.
+    pub fn with_crash_deadline(mut self, crash_deadline: Option<Duration>) -> Self {
+        self.crash_deadline = crash_deadline;
+        self
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Changes which spans and events are traced, with the syntax of RUST_LOG,
+    /// e.g. `info,SkyLinksProg::skylink_drone::drone=trace` to follow every check of the drones.
+    pub fn set_trace_filter(&mut self, directives: &str) {
+        let Some(tracing) = self.tracing.as_ref() else {
+            self.log.push("there's no tracing to filter".to_string());
+            return;
+        };
+        match tracing.set_filter(directives) {
+            Ok(()) => self.log.push(format!("trace filter set to {}", directives)),
+            Err(error) => self.log.push(error),
+        }
+    }
+
+    pub fn get_trace_filter(&self) -> Option<String> {
+        self.tracing.as_ref().map(Tracing::get_filter)
+    }
+
+    /// The last traced lines, if the GUI is one of the outputs of the tracing.
+    pub fn trace_lines(&self) -> Vec<String> {
+        self.tracing.as_ref().and_then(Tracing::gui).map(|gui| gui.lines()).unwrap_or_default()
+    }
+
+    /// Last charge reported by the given drone, if it has a battery.
+    pub fn get_battery(&self, id: NodeId) -> Option<f32> {
+        self.battery.get(&id).copied()
+    }
+
+    /// Number of fragments dropped by the given drone so far.
+    pub fn get_dropped_packets(&self, id: NodeId) -> u64 {
+        self.dropped_packets.get(&id).copied().unwrap_or(0)
+    }
+
+    /// Packets sent through the controller to the given node so far.
+    pub fn get_shortcuts(&self, id: NodeId) -> ShortcutCounters {
+        self.shortcuts.get(&id).copied().unwrap_or_default()
+    }
+
+    /// Handles the events the drones already sent, without waiting for new ones, and tells how many there were.
+    /// Usually the `EventPump` does it as soon as they arrive.
+    pub fn handle_pending_events(&mut self) -> usize {
+        let mut handled = 0;
+        while let Ok(event) = self.node_recv.try_recv() {
+            self.add_to_log(event);
+            handled += 1;
+        }
+        while let Ok(event) = self.skylink_recv.try_recv() {
+            self.add_skylink_event_to_log(event);
+            handled += 1;
+        }
+        self.trim_log();
+        handled
+    }
+
+    fn trim_log(&mut self) {
+        if self.log.len() > MAX_LOG_LINES {
+            let excess = self.log.len() - MAX_LOG_LINES;
+            self.log.drain(..excess);
         }
     }
 
     fn add_to_log(&mut self, e: DroneEvent){
         match e {
             DroneEvent::PacketSent(packet) => {
-                let id_drone = packet.routing_header.hops.get(packet.routing_header.hops.len() -1).unwrap();
-                self.log.push( format!("Drone {} sent fragment {:?} of type: {:?}",id_drone ,packet.session_id, packet.pack_type))}
+                //A FloodRequest has the drone at the end of its path_trace, the other packets were sent with
+                //hop_index already pointing to the next hop, so the drone is the one before.
+                let sender = match &packet.pack_type {
+                    PacketType::FloodRequest(flood_request) => flood_request.path_trace.last().map(|(id, _kind)| id),
+                    _ => packet.routing_header.hop_index.checked_sub(1).and_then(|index| packet.routing_header.hops.get(index)),
+                };
+                if let Some(&id_drone) = sender {
+                    debug!(drone = id_drone, session_id = packet.session_id, hop_index = packet.routing_header.hop_index, kind = ?PacketKind::of(&packet.pack_type), "packet sent");
+                    self.log.push( format!("Drone {} sent fragment {:?} of type: {:?}",id_drone ,packet.session_id, packet.pack_type))
+                }
+            }
             DroneEvent::PacketDropped(packet) => {
-                let id_drone = packet.routing_header.hops.get(packet.routing_header.hops.len() -1).unwrap();
-                self.log.push( format!("Drone {} dropped fragment {:?} of type: {:?}",id_drone ,packet.session_id, packet.pack_type))}
+                //The packet is the one the drone received, so its hop_index points to the drone itself.
+                if let Some(&id_drone) = packet.routing_header.hops.get(packet.routing_header.hop_index) {
+                    *self.dropped_packets.entry(id_drone).or_insert(0) += 1;
+                    debug!(drone = id_drone, session_id = packet.session_id, hop_index = packet.routing_header.hop_index, "fragment dropped");
+                    self.log.push( format!("Drone {} dropped fragment {:?} of type: {:?}",id_drone ,packet.session_id, packet.pack_type))
+                }
+            }
             DroneEvent::ControllerShortcut(packet) => {
-                let id_drone = packet.routing_header.hops.get(packet.routing_header.hops.len() -1).unwrap();
-                self.log.push( format!("Received {:?} from drone {:?}", packet.pack_type, id_drone));
+                self.deliver_shortcut(packet);
             }
         }
     }
 
-    fn spawn_drone (&mut self, pdr: f32, connections: Vec<NodeId>) -> JoinHandle<()>{
-        let new_id = self.generate_id();
-        //aggiorna network graph
-        self.network_graph.insert(new_id, connections.clone());
+    //The Ack, Nack or FloodResponse a drone couldn't route goes straight to the last hop of its routing header,
+    //if that's a client or a server.
+    fn deliver_shortcut(&mut self, mut packet: Packet){
+        let kind = PacketKind::of(&packet.pack_type);
+        let Some(&destination) = packet.routing_header.hops.last() else {
+            warn!(session_id = packet.session_id, kind = ?kind, "shortcut without a destination");
+            self.log.push(format!("Can't deliver {:?} of session {}: it has no destination", packet.pack_type, packet.session_id));
+            return;
+        };
+        //The destination gets it as if it arrived through the last hop.
+        packet.routing_header.hop_index = packet.routing_header.hops.len() - 1;
+        let session_id = packet.session_id;
+        let sender = match self.nodes.get(&destination).map(|node| node.kind) {
+            Some(NodeType::Client | NodeType::Server) => self.all_sender_packets.get(&destination),
+            //A drone would send it back through the controller, again and again.
+            Some(NodeType::Drone) => {
+                warn!(destination, session_id, kind = ?kind, "shortcut destination is a drone");
+                self.log.push(format!("Can't deliver {:?} of session {} to node {}: it's a drone", kind, session_id, destination));
+                self.shortcuts.entry(destination).or_default().undeliverable += 1;
+                return;
+            },
+            None => None,
+        };
+        let delivered = match sender {
+            Some(sender) => match sender.send(packet) {
+                Ok(()) => {
+                    info!(destination, session_id, kind = ?kind, "shortcut delivered");
+                    self.log.push(format!("Delivered {:?} of session {} to node {} through the controller", kind, session_id, destination));
+                    true
+                },
+                Err(_e) => {
+                    warn!(destination, session_id, kind = ?kind, "shortcut destination doesn't receive anymore");
+                    self.log.push(format!("Can't deliver {:?} of session {} to node {}: it doesn't receive anymore", kind, session_id, destination));
+                    false
+                },
+            },
+            None => {
+                warn!(destination, session_id, kind = ?kind, "shortcut destination unknown");
+                self.log.push(format!("Can't deliver {:?} of session {} to node {}: it isn't in the network", kind, session_id, destination));
+                false
+            },
+        };
+        let counters = self.shortcuts.entry(destination).or_default();
+        if delivered {
+            counters.delivered += 1;
+        } else {
+            counters.undeliverable += 1;
+        }
+    }
 
-        let (control_sender, control_receiver) = unbounded();  //canale per il Sim che manda drone command al drone
-        self.node_send.insert(new_id.clone(), control_sender.clone());                                      // do al sim il sender per questo drone
+    fn add_skylink_event_to_log(&mut self, e: SkyLinkEvent){
+        match e {
+            SkyLinkEvent::NackGenerated { drone, nack_type, nack } => {
+                debug!(drone, session_id = nack.session_id, nack_type = ?nack_type, "nack generated");
+                self.log.push(format!("Drone {} generated a {:?} nack for session {}", drone, nack_type, nack.session_id));
+            }
+            SkyLinkEvent::PacketDiscarded { drone, reason, packet } => {
+                warn!(drone, session_id = packet.session_id, reason = ?reason, "packet discarded");
+                self.log.push(format!("Drone {} discarded {:?} of session {} ({:?})", drone, packet.pack_type, packet.session_id, reason));
+            }
+            SkyLinkEvent::FaultInjected { drone, fault, packet } => {
+                debug!(drone, session_id = packet.session_id, fault = ?fault, "fault injected");
+                self.log.push(format!("Drone {} injected {:?} in {:?} of session {}", drone, fault, packet.pack_type, packet.session_id));
+            }
+            SkyLinkEvent::Crashed { drone, forced } => {
+                info!(drone, forced, "drone stopped");
+                if let Some(node) = self.nodes.get_mut(&drone) {
+                    node.state = NodeState::Stopped;
+                }
+                if forced {
+                    self.log.push(format!("Drone {} stopped after its crash deadline, some neighbour still had a channel to it", drone));
+                } else {
+                    self.log.push(format!("Drone {} stopped", drone));
+                }
+            }
+            SkyLinkEvent::BatteryLevel { drone, percentage, charge } => {
+                debug!(drone, percentage, charge, "battery level");
+                self.battery.insert(drone, charge);
+                self.log.push(format!("Drone {} has {}% of its battery left", drone, percentage));
+            }
+            SkyLinkEvent::BatteryDepleted { drone } => {
+                info!(drone, "battery empty");
+                self.battery.insert(drone, 0.0);
+                self.log.push(format!("Drone {} ran out of battery", drone));
+                //The drone is already crashing, its neighbours have to let it go, whatever it does to the network.
+                self.crash(drone);
+            }
+        }
+    }
 
+    /// Creates a new drone with the given pdr, linked to the given nodes, and tells its id.
+    /// Its thread, unless it runs on the pool, is kept until `take_handle`.
+    pub fn spawn_drone(&mut self, pdr: f32, neighbours: Vec<NodeId>) -> Result<NodeId, TopologyError>{
+        if let Err(error) = self.check_spawn(pdr, &neighbours) {
+            return Err(self.refused(error));
+        }
+        let new_id = match self.generate_id() {
+            Some(id) => id,
+            None => return Err(self.refused(TopologyError::NoFreeId)),
+        };
+        //A client can't get a third drone.
+        if let Err(error) = self.check_safe(|graph| {
+            graph.insert(new_id, Vec::new());
+            for neighbour in neighbours.iter() {
+                link(graph, new_id, *neighbour);
+            }
+        }) {
+            return Err(self.refused(error));
+        }
 
+        let (control_sender, control_receiver) = unbounded();  //canale per il Sim che manda drone command al drone
         let (packet_send, packet_recv) = unbounded();                       //canale per il drone, il recv gli va dentro, il send va dato in copia a tutti i droni che vogliono comunicare con lui
-        for (id, sender) in self.node_send.iter() {                        // per dare a tutti i droni in node_in il sender al new drone
-            for i in connections.clone() {
-                if i == *id {
-                    sender.send(AddSender(new_id, packet_send.clone())).unwrap();
+        self.node_send.insert(new_id, control_sender);
+        self.all_sender_packets.insert(new_id, packet_send);
+
+        //The neighbours learn about the new one, if one of them can't the drone isn't spawned.
+        for (i, neighbour) in neighbours.iter().enumerate() {
+            if let Err(error) = self.connect(*neighbour, new_id) {
+                for connected in neighbours[..i].iter() {
+                    let _ = self.disconnect(*connected, new_id);
                 }
+                self.node_send.remove(&new_id);
+                self.all_sender_packets.remove(&new_id);
+                return Err(self.refused(error));
             }
         }
 
-        let mut packet_send = HashMap::new();
-        //riempi la hashmap
-        for (id, sender) in &self.all_sender_packets {
-            for i in connections.clone() {
-                if i == *id{
-                    packet_send.insert(*id, sender.clone());
-                }
-            }
+        let (skylink_control_sender, skylink_control_receiver) = unbounded();
+        self.skylink_send.insert(new_id, skylink_control_sender);
+        self.network_graph.insert(new_id, Vec::new());
+        for neighbour in neighbours.iter() {
+            link(&mut self.network_graph, new_id, *neighbour);
         }
+        let packet_send = neighbours.iter()
+            .map(|neighbour| (*neighbour, self.all_sender_packets[neighbour].clone()))
+            .collect::<HashMap<NodeId, Sender<Packet>>>();
 
         let channel_clone = self.channel_for_drone.clone();
+        let skylink_channel_clone = self.channel_for_skylink_events.clone();
+        let seed = derive_seed(self.seed, new_id);
+        info!(drone = new_id, seed, neighbours = ?neighbours, "drone spawned");
+        self.log.push(format!("drone {} spawned with seed {}", new_id, seed));
 
-        //crea thread
-        let handle = thread::spawn(move || {
-            let mut new_drone = SkyLinkDrone::new(new_id, channel_clone, control_receiver, packet_recv, packet_send, pdr);
-            new_drone.run();
-        });
-        handle
+        let mut new_drone = SkyLinkDrone::new(new_id, channel_clone, control_receiver, packet_recv, packet_send, pdr)
+            .with_seed(seed)
+            .with_event_channel(skylink_channel_clone)
+            .with_command_channel(skylink_control_receiver)
+            .with_crash_deadline(self.crash_deadline);
+        let mut node = NodeInfo::new(NodeType::Drone, Some(pdr));
+        node.handle = match self.pool.as_mut() {
+            Some(pool) => {
+                pool.add(new_drone);
+                None
+            },
+            //crea thread
+            None => Some(thread::spawn(move || {
+                new_drone.run();
+            })),
+        };
+        self.nodes.insert(new_id, node);
+        Ok(new_id)
     }
 
-    fn generate_id (&mut self) -> NodeId {//just a function to generate an id that is empty in our hashmap, if is 1-3-4, it should give 2, if it's 1-2-3, should give 4.
-        for k in 0..=u8::MAX {
-            //If k is not a key in the map, I return it.
-            if !self.node_send.contains_key(&k) {
-                return k;
+    fn check_spawn(&self, pdr: f32, neighbours: &[NodeId]) -> Result<(), TopologyError> {
+        check_pdr(pdr)?;
+        for (i, neighbour) in neighbours.iter().enumerate() {
+            if !self.network_graph.contains_key(neighbour) {
+                return Err(TopologyError::UnknownNode(*neighbour));
+            }
+            if neighbours[..i].contains(neighbour) {
+                return Err(TopologyError::DuplicateNeighbour(*neighbour));
+            }
+            if !self.all_sender_packets.contains_key(neighbour) {
+                return Err(TopologyError::Unreachable(*neighbour));
             }
         }
+        Ok(())
+    }
+
+    fn generate_id (&self) -> Option<NodeId> {//just a function to generate an id that is empty in our hashmap, if is 1-3-4, it should give 2, if it's 1-2-3, should give 4.
+        //The ids of the clients, the servers and the crashed drones are taken too.
+        (0..=u8::MAX).find(|k| !self.nodes.contains_key(k) && !self.network_graph.contains_key(k))
+    }
 
-        unreachable!("No free key found");
+    /// Crashes the drone, unless that would cut off a client or a server.
+    pub fn crash_drone(&mut self, id: NodeId) -> Result<(), TopologyError>{
+        if let Err(error) = self.running_drone(id).and_then(|()| self.check_safe(|graph| unlink_all(graph, id))) {
+            return Err(self.refused(error));
+        }
+        if self.crash(id) {
+            Ok(())
+        } else {
+            Err(self.refused(TopologyError::Unreachable(id)))
+        }
     }
 
-    pub fn crash_drone(&mut self, id: NodeId){
+    //Crashes the drone without looking at the network, tells if it got the command.
+    fn crash(&mut self, id: NodeId) -> bool {
         if let Some(sender) = self.node_send.get(&id) {
             if let Err(e) = sender.send(DroneCommand::Crash) {
-                println!("error in crashing drone {}: {:?}", id, e);
+                warn!(drone = id, error = ?e, "can't send the crash command");
+                false
             } else {
-                println!("crash command sent do the drone {}", id);
+                info!(drone = id, "crash command sent");
 
 
                 // remove the drone from the neighbour's sends
                 if let Some(vec) = self.network_graph.get(&id) {
                     for (neighbor_id, neighbor_sender) in &self.node_send {
                         if vec.contains(neighbor_id) {
-                            neighbor_sender.send(RemoveSender(id)).unwrap()
+                            //A neighbour that already stopped has nothing to remove.
+                            if let Err(_e) = neighbor_sender.send(RemoveSender(id)) {
+                                warn!(drone = *neighbor_id, crashed = id, "neighbour doesn't take commands anymore");
+                                self.log.push(format!("drone {} doesn't take commands anymore, it can't remove drone {}", neighbor_id, id));
+                            }
+                        }
+                    }
+                    //The clients and servers that can be told let it go too.
+                    for (neighbor_id, neighbor_sender) in &self.endpoint_send {
+                        if vec.contains(neighbor_id) {
+                            let _ = neighbor_sender.send(RemoveSender(id));
                         }
                     }
                 }
                 if let Some(to_be_dropped) = self.node_send.remove(&id){
                     drop(to_be_dropped);
                 }
+                //The drone stops once nobody can send to it, the controller included.
+                self.all_sender_packets.remove(&id);
+                if let Some(node) = self.nodes.get_mut(&id) {
+                    node.state = NodeState::Crashing;
+                }
+                //The crashed drone isn't part of the network anymore.
+                unlink_all(&mut self.network_graph, id);
                 self.log.push(format!("drone {} crashed.", id));
+                true
             }
         } else {
-            println!("drone {} not found in the network.", id);
+            warn!(drone = id, "drone not found in the network");
+            false
         }
     }
-    fn remove_senders(&mut self, id: NodeId, id_to_remove: NodeId){
-        if let Some(sender) = self.node_send.get(&id) {
-            if let Err(_e) = sender.send(RemoveSender(id_to_remove)) {
-                println!("error in removing drone {} from drone {} senders", id_to_remove, id);
-            } else {
-                println!("drone {} removed from drone {} senders", id_to_remove, id);
-                self.log.push(format!("drone {} removed from drone {} senders", id_to_remove, id));
-            }
+
+    /// Links two nodes, both ways: each one gets the channel to the other.
+    /// A client or server must have a channel given with `with_endpoint_channels`, or the link is refused.
+    pub fn add_link(&mut self, a: NodeId, b: NodeId) -> Result<(), TopologyError>{
+        if let Err(error) = self.check_link(a, b, false).and_then(|()| self.check_safe(|graph| link(graph, a, b))) {
+            return Err(self.refused(error));
+        }
+        if let Err(error) = self.connect(a, b) {
+            return Err(self.refused(error));
+        }
+        if let Err(error) = self.connect(b, a) {
+            //Half a link is worse than none.
+            let _ = self.disconnect(a, b);
+            return Err(self.refused(error));
         }
+        link(&mut self.network_graph, a, b);
+        info!(a, b, "link added");
+        self.log.push(format!("link between {} and {} added", a, b));
+        Ok(())
     }
 
-    fn add_sender(&mut self, id: NodeId, id_to_add: NodeId, ){
-        if let Some(sender) = self.node_send.get(&id) {
-            if let Some(senderpacket) = self.all_sender_packets.get(&id) {
-                if let Err(_e) = sender.send(AddSender(id_to_add, senderpacket.clone())) {
-                    println!("error adding drone {} to drone {} senders", id_to_add, id);
-                } else {
-                    println!("drone {} added to drone {} senders", id_to_add, id);
-                    self.log.push(format!("drone {} added to drone {} senders", id_to_add, id));
-                }
+    /// Removes the link between two nodes, both ways, as `add_link` adds it, unless that would cut off a client or a server.
+    pub fn remove_link(&mut self, a: NodeId, b: NodeId) -> Result<(), TopologyError>{
+        if let Err(error) = self.check_link(a, b, true).and_then(|()| self.check_safe(|graph| unlink(graph, a, b))) {
+            return Err(self.refused(error));
+        }
+        if let Err(error) = self.disconnect(a, b) {
+            return Err(self.refused(error));
+        }
+        if let Err(error) = self.disconnect(b, a) {
+            let _ = self.connect(a, b);
+            return Err(self.refused(error));
+        }
+        unlink(&mut self.network_graph, a, b);
+        info!(a, b, "link removed");
+        self.log.push(format!("link between {} and {} removed", a, b));
+        Ok(())
+    }
+
+    pub fn set_pdr(&mut self, id: NodeId, pdr: f32) -> Result<(), TopologyError>{
+        if let Err(error) = check_pdr(pdr) {
+            return Err(self.refused(error));
+        }
+        if let Err(error) = self.running_drone(id) {
+            return Err(self.refused(error));
+        }
+        let sent = self.node_send.get(&id).is_some_and(|sender| sender.send(DroneCommand::SetPacketDropRate(pdr)).is_ok());
+        if !sent {
+            return Err(self.refused(TopologyError::Unreachable(id)));
+        }
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.pdr = Some(pdr);
+        }
+        info!(drone = id, pdr, "pdr set");
+        self.log.push(format!("drone {} now has pdr set to {}", id, pdr));
+        Ok(())
+    }
+
+    fn check_link(&self, a: NodeId, b: NodeId, linked: bool) -> Result<(), TopologyError> {
+        if a == b {
+            return Err(TopologyError::SelfLink(a));
+        }
+        for id in [a, b] {
+            if !self.network_graph.contains_key(&id) {
+                return Err(TopologyError::UnknownNode(id));
             }
         }
+        let is_linked = self.network_graph[&a].contains(&b) || self.network_graph[&b].contains(&a);
+        match (linked, is_linked) {
+            (false, true) => Err(TopologyError::AlreadyLinked(a, b)),
+            (true, false) => Err(TopologyError::NotLinked(a, b)),
+            _ => Ok(()),
+        }
     }
 
-    fn set_pdr(&mut self, id: NodeId, pdr: f32 ){
-        if let Some(sender) = self.node_send.get(&id) {
-            if let Err(_e) = sender.send(DroneCommand::SetPacketDropRate(pdr)) {
-                println!("error in setting drone {} pdr to {}", id, pdr);
-            } else {
-                println!("setting drone {} pdr to {}", id, pdr);
-                self.log.push(format!("drone {} now has pdr set to {}", id, pdr));
+    //Gives node `id` the channel to `neighbour`, with the same command for drones, clients and servers.
+    fn connect(&self, id: NodeId, neighbour: NodeId) -> Result<(), TopologyError> {
+        let sender = self.command_channel(id)?;
+        let packet_send = self.all_sender_packets.get(&neighbour).ok_or(TopologyError::Unreachable(neighbour))?;
+        sender.send(AddSender(neighbour, packet_send.clone())).map_err(|_e| TopologyError::Unreachable(id))
+    }
+
+    fn disconnect(&self, id: NodeId, neighbour: NodeId) -> Result<(), TopologyError> {
+        let sender = self.command_channel(id)?;
+        sender.send(RemoveSender(neighbour)).map_err(|_e| TopologyError::Unreachable(id))
+    }
+
+    fn command_channel(&self, id: NodeId) -> Result<&Sender<DroneCommand>, TopologyError> {
+        if self.is_drone(id) {
+            self.node_send.get(&id).ok_or(TopologyError::Unreachable(id))
+        } else {
+            self.endpoint_send.get(&id).ok_or(TopologyError::NoCommandChannel(id))
+        }
+    }
+
+    fn is_drone(&self, id: NodeId) -> bool {
+        self.nodes.get(&id).is_some_and(|node| node.kind == NodeType::Drone)
+    }
+
+    //The change of the graph mustn't break a rule of the protocol the network follows now.
+    fn check_safe(&self, change: impl FnOnce(&mut HashMap<NodeId, Vec<NodeId>>)) -> Result<(), TopologyError> {
+        check_change(&self.network_graph, &self.node_kinds(), change).map_err(TopologyError::Unsafe)
+    }
+
+    fn refused(&mut self, error: TopologyError) -> TopologyError {
+        warn!(error = %error, "topology change refused");
+        self.log.push(format!("topology change refused: {}", error));
+        error
+    }
+
+    /// Changes the timing of the link from drone `id` to its neighbour, the other direction isn't touched.
+    pub fn set_link(&mut self, id: NodeId, neighbour: NodeId, params: LinkParams) -> Result<(), TopologyError>{
+        if let Err(error) = self.check_link(id, neighbour, true) {
+            return Err(self.refused(error));
+        }
+        self.send_skylink(id, SkyLinkCommand::SetLink(neighbour, params))?;
+        info!(drone = id, neighbour, params = ?params, "link set");
+        self.log.push(format!("link from drone {} to {} now has {:?}", id, neighbour, params));
+        Ok(())
+    }
+
+    /// Changes the pdr of the link from drone `id` to its neighbour, without touching the drone's other links.
+    /// With None, the link goes back to the pdr of the drone.
+    pub fn set_link_pdr(&mut self, id: NodeId, neighbour: NodeId, pdr: Option<f32>) -> Result<(), TopologyError>{
+        if let Err(error) = pdr.map_or(Ok(()), check_pdr).and_then(|()| self.check_link(id, neighbour, true)) {
+            return Err(self.refused(error));
+        }
+        self.send_skylink(id, SkyLinkCommand::SetLinkPdr(neighbour, pdr))?;
+        info!(drone = id, neighbour, pdr = ?pdr, "link pdr set");
+        self.log.push(format!("link from drone {} to {} now has pdr set to {:?}", id, neighbour, pdr));
+        Ok(())
+    }
+
+    //Gives the drone one of the commands that only the SkyLink drones take.
+    fn send_skylink(&mut self, id: NodeId, command: SkyLinkCommand) -> Result<(), TopologyError> {
+        if let Err(error) = self.running_drone(id) {
+            return Err(self.refused(error));
+        }
+        let sent = self.skylink_send.get(&id).is_some_and(|sender| sender.send(command).is_ok());
+        if !sent {
+            return Err(self.refused(TopologyError::Unreachable(id)));
+        }
+        Ok(())
+    }
+
+    /// Gathers the traffic statistics of every drone still running.
+    pub fn collect_stats(&mut self) -> HashMap<NodeId, DroneStats>{
+        let stats = collect_stats(&self.skylink_send, Duration::from_millis(500));
+        info!(answered = stats.len(), drones = self.skylink_send.len(), "statistics collected");
+        self.log.push(format!("collected the statistics of {} drones out of {}", stats.len(), self.skylink_send.len()));
+        stats
+    }
+
+    /// Takes a snapshot of every drone still running, to resume the simulation later with `initializer::resume`.
+    /// The packets travelling between the nodes aren't saved, so it's better to take it while the network is quiet.
+    /// The drones the registry gives as running are asked again if they don't answer, then the checkpoint is refused.
+    pub fn checkpoint(&mut self) -> Result<Checkpoint, CheckpointError>{
+        let mut drones = HashMap::new();
+        for _attempt in 0..CHECKPOINT_ATTEMPTS {
+            //A drone that stopped by itself (e.g. with an empty battery) isn't waited for once its events are handled.
+            self.handle_pending_events();
+            let waiting = self.unanswered(&drones);
+            if waiting.is_empty() {
+                break;
             }
+            let senders = waiting.iter().filter_map(|id| self.skylink_send.get(id).map(|send| (*id, send.clone()))).collect();
+            drones.extend(collect_snapshots(&senders, SNAPSHOT_TIMEOUT));
         }
+        self.handle_pending_events();
+        let silent = self.unanswered(&drones);
+        if !silent.is_empty() {
+            warn!(drones = ?silent, "checkpoint refused");
+            self.log.push(format!("checkpoint refused, drones {:?} didn't answer", silent));
+            return Err(CheckpointError::NoAnswer(silent));
+        }
+
+        //The drones without a snapshot have stopped, they're left out of the network.
+        let stopped = self.skylink_send.keys().filter(|id| !drones.contains_key(id)).copied().collect::<Vec<NodeId>>();
+        let mut network_graph = self.network_graph.clone();
+        for id in stopped.iter() {
+            network_graph.remove(id);
+        }
+        for neighbours in network_graph.values_mut() {
+            neighbours.retain(|id| !stopped.contains(id));
+        }
+        let node_kinds = self.nodes.iter()
+            .filter(|(id, _node)| network_graph.contains_key(id))
+            .map(|(id, node)| (*id, node.kind))
+            .collect();
+        info!(drones = drones.len(), stopped = stopped.len(), "checkpoint taken");
+        self.log.push(format!("checkpoint of {} drones taken", drones.len()));
+        Ok(Checkpoint {
+            seed: self.seed,
+            network_graph,
+            node_kinds,
+            drones,
+        })
+    }
+
+    //The drones that should answer a checkpoint and didn't yet, sorted.
+    fn unanswered(&self, drones: &HashMap<NodeId, DroneSnapshot>) -> Vec<NodeId> {
+        let mut ids = self.skylink_send.keys()
+            .filter(|id| self.running_drone(**id).is_ok() && !drones.contains_key(id))
+            .copied()
+            .collect::<Vec<NodeId>>();
+        ids.sort();
+        ids
+    }
+
+    /// Limits the floodings forwarded by drone `id` to path_traces of `max_path_trace` nodes, None removes the limit.
+    pub fn set_flood_limit(&mut self, id: NodeId, max_path_trace: Option<usize>) -> Result<(), TopologyError>{
+        self.send_skylink(id, SkyLinkCommand::SetFloodLimit(max_path_trace))?;
+        info!(drone = id, max_path_trace = ?max_path_trace, "flood limit set");
+        self.log.push(format!("drone {} now has flood limit {:?}", id, max_path_trace));
+        Ok(())
+    }
+
+    /// Makes drone `id` inject the faults of the profile in the packets it sends, None makes it behave again.
+    pub fn set_fault_profile(&mut self, id: NodeId, faults: Option<FaultProfile>) -> Result<(), TopologyError>{
+        self.send_skylink(id, SkyLinkCommand::SetFaultProfile(faults))?;
+        info!(drone = id, faults = ?faults, "faults set");
+        self.log.push(format!("drone {} now has faults {:?}", id, faults));
+        Ok(())
     }
 
 }
 
+/// Thread that handles the events of the drones as soon as they arrive, for a Simulation Controller shared with the GUI.
+/// It waits for the events without holding the lock, and takes it only to handle each of them.
+/// It stops when it's dropped, which mustn't happen while the dropping thread holds the lock.
+pub struct EventPump {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl EventPump {
+    pub fn start(sim_contr: Arc<Mutex<SimulationControl>>) -> EventPump {
+        //A thread that panicked while holding the lock doesn't stop the events, as it doesn't stop the GUI.
+        let (node_recv, skylink_recv) = {
+            let sim_contr = sim_contr.lock().unwrap_or_else(PoisonError::into_inner);
+            (sim_contr.node_recv.clone(), sim_contr.skylink_recv.clone())
+        };
+        let (stop, stop_recv) = unbounded::<()>();
+        let handle = thread::spawn(move || {
+            loop {
+                select! {
+                    recv(stop_recv) -> _ => break,
+                    recv(node_recv) -> e => {
+                        let Ok(event) = e else { break };
+                        let mut sim_contr = sim_contr.lock().unwrap_or_else(PoisonError::into_inner);
+                        sim_contr.add_to_log(event);
+                        sim_contr.trim_log();
+                    },
+                    recv(skylink_recv) -> e => {
+                        let Ok(event) = e else { break };
+                        let mut sim_contr = sim_contr.lock().unwrap_or_else(PoisonError::into_inner);
+                        sim_contr.add_skylink_event_to_log(event);
+                        sim_contr.trim_log();
+                    },
+                }
+            }
+            debug!("event pump stopped");
+        });
+        EventPump {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for EventPump {
+    fn drop(&mut self) {
+        //Dropping the sender wakes the thread up, even if it was waiting for an event.
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use wg_2024::packet::{Packet, PacketType};
+use crate::skylink_drone::snapshot::BatterySnapshot;
+
+/// Steps, in percentage of the initial charge, at which the drone reports its battery.
+pub const BATTERY_REPORT_STEP: u32 = 10;
+
+/// Energy spent by a drone, in any unit as long as it's the same for every field.
+/// Once the battery is empty the drone starts crashing, as if it got a `DroneCommand::Crash`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BatteryModel {
+    /// Charge of the battery when the drone starts.
+    pub initial_charge: f32,
+    /// Spent for every packet sent, whatever its kind.
+    pub per_packet: f32,
+    /// Spent for every byte of payload of the fragments sent, on top of `per_packet`.
+    pub per_byte: f32,
+    /// Spent every second, even if the drone does nothing.
+    pub idle_drain: f32,
+}
+
+impl Default for BatteryModel {
+    fn default() -> Self {
+        BatteryModel {
+            initial_charge: 1000.0,
+            per_packet: 1.0,
+            per_byte: 0.0,
+            idle_drain: 0.0,
+        }
+    }
+}
+
+impl BatteryModel {
+    /// What sending the packet costs.
+    pub fn cost_of(&self, packet: &Packet) -> f32 {
+        match &packet.pack_type {
+            PacketType::MsgFragment(fragment) => self.per_packet + self.per_byte * fragment.length as f32,
+            _ => self.per_packet,
+        }
+    }
+}
+
+pub(crate) struct Battery {
+    model: BatteryModel,
+    charge: f32,
+    drained_at: Option<Instant>, //Until when the idle drain was already taken, None until the drone starts.
+    reported: u32, //Last level reported, in percentage.
+}
+
+impl Battery {
+    pub(crate) fn new(model: BatteryModel) -> Self {
+        Battery {
+            model,
+            charge: model.initial_charge.max(0.0),
+            drained_at: None,
+            reported: 100,
+        }
+    }
+
+    /// A battery with the charge of the snapshot, whose idle drain starts again with the drone.
+    pub(crate) fn restore(snapshot: &BatterySnapshot) -> Self {
+        Battery {
+            model: snapshot.model,
+            charge: snapshot.charge.max(0.0),
+            drained_at: None,
+            reported: snapshot.reported,
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> BatterySnapshot {
+        BatterySnapshot {
+            model: self.model,
+            charge: self.charge,
+            reported: self.reported,
+        }
+    }
+
+    pub(crate) fn get_charge(&self) -> f32 {
+        self.charge
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.charge <= 0.0
+    }
+
+    pub(crate) fn spend_on(&mut self, packet: &Packet) {
+        self.spend(self.model.cost_of(packet));
+    }
+
+    pub(crate) fn drain_idle(&mut self, now: Instant) {
+        if let Some(drained_at) = self.drained_at {
+            self.spend(self.model.idle_drain * now.saturating_duration_since(drained_at).as_secs_f32());
+        }
+        self.drained_at = Some(now);
+    }
+
+    fn spend(&mut self, amount: f32) {
+        self.charge = (self.charge - amount.max(0.0)).max(0.0);
+    }
+
+    //Level of the charge, rounded up to a step: it's 0 only when the battery is (almost) empty.
+    fn level(&self) -> u32 {
+        if self.model.initial_charge <= 0.0 {
+            return 0;
+        }
+        let percentage = (self.charge * 100.0 / self.model.initial_charge).clamp(0.0, 100.0);
+        //A charge right on a step belongs to it, even with the rounding of the floats.
+        (((percentage / BATTERY_REPORT_STEP as f32) - 1e-4).ceil().max(0.0) as u32) * BATTERY_REPORT_STEP
+    }
+
+    /// The level to report, if the charge went below a new step since the last report.
+    pub(crate) fn new_level(&mut self) -> Option<u32> {
+        let level = self.level();
+        if level < self.reported {
+            self.reported = level;
+            Some(level)
+        } else {
+            None
+        }
+    }
+
+    /// How long the idle drain takes to bring the charge to the next step to report.
+    pub(crate) fn time_to_next_level(&self, now: Instant) -> Duration {
+        if self.model.idle_drain <= 0.0 || self.reported == 0 {
+            return Duration::MAX;
+        }
+        let next = (self.reported - BATTERY_REPORT_STEP) as f32 / 100.0 * self.model.initial_charge;
+        let elapsed = self.drained_at.map_or(0.0, |drained_at| now.saturating_duration_since(drained_at).as_secs_f32());
+        let seconds = ((self.charge - next) / self.model.idle_drain - elapsed).max(0.0);
+        Duration::try_from_secs_f32(seconds).unwrap_or(Duration::MAX)
+    }
+}
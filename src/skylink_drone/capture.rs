@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use wg_2024::controller::{DroneCommand, DroneEvent};
+use wg_2024::network::NodeId;
+use wg_2024::packet::{Packet, PacketType};
+use crate::skylink_drone::drone::{SkyLinkDrone, Step};
+use crate::skylink_drone::event::SkyLinkEvent;
+use crate::skylink_drone::command::SkyLinkCommand;
+use crate::skylink_drone::fault::FaultProfile;
+use crate::skylink_drone::link::LinkParams;
+use crate::skylink_drone::snapshot::DroneSnapshot;
+
+/// First line of a capture: what's needed to build the same drone again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureHeader {
+    /// The drone as it was when the capture started, with its whole configuration and the state of its random source.
+    pub snapshot: DroneSnapshot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+    /// Received by the drone.
+    In,
+    /// Sent by the drone.
+    Out,
+}
+
+/// A command from the controller, without the channel of `AddSender`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CapturedCommand {
+    RemoveSender(NodeId),
+    AddSender(NodeId),
+    SetPacketDropRate(f32),
+    Crash,
+    SkyLink(CapturedSkyLinkCommand),
+}
+
+impl From<&DroneCommand> for CapturedCommand {
+    fn from(command: &DroneCommand) -> Self {
+        match command {
+            DroneCommand::RemoveSender(id) => CapturedCommand::RemoveSender(*id),
+            DroneCommand::AddSender(id, _) => CapturedCommand::AddSender(*id),
+            DroneCommand::SetPacketDropRate(pdr) => CapturedCommand::SetPacketDropRate(*pdr),
+            DroneCommand::Crash => CapturedCommand::Crash,
+        }
+    }
+}
+
+/// A `SkyLinkCommand`, without the channel where the questions are answered.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CapturedSkyLinkCommand {
+    SetLink(NodeId, LinkParams),
+    SetLinkPdr(NodeId, Option<f32>),
+    SetFaultProfile(Option<FaultProfile>),
+    SetFloodLimit(Option<usize>),
+    GetStats,
+    GetSnapshot,
+}
+
+impl From<&SkyLinkCommand> for CapturedSkyLinkCommand {
+    fn from(command: &SkyLinkCommand) -> Self {
+        match command {
+            SkyLinkCommand::SetLink(neighbour, params) => CapturedSkyLinkCommand::SetLink(*neighbour, *params),
+            SkyLinkCommand::SetLinkPdr(neighbour, pdr) => CapturedSkyLinkCommand::SetLinkPdr(*neighbour, *pdr),
+            SkyLinkCommand::SetFaultProfile(faults) => CapturedSkyLinkCommand::SetFaultProfile(*faults),
+            SkyLinkCommand::SetFloodLimit(max_path_trace) => CapturedSkyLinkCommand::SetFloodLimit(*max_path_trace),
+            SkyLinkCommand::GetStats(_) => CapturedSkyLinkCommand::GetStats,
+            SkyLinkCommand::GetSnapshot(_) => CapturedSkyLinkCommand::GetSnapshot,
+        }
+    }
+}
+
+impl CapturedSkyLinkCommand {
+    //The command given again to a replayed drone, whose answers nobody reads.
+    fn replayed(&self) -> SkyLinkCommand {
+        match self {
+            CapturedSkyLinkCommand::SetLink(neighbour, params) => SkyLinkCommand::SetLink(*neighbour, *params),
+            CapturedSkyLinkCommand::SetLinkPdr(neighbour, pdr) => SkyLinkCommand::SetLinkPdr(*neighbour, *pdr),
+            CapturedSkyLinkCommand::SetFaultProfile(faults) => SkyLinkCommand::SetFaultProfile(*faults),
+            CapturedSkyLinkCommand::SetFloodLimit(max_path_trace) => SkyLinkCommand::SetFloodLimit(*max_path_trace),
+            CapturedSkyLinkCommand::GetStats => SkyLinkCommand::GetStats(unbounded().0),
+            CapturedSkyLinkCommand::GetSnapshot => SkyLinkCommand::GetSnapshot(unbounded().0),
+        }
+    }
+}
+
+/// An event for the controller.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CapturedEvent {
+    PacketSent(Packet),
+    PacketDropped(Packet),
+    ControllerShortcut(Packet),
+    SkyLink(SkyLinkEvent),
+}
+
+impl From<&DroneEvent> for CapturedEvent {
+    fn from(event: &DroneEvent) -> Self {
+        match event {
+            DroneEvent::PacketSent(packet) => CapturedEvent::PacketSent(packet.clone()),
+            DroneEvent::PacketDropped(packet) => CapturedEvent::PacketDropped(packet.clone()),
+            DroneEvent::ControllerShortcut(packet) => CapturedEvent::ControllerShortcut(packet.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Captured {
+    Packet(Packet),
+    Command(CapturedCommand),
+    Event(CapturedEvent),
+}
+
+/// Something that went in or out of the drone.
+/// Packets have the neighbour they came from (if the route tells it) or went to,
+/// commands come from the controller and events go to it, so they have none.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// Microseconds since the capture started, as measured by the clock of the drone.
+    pub time_us: u64,
+    pub direction: Direction,
+    pub neighbour: Option<NodeId>,
+    pub captured: Captured,
+}
+
+impl CaptureRecord {
+    /// Two records are the same, apart from the time they happened.
+    pub fn same_as(&self, other: &CaptureRecord) -> bool {
+        (self.direction, self.neighbour, &self.captured) == (other.direction, other.neighbour, &other.captured)
+    }
+}
+
+/// Writes what goes in and out of a drone as JSON lines: the header first, then a record per line.
+/// Every line is flushed as soon as it's written, so that the capture of a process that dies is complete up to there.
+pub(crate) struct Capturer {
+    sink: Box<dyn Write + Send>,
+    start: Option<Instant>, //Time of the drone's clock when the header was written.
+}
+
+impl Capturer {
+    pub(crate) fn new(sink: Box<dyn Write + Send>) -> Self {
+        Capturer {
+            sink,
+            start: None,
+        }
+    }
+
+    /// The header is written with the first record, once the drone is completely built.
+    pub(crate) fn started(&self) -> bool {
+        self.start.is_some()
+    }
+
+    /// `now` is the time of the drone's clock.
+    pub(crate) fn record(&mut self, header: Option<CaptureHeader>, now: Instant, direction: Direction, neighbour: Option<NodeId>, captured: Captured) {
+        let start = match self.start {
+            Some(start) => start,
+            None => {
+                let Some(header) = header else {
+                    return;
+                };
+                self.start = Some(now);
+                self.write_line(&header);
+                now
+            },
+        };
+        let record = CaptureRecord {
+            time_us: now.saturating_duration_since(start).as_micros() as u64,
+            direction,
+            neighbour,
+            captured,
+        };
+        self.write_line(&record);
+    }
+
+    fn write_line<T: Serialize>(&mut self, value: &T) {
+        //A capture that can't be written mustn't stop the drone.
+        if serde_json::to_writer(&mut self.sink, value).is_ok() {
+            let _ = self.sink.write_all(b"\n");
+            let _ = self.sink.flush();
+        }
+    }
+}
+
+/// The neighbour a packet comes from, as far as the packet can tell.
+pub(crate) fn sender_of(packet: &Packet) -> Option<NodeId> {
+    match &packet.pack_type {
+        PacketType::FloodRequest(flood_request) => flood_request.path_trace.last().map(|(id, _)| *id),
+        _ => packet.routing_header.hop_index.checked_sub(1).and_then(|index| packet.routing_header.hops.get(index).copied()),
+    }
+}
+
+/// A capture read back from a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    pub header: CaptureHeader,
+    pub records: Vec<CaptureRecord>,
+}
+
+impl Capture {
+    pub fn read_from(path: impl AsRef<Path>) -> io::Result<Capture> {
+        Capture::parse(BufReader::new(File::open(path)?))
+    }
+
+    pub fn parse(reader: impl BufRead) -> io::Result<Capture> {
+        let mut lines = reader.lines();
+        let header = match lines.next() {
+            Some(line) => serde_json::from_str(&line?).map_err(invalid_data)?,
+            None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the capture is empty")),
+        };
+        let mut records = Vec::new();
+        for line in lines {
+            let line = line?;
+            if !line.trim().is_empty() {
+                records.push(serde_json::from_str(&line).map_err(invalid_data)?);
+            }
+        }
+        Ok(Capture { header, records })
+    }
+
+    pub fn write_to(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, &self.header).map_err(invalid_data)?;
+        writer.write_all(b"\n")?;
+        for record in self.records.iter() {
+            serde_json::to_writer(&mut writer, record).map_err(invalid_data)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    }
+
+    /// What the drone sent: packets to its neighbours and events to the controller.
+    pub fn outputs(&self) -> Vec<&CaptureRecord> {
+        self.records.iter().filter(|record| record.direction == Direction::Out).collect()
+    }
+}
+
+fn invalid_data(error: serde_json::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Opens a file where a drone writes its capture, see `SkyLinkDrone::with_capture`.
+pub fn capture_file(path: impl AsRef<Path>) -> io::Result<BufWriter<File>> {
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+/// An output where the replayed drone and the captured one disagree.
+/// With None, that drone sent nothing more.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub index: usize,
+    pub expected: Option<CaptureRecord>,
+    pub actual: Option<CaptureRecord>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayReport {
+    /// Everything the replayed drone received and sent.
+    pub replayed: Capture,
+    pub differences: Vec<Difference>,
+}
+
+impl ReplayReport {
+    pub fn is_identical(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+//Keeps what the replayed drone captures in memory.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0.lock() {
+            Ok(mut buffer) => buffer.write(buf),
+            Err(_) => Err(io::Error::other("poisoned capture buffer")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Feeds what a drone received (packets and commands, in the same order) to a fresh drone,
+/// restored from the snapshot in the header of the capture and then given to `configure` (e.g. to set the same checks),
+/// and compares what the two drones sent.
+/// The replay runs on the calling thread, one input at a time: it's exact for drones that don't wait
+/// in real time, i.e. without link timing, scheduler and faults that delay packets.
+pub fn replay(capture: &Capture, configure: impl FnOnce(SkyLinkDrone) -> SkyLinkDrone) -> ReplayReport {
+    let header = &capture.header;
+    let (event_send, _event_recv) = unbounded::<DroneEvent>();
+    let (command_send, command_recv) = unbounded::<DroneCommand>();
+    let (skylink_command_send, skylink_command_recv) = unbounded::<SkyLinkCommand>();
+    let (packet_in, packet_recv) = unbounded::<Packet>();
+    //The neighbours never read, but keep listening, as the captured ones did.
+    let mut neighbours: HashMap<NodeId, Receiver<Packet>> = HashMap::new();
+    let mut packet_send: HashMap<NodeId, Sender<Packet>> = HashMap::new();
+    for id in header.snapshot.neighbours.iter() {
+        let (send, recv) = unbounded();
+        packet_send.insert(*id, send);
+        neighbours.insert(*id, recv);
+    }
+
+    let buffer = SharedBuffer::default();
+    let drone = SkyLinkDrone::restore(&header.snapshot, event_send, command_recv, packet_recv, packet_send)
+        .with_command_channel(skylink_command_recv);
+    let mut drone = configure(drone).with_capture(buffer.clone());
+    let mut crashed = false;
+
+    for record in capture.records.iter().filter(|record| record.direction == Direction::In) {
+        match &record.captured {
+            Captured::Packet(packet) => {
+                let _ = packet_in.send(packet.clone());
+            },
+            Captured::Command(command) => {
+                let command = match command {
+                    CapturedCommand::AddSender(id) => {
+                        let (send, recv) = unbounded();
+                        neighbours.insert(*id, recv);
+                        Some(DroneCommand::AddSender(*id, send))
+                    },
+                    CapturedCommand::RemoveSender(id) => Some(DroneCommand::RemoveSender(*id)),
+                    CapturedCommand::SetPacketDropRate(pdr) => Some(DroneCommand::SetPacketDropRate(*pdr)),
+                    CapturedCommand::Crash => {
+                        crashed = true;
+                        Some(DroneCommand::Crash)
+                    },
+                    //The commands only a SkyLinkDrone takes have their own channel.
+                    CapturedCommand::SkyLink(command) => {
+                        let _ = skylink_command_send.send(command.replayed());
+                        None
+                    },
+                };
+                if let Some(command) = command {
+                    let _ = command_send.send(command);
+                }
+            },
+            Captured::Event(_) => {},
+        }
+        while drone.step() == Step::Busy {}
+    }
+    if crashed {
+        //The captured drone stopped once its neighbours were gone, so does this one.
+        drop(packet_in);
+        while drone.step() == Step::Busy {}
+    }
+    drop(drone);
+
+    let bytes = buffer.0.lock().map(|buffer| buffer.clone()).unwrap_or_default();
+    let replayed = Capture::parse(&bytes[..]).unwrap_or_else(|_| Capture {
+        header: header.clone(),
+        records: Vec::new(),
+    });
+
+    let expected = capture.outputs();
+    let actual = replayed.outputs();
+    let mut differences = Vec::new();
+    for index in 0..expected.len().max(actual.len()) {
+        let (expected, actual) = (expected.get(index), actual.get(index));
+        let same = match (expected, actual) {
+            (Some(expected), Some(actual)) => expected.same_as(actual),
+            _ => false,
+        };
+        if !same {
+            differences.push(Difference {
+                index,
+                expected: expected.map(|record| (*record).clone()),
+                actual: actual.map(|record| (*record).clone()),
+            });
+        }
+    }
+    ReplayReport {
+        replayed,
+        differences,
+    }
+}
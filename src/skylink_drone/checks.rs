@@ -2,13 +2,175 @@ use wg_2024::packet::{NackType, Packet, PacketType};
 use crate::skylink_drone::error::create_error;
 use crate::skylink_drone::drone::SkyLinkDrone;
 
+/// A single step of the drone's packet pipeline.
+///
+/// A check receives the packet as left by the previous check and either passes it on
+/// (possibly modified, e.g. with the hop index advanced) or returns the packet that the
+/// drone should handle instead, usually a Nack built with `create_error`.
+pub trait PacketCheck: Send {
+    /// Name used to find the check inside a `CheckPipeline`.
+    fn name(&self) -> &str;
+
+    fn check(&mut self, drone: &mut SkyLinkDrone, packet: Packet) -> Result<Packet, Packet>;
+}
+
+/// Checks that the drone is the node the routing header points to, and moves the hop index forward.
+pub struct IdHopMatchCheck;
+/// Checks that the drone is not the last hop of the routing header.
+pub struct FinalDestinationCheck;
+/// Drops fragments according to the pdr of the link they're going through.
+pub struct PdrCheck;
+/// Checks that the next hop is one of the drone's neighbours.
+pub struct NextHopCheck;
+
+impl PacketCheck for IdHopMatchCheck {
+    fn name(&self) -> &str {
+        "id_hop_match"
+    }
+    fn check(&mut self, drone: &mut SkyLinkDrone, mut packet: Packet) -> Result<Packet, Packet> {
+        id_hop_match_check(drone, packet.clone())?;
+        packet.routing_header.hop_index += 1;
+        Ok(packet)
+    }
+}
+
+impl PacketCheck for FinalDestinationCheck {
+    fn name(&self) -> &str {
+        "final_destination"
+    }
+    fn check(&mut self, drone: &mut SkyLinkDrone, packet: Packet) -> Result<Packet, Packet> {
+        final_destination_check(drone, packet.clone())?;
+        Ok(packet)
+    }
+}
+
+impl PacketCheck for PdrCheck {
+    fn name(&self) -> &str {
+        "pdr"
+    }
+    fn check(&mut self, drone: &mut SkyLinkDrone, packet: Packet) -> Result<Packet, Packet> {
+        pdr_check(drone, packet.clone())?;
+        Ok(packet)
+    }
+}
+
+impl PacketCheck for NextHopCheck {
+    fn name(&self) -> &str {
+        "next_hop"
+    }
+    fn check(&mut self, drone: &mut SkyLinkDrone, packet: Packet) -> Result<Packet, Packet> {
+        is_next_hop_check(drone, packet.clone())?;
+        Ok(packet)
+    }
+}
+
+/// Ordered list of checks applied by the drone to every packet that isn't a FloodRequest.
+pub struct CheckPipeline {
+    checks: Vec<Box<dyn PacketCheck>>,
+}
+
+impl Default for CheckPipeline {
+    /// The standard pipeline: id_hop_match -> final_destination -> pdr -> next_hop.
+    fn default() -> Self {
+        CheckPipeline::empty()
+            .with(IdHopMatchCheck)
+            .with(FinalDestinationCheck)
+            .with(PdrCheck)
+            .with(NextHopCheck)
+    }
+}
+
+impl CheckPipeline {
+    /// A pipeline without any check, every packet is forwarded as it is.
+    pub fn empty() -> Self {
+        CheckPipeline {
+            checks: Vec::new(),
+        }
+    }
+
+    /// Adds a check at the end of the pipeline.
+    pub fn with(mut self, check: impl PacketCheck + 'static) -> Self {
+        self.checks.push(Box::new(check));
+        self
+    }
+
+    /// Adds a check right before the one called `name`, or at the end if there's no such check.
+    pub fn with_before(mut self, name: &str, check: impl PacketCheck + 'static) -> Self {
+        let index = self.position(name).unwrap_or(self.checks.len());
+        self.checks.insert(index, Box::new(check));
+        self
+    }
+
+    /// Adds a check right after the one called `name`, or at the end if there's no such check.
+    pub fn with_after(mut self, name: &str, check: impl PacketCheck + 'static) -> Self {
+        let index = self.position(name).map(|i| i + 1).unwrap_or(self.checks.len());
+        self.checks.insert(index, Box::new(check));
+        self
+    }
+
+    /// Removes the check called `name`, if present.
+    pub fn without(mut self, name: &str) -> Self {
+        self.remove(name);
+        self
+    }
+
+    /// Moves the check called `name` right before the check called `before`.
+    pub fn move_before(mut self, name: &str, before: &str) -> Self {
+        if let Some(check) = self.remove(name) {
+            let index = self.position(before).unwrap_or(self.checks.len());
+            self.checks.insert(index, check);
+        }
+        self
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn PacketCheck>> {
+        self.position(name).map(|index| self.checks.remove(index))
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.checks.iter().map(|check| check.name()).collect()
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.checks.iter().position(|check| check.name() == name)
+    }
+
+    pub(crate) fn run(&mut self, drone: &mut SkyLinkDrone, mut packet: Packet) -> Result<Packet, Packet> {
+        for check in self.checks.iter_mut() {
+            match check.check(drone, packet) {
+                Ok(passed) => {
+                    tracing::trace!(check = check.name(), decision = "pass");
+                    packet = passed;
+                },
+                Err(error) => {
+                    match &error.pack_type {
+                        PacketType::Nack(nack) => tracing::debug!(check = check.name(), decision = "nack", nack_type = ?nack.nack_type),
+                        _ => tracing::debug!(check = check.name(), decision = "reject"),
+                    }
+                    return Err(error);
+                },
+            }
+        }
+        Ok(packet)
+    }
+}
+
+
 pub fn id_hop_match_check(drone: &SkyLinkDrone, packet: Packet) -> Result<(), Packet> {
-    if packet.routing_header.hops[packet.routing_header.hop_index] == drone.get_id() {
+    let hop_index = packet.routing_header.hop_index;
+    if packet.routing_header.hops.get(hop_index) == Some(&drone.get_id()) {
         Ok(())
     } else {
         match packet.pack_type.clone() {
             PacketType::MsgFragment(_fragment) => {
-                Err(create_error(packet.routing_header.hops[packet.routing_header.hop_index-1], packet, NackType::UnexpectedRecipient(drone.get_id())))
+                //The nack goes back to the node before me, if the header tells which one it is.
+                let previous = hop_index.checked_sub(1)
+                    .and_then(|index| packet.routing_header.hops.get(index))
+                    .copied();
+                match previous {
+                    Some(previous) => Err(create_error(previous, packet.clone(), NackType::UnexpectedRecipient(drone.get_id())).unwrap_or(packet)),
+                    None => Err(packet),
+                }
             },
             _ => {
                 Err(packet)
@@ -21,7 +183,7 @@ pub fn final_destination_check(drone: &SkyLinkDrone, packet: Packet) -> Result<(
     } else {
         match packet.pack_type.clone() {
             PacketType::MsgFragment(_fragment) => {
-                Err(create_error(drone.get_id(), packet, NackType::DestinationIsDrone))
+                Err(create_error(drone.get_id(), packet.clone(), NackType::DestinationIsDrone).unwrap_or(packet))
             },
             _ => {
                 Err(packet)
@@ -30,13 +192,13 @@ pub fn final_destination_check(drone: &SkyLinkDrone, packet: Packet) -> Result<(
     }
 }
 pub fn is_next_hop_check(drone: &SkyLinkDrone, packet: Packet) -> Result<(), Packet> {
-    let next_hop = &packet.routing_header.hops[packet.routing_header.hop_index];
-    if drone.get_packet_send().contains_key(next_hop) {
+    let next_hop = packet.routing_header.hops.get(packet.routing_header.hop_index);
+    if next_hop.is_some_and(|next_hop| drone.has_neighbour(*next_hop)) {
         Ok(())
     } else {
         match packet.pack_type.clone() {
             PacketType::MsgFragment(_fragment) => {
-                Err(create_error(drone.get_id(), packet, NackType::ErrorInRouting(drone.get_id())))
+                Err(create_error(drone.get_id(), packet.clone(), NackType::ErrorInRouting(drone.get_id())).unwrap_or(packet))
             },
             _ => {
                 Err(packet)
@@ -44,12 +206,17 @@ pub fn is_next_hop_check(drone: &SkyLinkDrone, packet: Packet) -> Result<(), Pac
         }
     }
 }
-pub fn pdr_check(drone: &SkyLinkDrone, packet: Packet) -> Result<(), Packet> {
+pub fn pdr_check(drone: &mut SkyLinkDrone, packet: Packet) -> Result<(), Packet> {
     if let PacketType::MsgFragment(_) = packet.pack_type.clone() {
-        let random_number: u32 = fastrand::u32(0..101);
-        if random_number < drone.get_pdr() {
-            return Err(create_error(drone.get_id(), packet, NackType::Dropped))
+        //The pdr is the one of the link the fragment is going through.
+        let pdr = match packet.routing_header.hops.get(packet.routing_header.hop_index) {
+            Some(next_hop) => drone.get_link_pdr(*next_hop),
+            None => drone.get_pdr(),
+        };
+        let random_number: u32 = drone.get_rng().u32(0..101);
+        if random_number < pdr {
+            return Err(create_error(drone.get_id(), packet.clone(), NackType::Dropped).unwrap_or(packet))
         }
     }
     Ok(())
-}
+}
\ No newline at end of file
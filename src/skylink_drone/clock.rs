@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where a drone reads the time: the delays of its links, its crash deadline, the age of the floodings
+/// it remembers and the idle drain of its battery all follow this clock.
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+
+    /// Waits for `duration`, as measured by this clock.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The clock of the system, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that moves only when it's told to, for drones driven by hand with `step` and `handle_packet`.
+/// Every clone shows the same time, so a test keeps one to move the time of the drone it gave the other to.
+/// Sleeping on it moves it forward right away.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    start: Instant,
+    elapsed_nanos: Arc<AtomicU64>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock {
+            start: Instant::now(),
+            elapsed_nanos: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        //Saturating, so that sleeping for Duration::MAX doesn't bring the clock back.
+        let _ = self.elapsed_nanos.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |elapsed| Some(elapsed.saturating_add(nanos)));
+    }
+
+    /// Time passed since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_nanos.load(Ordering::SeqCst))
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
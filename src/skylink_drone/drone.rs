@@ -1,23 +1,59 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
 use wg_2024::network::{NodeId, SourceRoutingHeader};
-use crossbeam_channel::{select_biased, Receiver, Sender};
+use crossbeam_channel::{never, select_biased, unbounded, Receiver, Select, Sender};
+use tracing::{debug, debug_span, info, trace, warn};
 use wg_2024::controller::{DroneCommand, DroneEvent};
 use wg_2024::controller::DroneEvent::ControllerShortcut;
 use wg_2024::drone::Drone;
 use wg_2024::packet::{Packet, PacketType, FloodResponse, NodeType, FloodRequest, NackType};
 use crate::skylink_drone::error::create_error;
-use crate::skylink_drone::checks::{id_hop_match_check, final_destination_check, pdr_check, is_next_hop_check};
+use crate::skylink_drone::checks::*;
+use crate::skylink_drone::event::{DiscardReason, SkyLinkEvent};
+use crate::skylink_drone::flood_cache::FloodCache;
+use crate::skylink_drone::command::SkyLinkCommand;
+use crate::skylink_drone::link::{LinkParams, Links};
+use crate::skylink_drone::scheduler::PriorityScheduler;
+use crate::skylink_drone::fault::{FaultKind, FaultProfile};
+use crate::skylink_drone::stats::{DroneStats, PacketKind};
+use crate::skylink_drone::battery::{Battery, BatteryModel};
+use crate::skylink_drone::snapshot::{DroneSnapshot, FloodCacheSnapshot};
+use crate::skylink_drone::clock::{Clock, SystemClock};
+use crate::skylink_drone::random::RandomSource;
+use crate::skylink_drone::transport::{ChannelTransport, Transport};
+use crate::skylink_drone::capture::{sender_of, CaptureHeader, Captured, CapturedCommand, CapturedEvent, CapturedSkyLinkCommand, Capturer, Direction};
 
+/// How long a crashing drone of a simulation waits for its neighbours to drop their channels, before leaving anyway,
+/// if the options don't say otherwise. A drone alone has no deadline, see `SkyLinkDrone::with_crash_deadline`.
+pub const DEFAULT_CRASH_DEADLINE: Duration = Duration::from_secs(2);
 
 pub struct SkyLinkDrone {
     id: NodeId,
-    controller_send: Sender<DroneEvent>,
     controller_recv: Receiver<DroneCommand>,
     packet_recv: Receiver<Packet>,
-    packet_send: HashMap<NodeId, Sender<Packet>>,
+    packets_closed: bool, //Every neighbour dropped its channel to me before the crash, so packet_recv was swapped for never().
+    transport: Box<dyn Transport>, //Where the packets for the neighbours and the events for the controller go.
     pdr: u32,
-    flood_ids: HashSet<(u64, NodeId)>, //Tuple with the flood_id and the id of the initiator, to distinguish uniquely every flooding.
+    link_pdr: HashMap<NodeId, u32>, //Pdr of single links, the others use the drone's one.
+    flood_ids: FloodCache, //Keeps the flood_id and the id of the initiator, to distinguish uniquely every flooding.
     crashing: bool,
+    checks: CheckPipeline,
+    seed: u64,
+    rng: Box<dyn RandomSource>, //Every drone has its own random source, so that a run can be replayed from its seed.
+    clock: Box<dyn Clock>,
+    event_send: Option<Sender<SkyLinkEvent>>,
+    command_recv: Receiver<SkyLinkCommand>,
+    links: Links,
+    scheduler: Option<PriorityScheduler>,
+    faults: Option<FaultProfile>, //Misbehaviours injected in the packets sent, used to test the other nodes.
+    stats: DroneStats,
+    crash_deadline: Option<Duration>,
+    shutdown_at: Option<Instant>, //Set when the drone starts crashing, if it has a deadline.
+    capture: Option<Capturer>,
+    flood_limit: Option<usize>, //Longest path_trace a FloodRequest can have when it leaves the drone.
+    battery: Option<Battery>,
+    stopping: Option<bool>, //Set when the crashing drone stops listening (to whether it was forced), it only empties its links.
 }
 
 impl Drone for SkyLinkDrone {
@@ -27,134 +63,603 @@ impl Drone for SkyLinkDrone {
            packet_recv: Receiver<Packet>,
            packet_send: HashMap<NodeId, Sender<Packet>>,
            pdr: f32) -> Self {
-        let mut pdr = pdr;
-        if pdr > 1.00 {
-            pdr = 1.00;
-        }
-        if pdr < 0.00 {
-            pdr = 0.00;
-        }
+        let seed = fastrand::u64(..);
         SkyLinkDrone {
             id,
-            controller_send,
             controller_recv,
             packet_recv,
-            packet_send,
-            pdr: (pdr*100.0) as u32,
-            flood_ids: HashSet::new(),
+            packets_closed: false,
+            transport: Box::new(ChannelTransport::new(packet_send, controller_send)),
+            pdr: pdr_percentage(pdr),
+            link_pdr: HashMap::new(),
+            flood_ids: FloodCache::default(),
             crashing: false,
+            checks: CheckPipeline::default(),
+            seed,
+            rng: Box::new(fastrand::Rng::with_seed(seed)),
+            clock: Box::new(SystemClock),
+            event_send: None,
+            command_recv: never(),
+            links: Links::default(),
+            scheduler: None,
+            faults: None,
+            stats: DroneStats::new(id),
+            crash_deadline: None,
+            shutdown_at: None,
+            capture: None,
+            flood_limit: None,
+            battery: None,
+            stopping: None,
         }
     }
 
     fn run(&mut self) {
-        loop {
-            if !self.crashing {
-                select_biased! {
-                    recv(self.controller_recv) -> cmd => {
-                        if let Ok(command) = cmd {
-                            self.handle_command(command);
-                        }
+        while self.iterate(Duration::MAX) != Step::Stopped {}
+    }
+}
+
+/// What a drone did in a call to `SkyLinkDrone::step`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// It handled something, and may have more to do right away.
+    Busy,
+    /// It has nothing to do until something arrives on its channels,
+    /// or for at most the given time (Duration::MAX if nothing is planned).
+    Idle(Duration),
+    /// It crashed and stopped, it mustn't be stepped anymore.
+    Stopped,
+}
+
+impl SkyLinkDrone {
+    /// Does the work that's ready now without ever blocking, so that the caller decides when to come back:
+    /// the drone does the same things it would do in `run`, one at a time.
+    pub fn step(&mut self) -> Step {
+        self.iterate(Duration::ZERO)
+    }
+
+    //One turn of the loop of the drone: it waits up to `timeout` for something to handle.
+    fn iterate(&mut self, timeout: Duration) -> Step {
+        self.check_battery();
+        if let Some(forced) = self.stopping {
+            return self.empty_links(forced, timeout);
+        }
+        let mut received = true;
+        if !self.crashing {
+            select_biased! {
+                recv(self.controller_recv) -> cmd => {
+                    match cmd {
+                        Ok(command) => self.handle_command(command),
+                        //The controller is gone, its channel mustn't keep waking me up.
+                        Err(_error) => self.controller_recv = never(),
                     }
-                    recv(self.packet_recv) -> pkt => {
-                        if let Ok(packet) = pkt {
-                            self.handle_packet(packet);
-                        }
+                }
+                recv(self.command_recv) -> cmd => {
+                    self.handle_skylink_command(cmd.ok());
+                }
+                recv(self.packet_recv) -> pkt => {
+                    match pkt {
+                        Ok(packet) => self.receive(packet),
+                        //Nobody can send me packets anymore, the channel mustn't keep waking me up.
+                        Err(_error) => {
+                            self.packet_recv = never();
+                            self.packets_closed = true;
+                            received = false;
+                        },
                     }
                 }
-            } else {
-                select_biased! {
-                    recv(self.controller_recv) -> cmd => {
-                        // If I'm in crushing behavior, I still listen for RemoveSender command,
-                        // to avoid neighbour drones not crushing because of each other existence.
-                        if let Ok(command) = cmd {
+                default(self.time_to_wait().min(timeout)) => {
+                    received = false;
+                }
+            }
+        } else {
+            if self.packets_closed {
+                //Every neighbour had already dropped its channel when I crashed.
+                return self.shut_down(false, timeout);
+            }
+            select_biased! {
+                recv(self.controller_recv) -> cmd => {
+                    // If I'm in crushing behavior, I still listen for RemoveSender command,
+                    // to avoid neighbour drones not crushing because of each other existence.
+                    match cmd {
+                        Ok(command) => {
+                            self.capture(Direction::In, None, || Captured::Command(CapturedCommand::from(&command)));
                             if let DroneCommand::RemoveSender(node_id) = command {
-                                if self.packet_send.contains_key(&node_id) {
-                                    if let Some(to_be_dropped) = self.packet_send.remove(&node_id) {
-                                        drop(to_be_dropped);
-                                    }
-                                }
+                                self.transport.remove_neighbour(node_id);
                             }
-                        }
+                        },
+                        //The controller dropped its channel after the crash, what's left to wait for are the neighbours.
+                        Err(_error) => self.controller_recv = never(),
                     }
-                    recv(self.packet_recv) -> pkt => {
-                        match pkt {
-                            Ok(packet) => {
-                                self.crashing_handle_packet(packet);
-                            },
-                            Err(_error) => {
-                                break;
-                            }
+                }
+                recv(self.command_recv) -> cmd => {
+                    self.handle_skylink_command(cmd.ok());
+                }
+                recv(self.packet_recv) -> pkt => {
+                    match pkt {
+                        Ok(packet) => {
+                            self.receive(packet);
+                        },
+                        Err(_error) => {
+                            //Every neighbour dropped its channel.
+                            return self.shut_down(false, timeout);
                         }
                     }
                 }
+                default(self.time_to_wait().min(timeout)) => {
+                    received = false;
+                }
+            }
+            if self.time_to_shutdown().is_zero() {
+                //Some neighbour is still holding a channel to me, but I can't wait any longer.
+                return self.shut_down(true, timeout);
             }
         }
+        self.handle_scheduled();
+        self.deliver_arrived();
+
+        let wait = self.time_to_wait();
+        if received || wait.is_zero() {
+            Step::Busy
+        } else {
+            Step::Idle(wait)
+        }
+    }
+
+    /// Registers in the selection the channels the drone listens to, so that whoever steps it knows when it has work.
+    /// Every drone uses `CHANNELS` operations, in the order they're registered.
+    pub(crate) fn register<'a>(&'a self, select: &mut Select<'a>) {
+        select.recv(&self.controller_recv);
+        select.recv(&self.command_recv);
+        select.recv(&self.packet_recv);
+    }
+    pub(crate) const CHANNELS: usize = 3;
+
+    /// A drone without channels: the caller gives it the packets (e.g. with `handle_packet`) and drives it with `step`,
+    /// and whatever it sends goes to the transport, e.g. a `RecordingTransport`.
+    /// Together with a `ManualClock` and a `FixedRandom`, a test can check what it does without threads or sleeps.
+    pub fn detached(id: NodeId, transport: impl Transport + 'static, pdr: f32) -> Self {
+        let (controller_send, _controller_recv) = unbounded();
+        SkyLinkDrone::new(id, controller_send, never(), never(), HashMap::new(), pdr).with_transport(transport)
+    }
+
+    /// Replaces the checks applied to every packet (the default pipeline is `CheckPipeline::default()`).
+    pub fn with_checks(mut self, checks: CheckPipeline) -> Self {
+        self.checks = checks;
+        self
+    }
+
+    /// Makes the drone draw its random numbers (e.g. the drops) from the given seed.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self.rng = Box::new(fastrand::Rng::with_seed(seed));
+        self
+    }
+
+    /// Makes the drone draw its random numbers from the given source, instead of its seed.
+    pub fn with_random_source(mut self, rng: impl RandomSource + 'static) -> Self {
+        self.rng = Box::new(rng);
+        self
+    }
+
+    /// Makes the drone read the time from the given clock, e.g. a `ManualClock` moved by a test.
+    /// The drone still waits on its channels for real time, so a manual clock is for drones driven with `step`.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Makes the drone send its packets and events through the transport, instead of the channels given to `new`.
+    pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Box::new(transport);
+        self
+    }
+
+    /// Replaces the cache used to recognise the floodings already met.
+    pub fn with_flood_cache(mut self, flood_ids: FloodCache) -> Self {
+        self.flood_ids = flood_ids;
+        self
+    }
+
+    /// Gives the drone a channel where it receives the `SkyLinkCommand`s.
+    pub fn with_command_channel(mut self, command_recv: Receiver<SkyLinkCommand>) -> Self {
+        self.command_recv = command_recv;
+        self
+    }
+
+    /// Sets the timing of the links towards the given neighbours.
+    pub fn with_links(mut self, links: HashMap<NodeId, LinkParams>) -> Self {
+        for (neighbour, params) in links {
+            self.links.set(neighbour, params);
+        }
+        self
+    }
+
+    /// Sets a pdr for the links towards the given neighbours, instead of the drone's one.
+    pub fn with_link_pdrs(mut self, link_pdr: HashMap<NodeId, f32>) -> Self {
+        for (neighbour, pdr) in link_pdr {
+            self.link_pdr.insert(neighbour, pdr_percentage(pdr));
+        }
+        self
+    }
+
+    /// Makes the drone queue the packets it receives, to handle control packets before fragments.
+    pub fn with_scheduler(mut self, scheduler: PriorityScheduler) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Makes the drone misbehave when sending packets, as described by the profile.
+    pub fn with_fault_profile(mut self, faults: FaultProfile) -> Self {
+        self.set_fault_profile(Some(faults));
+        self
+    }
+
+    /// Sets how long the drone keeps working after a crash, waiting for its neighbours to drop their channels.
+    /// With None (the default) it waits as long as it takes, as the wg_2024 protocol says.
+    pub fn with_crash_deadline(mut self, crash_deadline: Option<Duration>) -> Self {
+        self.crash_deadline = crash_deadline;
+        self
+    }
+
+    /// Limits how far a flooding goes: once the path_trace of a FloodRequest (the drone included) has
+    /// `max_path_trace` nodes, the drone answers with a FloodResponse instead of forwarding it.
+    /// With None the flooding is only stopped by the nodes that already met it.
+    pub fn with_flood_limit(mut self, max_path_trace: Option<usize>) -> Self {
+        self.flood_limit = max_path_trace;
+        self
+    }
+
+    /// Gives the drone a battery, spent by every packet it sends and while it waits.
+    /// When it's empty, the drone starts crashing by itself.
+    pub fn with_battery(mut self, model: BatteryModel) -> Self {
+        let battery = Battery::new(model);
+        self.stats.battery = Some(battery.get_charge());
+        self.battery = Some(battery);
+        self
+    }
+
+    /// Makes the drone write every packet and command it receives, and everything it sends, to the sink
+    /// (e.g. a `capture_file`), to be read back with `Capture` and replayed with `replay`.
+    /// The capture starts with a snapshot of the drone, taken when the first record is written.
+    pub fn with_capture<W: Write + Send + 'static>(mut self, sink: W) -> Self {
+        self.capture = Some(Capturer::new(Box::new(sink)));
+        self
+    }
+
+    /// Gives the drone a channel where it reports the `SkyLinkEvent`s.
+    pub fn with_event_channel(mut self, event_send: Sender<SkyLinkEvent>) -> Self {
+        self.event_send = Some(event_send);
+        self
+    }
+
+    /// Takes the state of the drone, to rebuild it later (or more than once) with `restore`.
+    pub fn snapshot(&self) -> DroneSnapshot {
+        let now = self.clock.now();
+        DroneSnapshot {
+            id: self.id,
+            pdr: self.pdr,
+            link_pdr: self.link_pdr.clone(),
+            crashing: self.crashing,
+            shutdown_in: self.shutdown_at.map(|_| self.time_to_shutdown()),
+            crash_deadline: self.crash_deadline,
+            neighbours: self.transport.neighbours(),
+            flood_ids: FloodCacheSnapshot::of(&self.flood_ids),
+            seed: self.seed,
+            rng_state: self.rng.state(),
+            links: self.links.params(),
+            in_flight: self.links.in_flight(now),
+            scheduler: self.scheduler.clone(),
+            faults: self.faults,
+            flood_limit: self.flood_limit,
+            battery: self.battery.as_ref().map(Battery::snapshot),
+            stats: self.stats.clone(),
+        }
+    }
+
+    /// Rebuilds a drone from its snapshot, with new channels (as in `new`): it goes on from where the snapshot was taken.
+    /// `packet_send` should have a channel for every neighbour of the snapshot, the missing ones can't be reached anymore.
+    pub fn restore(snapshot: &DroneSnapshot,
+                   controller_send: Sender<DroneEvent>,
+                   controller_recv: Receiver<DroneCommand>,
+                   packet_recv: Receiver<Packet>,
+                   packet_send: HashMap<NodeId, Sender<Packet>>) -> Self {
+        let mut drone = SkyLinkDrone::new(snapshot.id, controller_send, controller_recv, packet_recv, packet_send, 0.0)
+            .with_seed(snapshot.seed)
+            .with_links(snapshot.links.clone())
+            .with_crash_deadline(snapshot.crash_deadline)
+            .with_flood_limit(snapshot.flood_limit);
+        drone.flood_ids = snapshot.flood_ids.restore(drone.clock.now());
+        drone.pdr = snapshot.pdr.min(100);
+        drone.link_pdr = snapshot.link_pdr.clone();
+        if let Some(state) = snapshot.rng_state {
+            drone.rng = Box::new(fastrand::Rng::with_seed(state));
+        }
+        let now = drone.clock.now();
+        drone.crashing = snapshot.crashing;
+        drone.shutdown_at = snapshot.shutdown_in.and_then(|shutdown_in| now.checked_add(shutdown_in));
+        drone.scheduler = snapshot.scheduler.clone();
+        drone.set_fault_profile(snapshot.faults);
+        drone.battery = snapshot.battery.as_ref().map(Battery::restore);
+        drone.stats = snapshot.stats.clone();
+        //The packets on the links go back last, since the ones that can't are handled by the restored drone.
+        for (neighbour, remaining, packet) in snapshot.in_flight.iter() {
+            if let Err(packet) = drone.links.push_remaining(*neighbour, packet.clone(), *remaining, now) {
+                drone.undeliverable(*neighbour, packet);
+            }
+        }
+        drone
     }
-}
 
-impl SkyLinkDrone {
     fn handle_command(&mut self, command: DroneCommand) {
+        self.capture(Direction::In, None, || Captured::Command(CapturedCommand::from(&command)));
         match command {
             DroneCommand::AddSender(node_id, sender) => {
-                self.packet_send.insert(node_id, sender);
-                //println!("Drone {} added a channel to {}!", self.id, node_id);
+                self.transport.add_neighbour(node_id, sender);
+                info!(drone = self.id, neighbour = node_id, "added a channel");
             },
             DroneCommand::SetPacketDropRate(pdr) => {
-                let mut pdr = pdr;
-                if pdr > 1.00 {
-                    pdr = 1.00;
-                }
-                if pdr < 0.00 {
-                    pdr = 0.00;
-                }
-                self.pdr = (pdr * 100.0) as u32;
-                //println!("Drone {} new pdr: {}%!", self.id, self.pdr);
+                self.pdr = pdr_percentage(pdr);
+                info!(drone = self.id, pdr = self.pdr, "new pdr");
             },
             DroneCommand::Crash => {
-                self.crashing = true;
-                //println!("Drone {} crashed!", self.id);
+                self.start_crashing();
             },
             DroneCommand::RemoveSender(node_id) => {
-                if self.packet_send.contains_key(&node_id) {
-                    if let Some(to_be_dropped) = self.packet_send.remove(&node_id) {
-                        drop(to_be_dropped);
-                        //println!("Drone {} no more has a connection to {}!", self.id, node_id);
+                if self.transport.remove_neighbour(node_id) {
+                    info!(drone = self.id, neighbour = node_id, "removed a channel");
+                }
+            }
+        }
+    }
+
+    fn receive(&mut self, packet: Packet) {
+        self.capture(Direction::In, sender_of(&packet), || Captured::Packet(packet.clone()));
+        match self.scheduler.as_mut() {
+            Some(_) => {
+                //I take everything that's waiting, so that control packets can overtake the fragments.
+                //Only what's there now, a neighbour that never stops sending can't keep me here.
+                let waiting = self.packet_recv.try_iter().take(self.packet_recv.len()).collect::<Vec<Packet>>();
+                for packet in waiting.iter() {
+                    self.capture(Direction::In, sender_of(packet), || Captured::Packet(packet.clone()));
+                }
+                if let Some(scheduler) = self.scheduler.as_mut() {
+                    scheduler.push(packet);
+                    for packet in waiting {
+                        scheduler.push(packet);
+                    }
+                }
+            },
+            None => self.dispatch(packet),
+        }
+    }
+
+    fn handle_scheduled(&mut self) {
+        if let Some(packet) = self.scheduler.as_mut().and_then(|scheduler| scheduler.pop()) {
+            self.dispatch(packet);
+        }
+    }
+
+    fn dispatch(&mut self, packet: Packet) {
+        if self.crashing {
+            self.crashing_handle_packet(packet);
+        } else {
+            self.handle_packet(packet);
+        }
+    }
+
+    //How long a crashing drone can still wait for its neighbours, Duration::MAX if there's no deadline.
+    fn time_to_shutdown(&self) -> Duration {
+        match self.shutdown_at {
+            Some(shutdown_at) => shutdown_at.saturating_duration_since(self.clock.now()),
+            None => Duration::MAX,
+        }
+    }
+
+    /// Last steps of a crashing drone before leaving the run loop: the packets already taken from the channel
+    /// are handled, the ones on a link are delivered, then the controller is told that the drone is gone.
+    /// If `forced`, some neighbour still had a channel to the drone when the deadline expired:
+    /// what's already in the channel is handled too, whatever arrives afterwards is never read.
+    /// The drone waits for its links up to `timeout`, after that it's `Step::Idle` until they're empty.
+    fn shut_down(&mut self, forced: bool, timeout: Duration) -> Step {
+        while let Some(packet) = self.scheduler.as_mut().and_then(|scheduler| scheduler.pop()) {
+            self.crashing_handle_packet(packet);
+        }
+        if forced {
+            //Acks, nacks and flood responses still have to get through, and the fragments get their nack.
+            let waiting = self.packet_recv.try_iter().take(self.packet_recv.len()).collect::<Vec<Packet>>();
+            for packet in waiting {
+                self.capture(Direction::In, sender_of(&packet), || Captured::Packet(packet.clone()));
+                self.crashing_handle_packet(packet);
+            }
+        }
+        //I don't listen anymore, so that whoever steps me isn't woken up by my channels while the links empty.
+        self.controller_recv = never();
+        self.command_recv = never();
+        self.packet_recv = never();
+        self.stopping = Some(forced);
+        self.empty_links(forced, timeout)
+    }
+
+    //Delivers what's left on the links of a drone that stopped listening, waiting for them up to `timeout`.
+    fn empty_links(&mut self, forced: bool, mut timeout: Duration) -> Step {
+        loop {
+            self.deliver_arrived();
+            if self.links.is_empty() {
+                self.stopped(forced);
+                return Step::Stopped;
+            }
+            let wait = self.links.time_to_next(self.clock.now());
+            if wait > self.time_to_shutdown() {
+                //There's no time left to wait for the links, what's on them is delivered now.
+                for (neighbour, packet) in self.links.drain() {
+                    if let Err(packet) = self.hand_over(neighbour, packet) {
+                        self.undeliverable(neighbour, packet);
                     }
                 }
+            } else if wait > timeout {
+                return Step::Idle(wait);
+            } else {
+                self.clock.sleep(wait);
+                timeout = timeout.saturating_sub(wait);
             }
         }
     }
 
-    fn handle_packet(&mut self, mut packet: Packet) {
+    //The drone is gone: the controller is told.
+    fn stopped(&mut self, forced: bool) {
+        if forced {
+            warn!(drone = self.id, "stopped after the crash deadline, some neighbour still had a channel");
+        } else {
+            info!(drone = self.id, "stopped");
+        }
+        self.notify(SkyLinkEvent::Crashed {
+            drone: self.id,
+            forced,
+        });
+    }
+
+    //How long the drone can wait for something to arrive: until a task is planned, or the crash deadline.
+    fn time_to_wait(&self) -> Duration {
+        self.time_to_next_task().min(self.time_to_shutdown()).min(self.time_to_battery_level())
+    }
+
+    //How long until the idle drain brings the battery to a level to report (or to empty).
+    fn time_to_battery_level(&self) -> Duration {
+        match self.battery.as_ref() {
+            Some(battery) if !self.crashing => battery.time_to_next_level(self.clock.now()),
+            _ => Duration::MAX,
+        }
+    }
+
+    fn start_crashing(&mut self) {
+        self.crashing = true;
+        self.shutdown_at = self.crash_deadline.and_then(|deadline| self.clock.now().checked_add(deadline));
+        info!(drone = self.id, deadline = ?self.crash_deadline, "crashing");
+    }
+
+    //Takes the idle drain, reports the level of the battery if it went down a step, and crashes the drone if it's empty.
+    fn check_battery(&mut self) {
+        if self.crashing {
+            return;
+        }
+        let Some(battery) = self.battery.as_mut() else {
+            return;
+        };
+        battery.drain_idle(self.clock.now());
+        let (charge, empty) = (battery.get_charge(), battery.is_empty());
+        let level = battery.new_level();
+        self.stats.battery = Some(charge);
+        if empty {
+            warn!(drone = self.id, "battery empty");
+            self.notify(SkyLinkEvent::BatteryDepleted { drone: self.id });
+            self.start_crashing();
+        } else if let Some(percentage) = level {
+            debug!(drone = self.id, percentage, charge, "battery level");
+            self.notify(SkyLinkEvent::BatteryLevel {
+                drone: self.id,
+                percentage,
+                charge,
+            });
+        }
+    }
+
+    //How long the drone can wait for something to arrive before having work to do.
+    fn time_to_next_task(&self) -> Duration {
+        if self.scheduler.as_ref().is_some_and(|scheduler| !scheduler.is_empty()) {
+            Duration::ZERO
+        } else {
+            self.links.time_to_next(self.clock.now())
+        }
+    }
+
+    fn handle_skylink_command(&mut self, command: Option<SkyLinkCommand>) {
+        if let Some(command) = command.as_ref() {
+            self.capture(Direction::In, None, || Captured::Command(CapturedCommand::SkyLink(CapturedSkyLinkCommand::from(command))));
+        }
+        match command {
+            Some(SkyLinkCommand::SetLink(neighbour, params)) => {
+                self.links.set(neighbour, params);
+            },
+            Some(SkyLinkCommand::SetLinkPdr(neighbour, pdr)) => {
+                match pdr {
+                    Some(pdr) => self.link_pdr.insert(neighbour, pdr_percentage(pdr)),
+                    None => self.link_pdr.remove(&neighbour),
+                };
+            },
+            Some(SkyLinkCommand::SetFaultProfile(faults)) => {
+                self.set_fault_profile(faults);
+            },
+            Some(SkyLinkCommand::SetFloodLimit(max_path_trace)) => {
+                self.flood_limit = max_path_trace;
+            },
+            Some(SkyLinkCommand::GetStats(reply)) => {
+                //If the controller stopped waiting for the answer, there's nothing to do.
+                let _ = reply.send(self.stats.clone());
+            },
+            Some(SkyLinkCommand::GetSnapshot(reply)) => {
+                let _ = reply.send(self.snapshot());
+            },
+            None => {
+                //Nobody can send commands anymore, I stop listening so that the channel doesn't keep waking me up.
+                self.command_recv = never();
+            }
+        }
+    }
+
+    /// Handles the packet right away, as a drone that isn't crashing does with what it receives:
+    /// it runs the checks and forwards it, or answers with a Nack, or floods it if it's a FloodRequest.
+    /// What the drone sends goes to its transport (after crossing the link, if the link isn't instant).
+    /// Then the battery is checked, once for everything the packet made the drone send.
+    pub fn handle_packet(&mut self, packet: Packet) {
+        self.process_packet(packet);
+        self.check_battery();
+    }
+
+    //Handling of a packet, or of the Nack or FloodResponse it turned into, without checking the battery.
+    fn process_packet(&mut self, mut packet: Packet) {
+        let _span = debug_span!(
+            "packet",
+            drone = self.id,
+            session_id = packet.session_id,
+            hop_index = packet.routing_header.hop_index,
+            kind = ?PacketKind::of(&packet.pack_type),
+        ).entered();
         if let PacketType::FloodRequest(mut flood_request) = packet.pack_type.clone() {
             //First check if we're dealing with a flood request, since we ignore its SRH.
             flood_request.path_trace.push((self.id, NodeType::Drone));
             //I add myself to the path trace.
 
-            //If I can insert the flooding inside the HashSet, then I never met this flooding.
-            if self.flood_ids.insert((flood_request.flood_id.clone(), flood_request.initiator_id.clone())) {
-                if self.packet_send.len() == 1 {
+            //If I can insert the flooding inside the cache, then I never met this flooding (or I forgot it).
+            if self.flood_ids.insert(flood_request.flood_id, flood_request.initiator_id, self.clock.now()) {
+                self.stats.floods_seen += 1;
+                let neighbours = self.transport.neighbours();
+                if neighbours.len() == 1 {
+                    debug!(flood_id = flood_request.flood_id, initiator = flood_request.initiator_id, decision = "respond", "no one else to flood");
+                    self.send_flood_response(flood_request);
+                } else if self.flood_limit.is_some_and(|limit| flood_request.path_trace.len() >= limit) {
+                    self.stats.floods_limited += 1;
+                    debug!(flood_id = flood_request.flood_id, initiator = flood_request.initiator_id, decision = "respond", "path_trace at the limit");
                     self.send_flood_response(flood_request);
                 } else {
-                    let mut prev = flood_request.initiator_id.clone();
-                    if flood_request.path_trace.clone().len() > 1 {
-                        prev = flood_request.path_trace.get(flood_request.path_trace.len() - 2).unwrap().0;
+                    let mut prev = flood_request.initiator_id;
+                    if flood_request.path_trace.len() > 1 {
+                        prev = flood_request.path_trace[flood_request.path_trace.len() - 2].0;
                     }
+                    debug!(flood_id = flood_request.flood_id, initiator = flood_request.initiator_id, decision = "forward");
                     //I update the path_trace in the packet.
                     packet.pack_type = PacketType::FloodRequest(flood_request);
-                    for (key, _) in self.packet_send.iter() {
-                        //println!("Previous: {}", prev);
-                        //println!("Key: {}", key);
-                        if *key != prev {
+                    //The neighbours are sorted, so that a run can be replayed.
+                    for key in neighbours {
+                        if key != prev {
                             //I send the flooding to everyone except the node I received it from.
-                            if let Ok(_) = self.packet_send.get(key).unwrap().send(packet.clone()) {
-                                self.controller_send.send(DroneEvent::PacketSent(packet.clone())).unwrap();
-                                //If the message was sent, I also notify the sim controller.
-                            } //There's no else, since I don't care of nodes which can't be reached.
+                            let _ = self.transmit(key, packet.clone());
+                            //There's no check on the result, since I don't care of nodes which can't be reached.
                         }
                     }
                 }
             } else {
+                self.stats.floods_repeated += 1;
+                debug!(flood_id = flood_request.flood_id, initiator = flood_request.initiator_id, decision = "respond", "flooding already met");
                 self.send_flood_response(flood_request);
             }
         } else {
@@ -162,37 +667,57 @@ impl SkyLinkDrone {
             match self.apply_checks(packet.clone()) {
                 //If every check is passed
                 Ok(packet) => {
-                    let next_hop = packet.routing_header.hops[packet.routing_header.hop_index];
-                    if let Some(sender) = self.packet_send.get(&next_hop) {
-                        if let Ok(_) = sender.send(packet.clone()) {
-                            self.controller_send.send(DroneEvent::PacketSent(packet)).unwrap();
-                            //If the message was sent, I also notify the sim controller.
-                            return;
-                        }
+                    let Some(&next_hop) = packet.routing_header.hops.get(packet.routing_header.hop_index) else {
+                        //Only a custom pipeline can let through a packet without a next hop.
+                        self.discard(packet, DiscardReason::Malformed);
+                        return;
+                    };
+                    trace!(next_hop, decision = "forward");
+                    if let Err(packet) = self.transmit(next_hop, packet) {
+                        self.undeliverable(next_hop, packet);
                     }
-                    let err = create_error(self.id, packet, NackType::ErrorInRouting(next_hop));
-                    self.send_nack(&err.routing_header.hops[1].clone(), err);
-                    //If the message wasn't sent, despite all the checks, I still send an error back.
                 },
                 //Otherwise the error is already the right one to send.
                 Err(err) => {
-                    if let PacketType::Nack(nack) = err.pack_type.clone() {
-                        if let NackType::UnexpectedRecipient(_) = nack.nack_type {
-                            //If my drone isn't the one that should have received the message, I've to
-                            //route the message differently, since I'm not the first id in the routing header.
-                            self.send_nack(&err.routing_header.hops[0].clone(), err);
-                        } else {
-                            match packet.pack_type {
-                                PacketType::FloodRequest(_) => { unreachable!() },
-                                PacketType::MsgFragment(_) => {
-                                    self.handle_packet(err);
+                    let next_hop = packet.routing_header.hop_index.checked_add(1)
+                        .and_then(|index| packet.routing_header.hops.get(index).copied());
+                    match (packet.pack_type.clone(), err.pack_type.clone()) {
+                        (PacketType::MsgFragment(_), PacketType::Nack(nack)) => {
+                            //The nack was created by one of my checks.
+                            self.notify_nack(next_hop, &packet, &err);
+                            match nack.nack_type {
+                                NackType::UnexpectedRecipient(_) => {
+                                    //If my drone isn't the one that should have received the message, I've to
+                                    //route the message differently, since I'm not the first id in the routing header.
+                                    match err.routing_header.hops.first().copied() {
+                                        Some(previous) => self.send_nack(&previous, err),
+                                        None => self.discard(err, DiscardReason::Malformed),
+                                    }
+                                },
+                                NackType::Dropped => {
+                                    self.stats.count_dropped(next_hop, &packet.pack_type);
+                                    self.send_event(DroneEvent::PacketDropped(packet));
+                                    self.process_packet(err);
                                 },
                                 _ => {
-                                    self.controller_send.send(ControllerShortcut(err)).unwrap();
-                                    //If I had got an error from the checks of the routing of an
-                                    //Ack, Nack or FloodResponse, I just forward it through the Simulation Controller.
+                                    self.process_packet(err);
                                 }
                             }
+                        },
+                        (PacketType::MsgFragment(_), _) => {
+                            //There's no way back to the source of the fragment, so I can't even send a nack.
+                            self.discard(err, DiscardReason::Malformed);
+                        },
+                        (PacketType::FloodRequest(_), _) => { unreachable!() },
+                        _ => {
+                            if err.routing_header.hops.is_empty() {
+                                //Not even the Simulation Controller could deliver it.
+                                self.discard(err, DiscardReason::Malformed);
+                            } else {
+                                self.shortcut(None, err);
+                                //If I had got an error from the checks of the routing of an
+                                //Ack, Nack or FloodResponse, I just forward it through the Simulation Controller.
+                            }
                         }
                     }
                 }
@@ -204,10 +729,20 @@ impl SkyLinkDrone {
         match packet.clone().pack_type {
             PacketType::MsgFragment(_fragment) => {
                 //If the message is a fragment, I send back a Nack
-                let err = create_error(self.id, packet, NackType::ErrorInRouting(self.id));
-                self.send_nack(&err.routing_header.hops[1].clone(), err);
+                match create_error(self.id, packet.clone(), NackType::ErrorInRouting(self.id)) {
+                    Some(err) => {
+                        let next_hop = packet.routing_header.hop_index.checked_add(1)
+                            .and_then(|index| packet.routing_header.hops.get(index).copied());
+                        self.notify_nack(next_hop, &packet, &err);
+                        self.handle_packet(err);
+                    },
+                    None => self.discard(packet, DiscardReason::Malformed),
+                }
             }
-            PacketType::FloodRequest(_flood_request) => {}, //I discard them.
+            PacketType::FloodRequest(_flood_request) => {
+                //I discard them.
+                self.discard(packet, DiscardReason::Crashing);
+            },
             _ => {
                 self.handle_packet(packet);
                 //If the message is an Ack, Nack or FloodResponse, I route it normally.
@@ -215,30 +750,209 @@ impl SkyLinkDrone {
         }
     }
 
-    fn send_nack(&self, index: &NodeId, err: Packet) {
-        if let Some(sender) = self.packet_send.get(index) {
-            sender.send(err.clone()).unwrap();
-            self.controller_send.send(DroneEvent::PacketSent(err)).unwrap();
-        } else {
-            self.controller_send.send(ControllerShortcut(err)).unwrap();
+    fn send_nack(&mut self, index: &NodeId, err: Packet) {
+        if let Err(err) = self.transmit(*index, err) {
+            self.shortcut(Some(*index), err);
             //If the routing of the nack gives an error, I pass through the Sim Contr.
         }
     }
 
-    fn apply_checks(&self, mut packet: Packet) -> Result<Packet, Packet> {
-        //Check if we're on the right hop.
-        id_hop_match_check(&self, packet.clone())?;
-        //Increase the index.
-        packet.routing_header.hop_index += 1;
-        //Check if we're a final destination.
-        final_destination_check(&self, packet.clone())?;
-        //Check if the packet is dropped (only when msg_fragment).
-        pdr_check(&self, packet.clone())?;
-        //Check if the next_hop exists.
-        is_next_hop_check(&self, packet.clone())?;
+    /// Sends the packet to a neighbour: immediately, or once it has crossed the link if the link has a delay.
+    /// If the neighbour can't be reached right now, the packet is given back.
+    fn transmit(&mut self, neighbour: NodeId, packet: Packet) -> Result<(), Packet> {
+        if !self.transport.has_neighbour(neighbour) {
+            return Err(packet);
+        }
+        match self.faults {
+            Some(faults) => self.transmit_faulty(faults, neighbour, packet),
+            None => self.put_on_link(neighbour, packet, Duration::ZERO),
+        }
+    }
 
-        //If no check gave an error, we return the starting packet.
-        Ok(packet)
+    fn put_on_link(&mut self, neighbour: NodeId, packet: Packet, extra: Duration) -> Result<(), Packet> {
+        let now = self.clock.now();
+        match self.links.push(neighbour, packet, extra, self.rng.as_mut(), now) {
+            Ok(Some(packet)) => self.hand_over(neighbour, packet),
+            Ok(None) => Ok(()),
+            Err(packet) => {
+                warn!(drone = self.id, neighbour, "the link would never deliver the packet, it's treated as down");
+                Err(packet)
+            },
+        }
+    }
+
+    /// Sends the packet like `transmit`, but injecting the faults of the profile.
+    /// Every fault is reported to the controller before the packet leaves.
+    fn transmit_faulty(&mut self, faults: FaultProfile, mut neighbour: NodeId, mut packet: Packet) -> Result<(), Packet> {
+        let mut injected = Vec::new();
+        let mut extra = Duration::ZERO;
+        let mut duplicate = false;
+        match packet.pack_type {
+            PacketType::Ack(_) if FaultProfile::happens(faults.drop_acks, self.rng.as_mut()) => {
+                self.notify_fault(FaultKind::AckDropped, packet);
+                return Ok(());
+            },
+            PacketType::Nack(_) if FaultProfile::happens(faults.delay_nacks, self.rng.as_mut()) => {
+                extra += faults.nack_delay;
+                injected.push(FaultKind::NackDelayed);
+            },
+            PacketType::MsgFragment(ref mut fragment) => {
+                let length = (fragment.length as usize).min(fragment.data.len());
+                if length > 0 && FaultProfile::happens(faults.corrupt, self.rng.as_mut()) {
+                    let index = self.rng.usize(0..length);
+                    fragment.data[index] ^= 1 << self.rng.u8(0..8);
+                    injected.push(FaultKind::Corrupted { index });
+                }
+                if FaultProfile::happens(faults.reorder, self.rng.as_mut()) {
+                    extra += faults.reorder_delay;
+                    injected.push(FaultKind::Reordered);
+                }
+                if FaultProfile::happens(faults.duplicate, self.rng.as_mut()) {
+                    duplicate = true;
+                    injected.push(FaultKind::Duplicated);
+                }
+            },
+            _ => {},
+        }
+
+        //A FloodRequest already goes to every neighbour, so there's no way to misroute it.
+        if !matches!(packet.pack_type, PacketType::FloodRequest(_)) && FaultProfile::happens(faults.misroute, self.rng.as_mut()) {
+            //The neighbours are sorted, so that the choice only depends on the seed.
+            let others = self.transport.neighbours().into_iter().filter(|id| *id != neighbour).collect::<Vec<NodeId>>();
+            if !others.is_empty() {
+                let actual = others[self.rng.usize(0..others.len())];
+                injected.push(FaultKind::Misrouted { intended: neighbour, actual });
+                neighbour = actual;
+            }
+        }
+
+        for fault in injected {
+            self.notify_fault(fault, packet.clone());
+        }
+        if duplicate {
+            let _ = self.put_on_link(neighbour, packet.clone(), extra);
+        }
+        self.put_on_link(neighbour, packet, extra)
+    }
+
+    fn hand_over(&mut self, neighbour: NodeId, packet: Packet) -> Result<(), Packet> {
+        match self.transport.send_packet(neighbour, packet.clone()) {
+            Ok(_) => {
+                self.stats.count_forwarded(neighbour, &packet.pack_type);
+                self.capture(Direction::Out, Some(neighbour), || Captured::Packet(packet.clone()));
+                if let Some(battery) = self.battery.as_mut() {
+                    battery.spend_on(&packet);
+                }
+                self.send_event(DroneEvent::PacketSent(packet));
+                //If the message was sent, I also notify the sim controller.
+                Ok(())
+            },
+            Err(packet) => Err(packet),
+        }
+    }
+
+    /// Sends to the neighbours the packets that have crossed their link.
+    fn deliver_arrived(&mut self) {
+        while let Some((neighbour, packet)) = self.links.pop_arrived(self.clock.now()) {
+            if let Err(packet) = self.hand_over(neighbour, packet) {
+                self.undeliverable(neighbour, packet);
+            }
+        }
+    }
+
+    /// Handles a packet that couldn't reach its next hop.
+    fn undeliverable(&mut self, next_hop: NodeId, packet: Packet) {
+        match packet.pack_type {
+            PacketType::MsgFragment(_) => {
+                //If the message wasn't sent, despite all the checks, I still send an error back.
+                match create_error(self.id, packet.clone(), NackType::ErrorInRouting(next_hop)) {
+                    Some(err) => {
+                        self.notify_nack(Some(next_hop), &packet, &err);
+                        self.process_packet(err);
+                    },
+                    None => self.discard(packet, DiscardReason::Malformed),
+                }
+            },
+            PacketType::FloodRequest(_) => {}, //I don't care of nodes which can't be reached by a flooding.
+            _ => {
+                self.shortcut(Some(next_hop), packet);
+            }
+        }
+    }
+
+    /// Gives the packet to the Simulation Controller, which delivers it without going through the network.
+    fn shortcut(&mut self, next_hop: Option<NodeId>, packet: Packet) {
+        debug!(next_hop, decision = "shortcut", "sent through the Simulation Controller");
+        self.stats.count_shortcut(next_hop, &packet.pack_type);
+        self.send_event(ControllerShortcut(packet));
+    }
+
+    fn send_event(&mut self, event: DroneEvent) {
+        self.capture(Direction::Out, None, || Captured::Event(CapturedEvent::from(&event)));
+        self.transport.send_event(event);
+    }
+
+    fn discard(&mut self, packet: Packet, reason: DiscardReason) {
+        debug!(drone = self.id, reason = ?reason, decision = "discard");
+        self.notify(SkyLinkEvent::PacketDiscarded {
+            drone: self.id,
+            reason,
+            packet,
+        });
+    }
+
+    fn notify(&mut self, event: SkyLinkEvent) {
+        self.capture(Direction::Out, None, || Captured::Event(CapturedEvent::SkyLink(event.clone())));
+        if let Some(event_send) = &self.event_send {
+            let _ = event_send.send(event);
+        }
+    }
+
+    fn notify_fault(&mut self, fault: FaultKind, packet: Packet) {
+        debug!(drone = self.id, fault = ?fault, decision = "inject");
+        self.notify(SkyLinkEvent::FaultInjected {
+            drone: self.id,
+            fault,
+            packet,
+        });
+    }
+
+    //Writes in the capture, if the drone has one, what went in or out.
+    fn capture(&mut self, direction: Direction, neighbour: Option<NodeId>, captured: impl FnOnce() -> Captured) {
+        let Some(capturer) = self.capture.as_ref() else {
+            return;
+        };
+        let header = (!capturer.started()).then(|| {
+            CaptureHeader {
+                snapshot: self.snapshot(),
+            }
+        });
+        let now = self.clock.now();
+        if let Some(capturer) = self.capture.as_mut() {
+            capturer.record(header, now, direction, neighbour, captured());
+        }
+    }
+
+    //The nack is counted like the packet it refuses, under the neighbour the packet was meant for.
+    fn notify_nack(&mut self, next_hop: Option<NodeId>, packet: &Packet, nack: &Packet) {
+        if let PacketType::Nack(n) = &nack.pack_type {
+            self.stats.count_nack(next_hop, &packet.pack_type, &n.nack_type);
+            self.notify(SkyLinkEvent::NackGenerated {
+                drone: self.id,
+                nack_type: n.nack_type,
+                nack: nack.clone(),
+            });
+        }
+    }
+
+    fn apply_checks(&mut self, packet: Packet) -> Result<Packet, Packet> {
+        //The pipeline is taken out of the drone while running, so that the checks can use the drone.
+        let mut checks = std::mem::replace(&mut self.checks, CheckPipeline::empty());
+        let result = checks.run(self, packet);
+        self.checks = checks;
+
+        //If no check gave an error, we return the packet as left by the checks.
+        result
     }
 
 
@@ -254,7 +968,7 @@ impl SkyLinkDrone {
             .rev()
             .map(|(id, _)| *id)
             .collect::<Vec<NodeId>>(); //I take only the ID's from the path trace and reverse them.
-        if flood.path_trace[0].0 != flood.initiator_id {
+        if flood.path_trace.first().map(|(id, _)| *id) != Some(flood.initiator_id) {
             hops.push(flood.initiator_id);
         }
 
@@ -266,8 +980,7 @@ impl SkyLinkDrone {
             },
             session_id: flood.flood_id,
         };
-        self.handle_packet(resp);
-        //self.controller_send.send(DroneEvent::PacketSent(resp)).unwrap(); //Should be set by handle_packet.
+        self.process_packet(resp);
     }
 
     pub fn get_id(&self) -> NodeId {
@@ -276,7 +989,58 @@ impl SkyLinkDrone {
     pub fn get_pdr(&self) -> u32 {
         self.pdr
     }
-    pub fn get_packet_send(&self) -> &HashMap<NodeId, Sender<Packet>>{
-        &self.packet_send
+    /// Pdr of the link towards the neighbour, which is the drone's one if the link hasn't its own.
+    pub fn get_link_pdr(&self, neighbour: NodeId) -> u32 {
+        self.link_pdr.get(&neighbour).copied().unwrap_or(self.pdr)
+    }
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+    pub fn get_rng(&mut self) -> &mut dyn RandomSource {
+        self.rng.as_mut()
+    }
+    pub fn get_clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+    pub fn get_stats(&self) -> &DroneStats {
+        &self.stats
+    }
+    pub fn is_crashing(&self) -> bool {
+        self.crashing
+    }
+    /// Charge left in the battery, if the drone has one.
+    pub fn get_battery(&self) -> Option<f32> {
+        self.battery.as_ref().map(Battery::get_charge)
+    }
+    pub fn get_flood_limit(&self) -> Option<usize> {
+        self.flood_limit
+    }
+    pub fn get_fault_profile(&self) -> Option<&FaultProfile> {
+        self.faults.as_ref()
+    }
+    /// Changes the faults injected by the drone, a profile that injects nothing turns them off.
+    pub fn set_fault_profile(&mut self, faults: Option<FaultProfile>) {
+        self.faults = faults.filter(|faults| !faults.is_honest());
+    }
+    pub fn get_flood_cache(&self) -> &FloodCache {
+        &self.flood_ids
+    }
+    /// The neighbours the drone can send to, sorted.
+    pub fn get_neighbours(&self) -> Vec<NodeId> {
+        self.transport.neighbours()
+    }
+    pub fn has_neighbour(&self, neighbour: NodeId) -> bool {
+        self.transport.has_neighbour(neighbour)
     }
 }
+
+//The pdr is kept as a percentage, after bringing it between 0 and 1.
+fn pdr_percentage(pdr: f32) -> u32 {
+    (pdr.clamp(0.00, 1.00) * 100.0) as u32
+}
+
+/// Derives the seed of a single drone from the seed of the whole simulation,
+/// so that every drone gets its own stream of random numbers.
+pub fn derive_seed(master_seed: u64, id: NodeId) -> u64 {
+    fastrand::Rng::with_seed(master_seed.wrapping_add(id as u64)).u64(..)
+}
\ No newline at end of file
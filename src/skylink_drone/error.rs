@@ -1,26 +1,29 @@
 use wg_2024::network::{NodeId, SourceRoutingHeader};
 use wg_2024::packet::{Nack, NackType, Packet, PacketType};
 
-pub fn create_error(starting_id: NodeId, packet: Packet, nack_type: NackType) -> Packet {
+/// Creates the Nack going back from `starting_id` to the source of the packet.
+/// Returns None if `starting_id` isn't in the routing header, since then there's no way back.
+pub fn create_error(starting_id: NodeId, packet: Packet, nack_type: NackType) -> Option<Packet> {
     let mut fragment_index = 0;
     if let PacketType::MsgFragment(msg_fragment) = packet.pack_type {
         fragment_index = msg_fragment.fragment_index;
     }
     let position = packet.routing_header.hops
         .iter()
-        .position(|x| *x == starting_id).unwrap();
-    Packet {
+        .position(|x| *x == starting_id)?;
+    Some(Packet {
         pack_type: PacketType::Nack(Nack{
             fragment_index,
             nack_type,
         }),
         routing_header: SourceRoutingHeader{
             hop_index: 0,
-            hops: packet.routing_header.hops[0..position + 1].to_vec()
-                .into_iter()
+            hops: packet.routing_header.hops[0..position + 1]
+                .iter()
+                .copied()
                 .rev()
                 .collect::<Vec<NodeId>>()
         },
         session_id: packet.session_id,
-    }
+    })
 }
\ No newline at end of file
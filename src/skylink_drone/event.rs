@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use wg_2024::network::NodeId;
+use wg_2024::packet::{NackType, Packet};
+use crate::skylink_drone::fault::FaultKind;
+
+/// Events reported by a SkyLinkDrone on top of the wg_2024 `DroneEvent`s, which can't be extended.
+/// They're sent only if the drone was given a channel with `SkyLinkDrone::with_event_channel`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SkyLinkEvent {
+    /// The drone created a Nack for a fragment it couldn't forward.
+    NackGenerated {
+        drone: NodeId,
+        nack_type: NackType,
+        nack: Packet,
+    },
+    /// The drone threw away a packet without answering to anyone.
+    PacketDiscarded {
+        drone: NodeId,
+        reason: DiscardReason,
+        packet: Packet,
+    },
+    /// The drone crashed and left its loop, this is the last event it sends.
+    /// `forced` is true if some neighbour still had a channel to it when the crash deadline expired.
+    Crashed {
+        drone: NodeId,
+        forced: bool,
+    },
+    /// The drone misbehaved on purpose, following its `FaultProfile`.
+    /// `packet` is the packet as it was sent, after the fault.
+    FaultInjected {
+        drone: NodeId,
+        fault: FaultKind,
+        packet: Packet,
+    },
+    /// The charge of the battery went below `percentage` of the initial one (a multiple of `BATTERY_REPORT_STEP`).
+    BatteryLevel {
+        drone: NodeId,
+        percentage: u32,
+        charge: f32,
+    },
+    /// The battery is empty, and the drone started crashing: the controller should remove it from its neighbours.
+    BatteryDepleted {
+        drone: NodeId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DiscardReason {
+    /// FloodRequests received while crashing are ignored.
+    Crashing,
+    /// The routing header doesn't allow to forward the packet, nor to send anything back.
+    Malformed,
+}
+
+impl SkyLinkEvent {
+    /// Id of the drone that sent the event.
+    pub fn drone(&self) -> NodeId {
+        match self {
+            SkyLinkEvent::NackGenerated { drone, .. } => *drone,
+            SkyLinkEvent::PacketDiscarded { drone, .. } => *drone,
+            SkyLinkEvent::FaultInjected { drone, .. } => *drone,
+            SkyLinkEvent::Crashed { drone, .. } => *drone,
+            SkyLinkEvent::BatteryLevel { drone, .. } => *drone,
+            SkyLinkEvent::BatteryDepleted { drone } => *drone,
+        }
+    }
+}
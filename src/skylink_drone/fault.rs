@@ -0,0 +1,69 @@
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use wg_2024::network::NodeId;
+use crate::skylink_drone::random::RandomSource;
+
+/// Misbehaviours a drone can inject in the packets it sends, to test how the other nodes cope with them.
+/// Every behaviour has the probability of being applied to each packet it concerns, 0 turns it off.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FaultProfile {
+    /// Probability of sending a fragment twice.
+    pub duplicate: f32,
+    /// Probability of holding a fragment back for `reorder_delay`, so that the following ones overtake it.
+    pub reorder: f32,
+    pub reorder_delay: Duration,
+    /// Probability of flipping a byte in the payload of a fragment.
+    pub corrupt: f32,
+    /// Probability of sending a packet to a neighbour which isn't its next hop.
+    pub misroute: f32,
+    /// Probability of silently dropping an Ack.
+    pub drop_acks: f32,
+    /// Probability of sending a Nack only after `nack_delay`.
+    pub delay_nacks: f32,
+    pub nack_delay: Duration,
+}
+
+impl Default for FaultProfile {
+    fn default() -> Self {
+        FaultProfile {
+            duplicate: 0.0,
+            reorder: 0.0,
+            reorder_delay: Duration::from_millis(50),
+            corrupt: 0.0,
+            misroute: 0.0,
+            drop_acks: 0.0,
+            delay_nacks: 0.0,
+            nack_delay: Duration::from_millis(200),
+        }
+    }
+}
+
+impl FaultProfile {
+    /// A profile that doesn't inject anything.
+    pub fn is_honest(&self) -> bool {
+        [self.duplicate, self.reorder, self.corrupt, self.misroute, self.drop_acks, self.delay_nacks]
+            .iter()
+            .all(|probability| *probability <= 0.0)
+    }
+
+    pub(crate) fn happens(probability: f32, rng: &mut dyn RandomSource) -> bool {
+        probability > 0.0 && rng.f32() < probability
+    }
+}
+
+/// Fault injected by a drone, reported with `SkyLinkEvent::FaultInjected`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FaultKind {
+    /// The fragment was sent twice.
+    Duplicated,
+    /// The fragment was held back, to arrive after the ones sent later.
+    Reordered,
+    /// A byte of the payload was changed.
+    Corrupted { index: usize },
+    /// The packet went to `actual` instead of its next hop `intended`.
+    Misrouted { intended: NodeId, actual: NodeId },
+    /// The Ack wasn't sent.
+    AckDropped,
+    /// The Nack was sent late.
+    NackDelayed,
+}
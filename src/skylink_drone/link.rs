@@ -0,0 +1,176 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::num::NonZeroU64;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use wg_2024::network::NodeId;
+use wg_2024::packet::{Packet, PacketType};
+use crate::skylink_drone::random::RandomSource;
+
+/// Timing of the link between the drone and one of its neighbours.
+/// The default link has no delay and no bandwidth limit, so packets are handed over immediately.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct LinkParams {
+    /// Time a packet needs to cross the link.
+    pub delay: Duration,
+    /// Random extra delay, between zero and this value, added to every packet.
+    pub jitter: Duration,
+    /// Bytes per second the link can carry, None means no limit.
+    /// Fragments use as many bytes as their `length`, the other packets are too small to count.
+    /// A link that carries nothing isn't a slow link, so 0 isn't accepted (not even in the config).
+    pub bandwidth: Option<NonZeroU64>,
+}
+
+impl LinkParams {
+    pub fn is_instant(&self) -> bool {
+        self.delay.is_zero() && self.jitter.is_zero() && self.bandwidth.is_none()
+    }
+
+    /// Time the link needs to transmit the packet, given its bandwidth.
+    pub fn transmission_time(&self, packet: &Packet) -> Duration {
+        match (self.bandwidth, &packet.pack_type) {
+            (Some(bandwidth), PacketType::MsgFragment(fragment)) => {
+                Duration::from_secs_f64(fragment.length as f64 / bandwidth.get() as f64)
+            },
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+//A packet waiting to reach the end of its link.
+struct InFlight {
+    due: Instant,
+    seq: u64, //Keeps the order of packets due at the same instant.
+    neighbour: NodeId,
+    packet: Packet,
+}
+
+impl PartialEq for InFlight {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.seq) == (other.due, other.seq)
+    }
+}
+impl Eq for InFlight {}
+impl PartialOrd for InFlight {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for InFlight {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+/// Links of a drone towards its neighbours, with the packets currently crossing them.
+#[derive(Default)]
+pub(crate) struct Links {
+    params: HashMap<NodeId, LinkParams>,
+    busy_until: HashMap<NodeId, Instant>, //When each link finishes transmitting the packets already given to it.
+    in_flight: BinaryHeap<Reverse<InFlight>>,
+    next_seq: u64,
+}
+
+impl Links {
+    pub(crate) fn get(&self, neighbour: NodeId) -> LinkParams {
+        self.params.get(&neighbour).copied().unwrap_or_default()
+    }
+
+    pub(crate) fn set(&mut self, neighbour: NodeId, params: LinkParams) {
+        if params.is_instant() {
+            self.params.remove(&neighbour);
+            self.busy_until.remove(&neighbour);
+        } else {
+            self.params.insert(neighbour, params);
+        }
+    }
+
+    /// Puts the packet on the link, unless the link is instant: then the packet is given back to be sent now.
+    /// The packet needs `extra` time, on top of the link's delay, to reach the neighbour.
+    /// If the packet would never arrive (its time doesn't fit in an Instant), the link is down for it and it's given back as an error.
+    pub(crate) fn push(&mut self, neighbour: NodeId, packet: Packet, extra: Duration, rng: &mut dyn RandomSource, now: Instant) -> Result<Option<Packet>, Packet> {
+        let params = self.get(neighbour);
+        if params.is_instant() && extra.is_zero() {
+            return Ok(Some(packet));
+        }
+        let start = self.busy_until.get(&neighbour).map_or(now, |busy| now.max(*busy));
+        let jitter = params.jitter.mul_f64(rng.f64());
+        let Some(transmitted) = start.checked_add(params.transmission_time(&packet)) else {
+            return Err(packet);
+        };
+        let Some(due) = params.delay.checked_add(jitter)
+            .and_then(|delay| delay.checked_add(extra))
+            .and_then(|delay| transmitted.checked_add(delay)) else {
+            return Err(packet);
+        };
+        self.busy_until.insert(neighbour, transmitted);
+
+        self.in_flight.push(Reverse(InFlight {
+            due,
+            seq: self.next_seq,
+            neighbour,
+            packet,
+        }));
+        self.next_seq += 1;
+        Ok(None)
+    }
+
+    /// How long until the next packet reaches its neighbour, Duration::MAX if no packet is on a link.
+    pub(crate) fn time_to_next(&self, now: Instant) -> Duration {
+        match self.in_flight.peek() {
+            Some(Reverse(next)) => next.due.saturating_duration_since(now),
+            None => Duration::MAX,
+        }
+    }
+
+    /// Takes the next packet that has crossed its link, if any.
+    pub(crate) fn pop_arrived(&mut self, now: Instant) -> Option<(NodeId, Packet)> {
+        if self.in_flight.peek().is_some_and(|Reverse(next)| next.due <= now) {
+            self.in_flight.pop().map(|Reverse(arrived)| (arrived.neighbour, arrived.packet))
+        } else {
+            None
+        }
+    }
+
+    /// Takes every packet on a link, arrived or not, in the order they would arrive.
+    pub(crate) fn drain(&mut self) -> Vec<(NodeId, Packet)> {
+        let mut in_flight = std::mem::take(&mut self.in_flight).into_sorted_vec();
+        //The heap is sorted on Reverse, so the first to arrive is at the end.
+        in_flight.reverse();
+        in_flight.into_iter().map(|Reverse(packet)| (packet.neighbour, packet.packet)).collect()
+    }
+
+    /// Timing of every link that isn't instant.
+    pub(crate) fn params(&self) -> HashMap<NodeId, LinkParams> {
+        self.params.clone()
+    }
+
+    /// The packets on a link, in the order they would arrive, with the time they still need.
+    pub(crate) fn in_flight(&self, now: Instant) -> Vec<(NodeId, Duration, Packet)> {
+        let mut in_flight = self.in_flight.iter().map(|Reverse(packet)| packet).collect::<Vec<&InFlight>>();
+        in_flight.sort();
+        in_flight.into_iter()
+            .map(|packet| (packet.neighbour, packet.due.saturating_duration_since(now), packet.packet.clone()))
+            .collect()
+    }
+
+    /// Puts back on its link a packet that needs `remaining` time to reach the neighbour, whatever the link's timing.
+    /// Like `push`, a packet that would never arrive is given back.
+    pub(crate) fn push_remaining(&mut self, neighbour: NodeId, packet: Packet, remaining: Duration, now: Instant) -> Result<(), Packet> {
+        let Some(due) = now.checked_add(remaining) else {
+            return Err(packet);
+        };
+        self.in_flight.push(Reverse(InFlight {
+            due,
+            seq: self.next_seq,
+            neighbour,
+            packet,
+        }));
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+}
@@ -1,3 +1,24 @@
+//The packets that can't be sent are given back in the Err, like the SendError of crossbeam, so the Err is as large as a Packet.
+#![allow(clippy::result_large_err)]
+//The program uses only a part of the API of the skylink crate, which is copied here.
+#![allow(dead_code)]
+
 pub mod drone;
 mod error;
-mod checks;
\ No newline at end of file
+pub mod checks;
+pub mod event;
+pub mod flood_cache;
+pub mod command;
+pub mod link;
+pub mod scheduler;
+pub mod fault;
+pub mod stats;
+pub mod pool;
+pub mod capture;
+pub mod battery;
+pub mod snapshot;
+pub mod clock;
+pub mod random;
+pub mod transport;
+#[cfg(feature = "async")]
+pub mod async_driver;
\ No newline at end of file
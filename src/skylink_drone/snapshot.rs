@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+use crossbeam_channel::{unbounded, Sender};
+use serde::{Deserialize, Serialize};
+use wg_2024::network::NodeId;
+use wg_2024::packet::Packet;
+use crate::skylink_drone::battery::BatteryModel;
+use crate::skylink_drone::command::SkyLinkCommand;
+use crate::skylink_drone::fault::FaultProfile;
+use crate::skylink_drone::flood_cache::FloodCache;
+use crate::skylink_drone::link::LinkParams;
+use crate::skylink_drone::scheduler::PriorityScheduler;
+use crate::skylink_drone::stats::DroneStats;
+
+/// Everything a SkyLinkDrone knows while it runs, taken with `SkyLinkDrone::snapshot`
+/// and turned back into a drone, with new channels, by `SkyLinkDrone::restore`.
+///
+/// The channels aren't part of it, and neither are the packets waiting in them: a snapshot of a whole network
+/// is consistent only if it's taken while no packet is travelling between the drones.
+/// The checks, the capture and the channels of the SkyLink events and commands are given again to the restored drone,
+/// with the usual builders.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DroneSnapshot {
+    pub id: NodeId,
+    /// Pdr of the drone, in percentage.
+    pub pdr: u32,
+    /// Pdr of the links that have their own, in percentage.
+    pub link_pdr: HashMap<NodeId, u32>,
+    pub crashing: bool,
+    /// How long a crashing drone could still wait for its neighbours, None if it has no deadline.
+    pub shutdown_in: Option<Duration>,
+    pub crash_deadline: Option<Duration>,
+    /// Neighbours the drone had a channel to, sorted.
+    pub neighbours: Vec<NodeId>,
+    pub flood_ids: FloodCacheSnapshot,
+    pub seed: u64,
+    /// State of the random source, so that the restored drone draws the numbers the original one would have drawn.
+    /// None if the drone had a source that can't be restored, then the restored drone draws from its seed.
+    pub rng_state: Option<u64>,
+    pub links: HashMap<NodeId, LinkParams>,
+    /// Packets that were crossing a link, with the neighbour they go to and the time they still needed.
+    pub in_flight: Vec<(NodeId, Duration, Packet)>,
+    pub scheduler: Option<PriorityScheduler>,
+    pub faults: Option<FaultProfile>,
+    pub flood_limit: Option<usize>,
+    pub battery: Option<BatterySnapshot>,
+    pub stats: DroneStats,
+}
+
+/// Floodings remembered by a `FloodCache`, from the oldest to the newest.
+/// Their age restarts when they're restored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FloodCacheSnapshot {
+    pub capacity: NonZeroUsize,
+    pub max_age: Option<Duration>,
+    pub floodings: Vec<(u64, NodeId)>,
+}
+
+impl FloodCacheSnapshot {
+    pub fn of(flood_ids: &FloodCache) -> Self {
+        FloodCacheSnapshot {
+            capacity: flood_ids.capacity(),
+            max_age: flood_ids.max_age(),
+            floodings: flood_ids.floodings(),
+        }
+    }
+
+    /// `now` is the time of the clock of the restored drone.
+    pub fn restore(&self, now: Instant) -> FloodCache {
+        let mut flood_ids = FloodCache::new(self.capacity);
+        if let Some(max_age) = self.max_age {
+            flood_ids = flood_ids.with_max_age(max_age);
+        }
+        for (flood_id, initiator_id) in self.floodings.iter() {
+            flood_ids.insert(*flood_id, *initiator_id, now);
+        }
+        flood_ids
+    }
+}
+
+/// Battery of a drone, with what's left of its charge.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BatterySnapshot {
+    pub model: BatteryModel,
+    pub charge: f32,
+    /// Last level reported, in percentage.
+    pub reported: u32,
+}
+
+/// Asks every drone for a snapshot and waits for the answers, up to `timeout` in total.
+/// Drones that don't answer in time (e.g. because they crashed and stopped) are left out.
+pub fn collect_snapshots(command_send: &HashMap<NodeId, Sender<SkyLinkCommand>>, timeout: Duration) -> HashMap<NodeId, DroneSnapshot> {
+    let (reply_send, reply_recv) = unbounded();
+    let mut waiting = 0;
+    for sender in command_send.values() {
+        if sender.send(SkyLinkCommand::GetSnapshot(reply_send.clone())).is_ok() {
+            waiting += 1;
+        }
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut snapshots = HashMap::new();
+    while snapshots.len() < waiting {
+        match reply_recv.recv_deadline(deadline) {
+            Ok(snapshot) => {
+                snapshots.insert(snapshot.id, snapshot);
+            },
+            Err(_timeout) => break,
+        }
+    }
+    snapshots
+}
@@ -1,2 +1,76 @@
 pub mod test_bench;
-mod test_initializer;
\ No newline at end of file
+mod test_initializer;
+pub mod test_fuzz;
+
+use crate::test::test_bench::*;
+use crate::test::test_fuzz::*;
+
+/// Runs the test of the bench with the given name, as `cargo run -- <name>` does.
+pub fn run_test(name: &str) {
+    println!("{}", name);
+    match name {
+        "test_generic_fragment_forward" => test_generic_fragment_forward(),
+        "test_generic_drop" => test_generic_drop(),
+        "test_generic_nack" => test_generic_nack(),
+        "test_flood" => test_flood(),
+        "test_double_chain_flood" => test_double_chain_flood(),
+        "test_star_flood" => test_star_flood(),
+        "test_butterfly_flood" => test_butterfly_flood(),
+        "test_tree_flood" => test_tree_flood(),
+        "test_drone_commands" => test_drone_commands(),
+        "test_busy_network" => test_busy_network(),
+        "test_custom_checks" => test_custom_checks(),
+        "test_seeded_drops" => test_seeded_drops(),
+        "test_drop_events" => test_drop_events(),
+        "test_flood_cache_bounded" => test_flood_cache_bounded(),
+        "test_flood_cache_in_flight" => test_flood_cache_in_flight(),
+        "test_arbitrary_packets" => test_arbitrary_packets(),
+        "test_link_timing" => test_link_timing(),
+        "test_config_links" => test_config_links(),
+        "test_link_pdr" => test_link_pdr(),
+        "test_priority_scheduler" => test_priority_scheduler(),
+        "test_fault_duplicate" => test_fault_duplicate(),
+        "test_fault_reorder" => test_fault_reorder(),
+        "test_fault_corrupt" => test_fault_corrupt(),
+        "test_fault_misroute" => test_fault_misroute(),
+        "test_fault_drop_acks" => test_fault_drop_acks(),
+        "test_fault_delay_nacks" => test_fault_delay_nacks(),
+        "test_drone_stats" => test_drone_stats(),
+        "test_crash_deadline" => test_crash_deadline(),
+        "test_crash_spamming_neighbour" => test_crash_spamming_neighbour(),
+        "test_crash_clean" => test_crash_clean(),
+        "test_crash_deadline_drain" => test_crash_deadline_drain(),
+        "test_step" => test_step(),
+        "test_worker_pool" => test_worker_pool(),
+        "test_pool_crash_links" => test_pool_crash_links(),
+        "test_pool_closed_channel" => test_pool_closed_channel(),
+        "test_config_pool" => test_config_pool(),
+        "test_des_deterministic" => test_des_deterministic(),
+        "test_des_virtual_time" => test_des_virtual_time(),
+        "test_des_crash" => test_des_crash(),
+        "test_des_shortcut_to_drone" => test_des_shortcut_to_drone(),
+        "test_des_flood_cache_age" => test_des_flood_cache_age(),
+        "test_capture_replay" => test_capture_replay(),
+        "test_capture_replay_diverges" => test_capture_replay_diverges(),
+        "test_tracing" => test_tracing(),
+        "test_flood_limit" => test_flood_limit(),
+        "test_flood_limit_command" => test_flood_limit_command(),
+        "test_battery_depletion" => test_battery_depletion(),
+        "test_battery_cost" => test_battery_cost(),
+        "test_battery_once_per_packet" => test_battery_once_per_packet(),
+        "test_battery_idle_drain" => test_battery_idle_drain(),
+        "test_snapshot_restore" => test_snapshot_restore(),
+        "test_checkpoint_resume" => test_checkpoint_resume(),
+        "test_checkpoint_no_answer" => test_checkpoint_no_answer(),
+        "test_detached_drone" => test_detached_drone(),
+        "test_event_pump" => test_event_pump(),
+        "test_controller_shortcut" => test_controller_shortcut(),
+        "test_topology_changes" => test_topology_changes(),
+        "test_client_link" => test_client_link(),
+        "test_unsafe_changes" => test_unsafe_changes(),
+        "test_node_registry" => test_node_registry(),
+        #[cfg(feature = "async")]
+        "test_async_drones" => test_async_drones(),
+        _ => panic!("No test is named {}", name),
+    }
+}
@@ -1,14 +1,38 @@
 use std::collections::{HashMap};
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::sync::{Arc, Mutex};
 use std::{thread, vec};
 use std::thread::JoinHandle;
-use crossbeam_channel::{select, select_biased, unbounded, Receiver, Sender};
+use std::time::{Duration, Instant};
+use crossbeam_channel::{never, select, select_biased, unbounded, Receiver, Sender};
 use wg_2024::controller::{DroneCommand, DroneEvent};
 use wg_2024::controller::DroneCommand::{SetPacketDropRate};
 use wg_2024::drone::Drone;
 use wg_2024::network::{NodeId, SourceRoutingHeader};
-use wg_2024::packet::{Fragment, Nack, NackType, Packet, PacketType};
+use wg_2024::packet::{Ack, FloodRequest, Fragment, Nack, NackType, NodeType, Packet, PacketType};
 use crate::skylink_drone::drone::SkyLinkDrone;
+use crate::skylink_drone::checks::CheckPipeline;
+use crate::skylink_drone::event::{DiscardReason, SkyLinkEvent};
+use crate::skylink_drone::flood_cache::FloodCache;
+use crate::skylink_drone::command::SkyLinkCommand;
+use crate::skylink_drone::link::LinkParams;
+use crate::skylink_drone::scheduler::PriorityScheduler;
+use crate::skylink_drone::fault::{FaultKind, FaultProfile};
+use crate::skylink_drone::stats::{collect_stats, PacketKind};
+use crate::skylink_drone::drone::Step;
+use crate::skylink_drone::pool::DronePool;
+use crate::skylink_drone::battery::BatteryModel;
+use crate::skylink_drone::snapshot::DroneSnapshot;
+use crate::skylink_drone::clock::{Clock, ManualClock};
+use crate::skylink_drone::random::FixedRandom;
+use crate::skylink_drone::transport::RecordingTransport;
+use crate::skylink_drone::capture::{capture_file, replay, Capture, Captured, CapturedCommand, CapturedEvent, CapturedSkyLinkCommand};
 use crate::test::test_initializer::test_initialize;
+use crate::des::{DesEngine, Record};
+use crate::initializer::{build_drone, initialize, parse_config, parse_options, resume, DroneChannels, DroneOrigin, SimulationOptions, MAX_LINK_DELAY_MS};
+use crate::sim_control::{Checkpoint, CheckpointError, EventPump, NodeInfo, NodeState, ShortcutCounters, SimulationControl, TopologyError};
+use crate::topology::Violation;
+use crate::logging::{TraceOptions, Tracing};
 
 fn packet_printer(packet: Packet) {
     match packet.pack_type.clone() {
@@ -67,8 +91,8 @@ fn event_printer(event: DroneEvent) {
             }
         },
         DroneEvent::PacketDropped(packet) => {
-            let id = packet.routing_header.hops[0];
-            println!("Packet dropped by {}:", id); //Not sure the index is right.
+            let id = packet.routing_header.hops[packet.routing_header.hop_index];
+            println!("Packet dropped by {}:", id); //The dropped packet is the one the drone received.
             packet_printer(packet);
         },
         DroneEvent::ControllerShortcut(packet) => {
@@ -143,7 +167,7 @@ pub fn test_generic_fragment_forward() {
 
     let msg = create_packet(vec![0,1,2,3]);
 
-    send_packet(msg, clients.get(0).unwrap().client_send.get(&1).unwrap());
+    send_packet(msg, clients.first().unwrap().client_send.get(&1).unwrap());
 
     for i in handles {
         i.join().unwrap();
@@ -157,9 +181,9 @@ pub fn test_generic_drop(){
     let msg = create_packet(vec![1,11,12,21]);
 
     // "Client 1" sends packet to the drone
-    send_packet(msg, clients.get(0).unwrap().client_send.get(&11).unwrap());
+    send_packet(msg, clients.first().unwrap().client_send.get(&11).unwrap());
 
-    let client_receiver = clients.get(0).unwrap().client_recv.clone();
+    let client_receiver = clients.first().unwrap().client_recv.clone();
     // Client receive an NACK originated from 'd2'
     /*assert_eq!(
         client_receiver.clone().recv().unwrap(),
@@ -189,9 +213,9 @@ pub fn test_generic_nack(){
 
     let msg = create_packet(vec![1,11,21]);
 
-    send_packet(msg, clients.get(0).unwrap().client_send.get(&11).unwrap());
+    send_packet(msg, clients.first().unwrap().client_send.get(&11).unwrap());
 
-    let client_receiver = clients.get(0).unwrap().client_recv.clone();
+    let client_receiver = clients.first().unwrap().client_recv.clone();
     handles.push(listen_handle(sim_contr.event_recv, client_receiver));
 
     for i in handles {
@@ -215,11 +239,11 @@ pub fn test_flood(){
         session_id: 0,
     };
 
-    let client_receiver = clients.get(0).unwrap().client_recv.clone();
+    let client_receiver = clients.first().unwrap().client_recv.clone();
     handles.push(listen_handle(_sim_contr.event_recv, client_receiver));
     // handles.push(client_only_listen_handle(client_receiver));
 
-    send_packet(packet, clients.get(0).unwrap().client_send.get(&1).unwrap());
+    send_packet(packet, clients.first().unwrap().client_send.get(&1).unwrap());
 
     for i in handles {
         i.join().unwrap();
@@ -242,7 +266,7 @@ pub fn test_double_chain_flood(){
         session_id: 0,
     };
 
-    let client1_receiver = clients.get(0).unwrap().client_recv.clone();
+    let client1_receiver = clients.first().unwrap().client_recv.clone();
     let client2_receiver = clients.get(1).unwrap().client_recv.clone();
     handles.push(client_only_listen_handle(client1_receiver));
     handles.push(client_only_listen_handle(client2_receiver));
@@ -259,7 +283,7 @@ pub fn test_double_chain_flood(){
     }));*/
 
 
-    send_packet(packet, clients.get(0).unwrap().client_send.get(&1).unwrap());
+    send_packet(packet, clients.first().unwrap().client_send.get(&1).unwrap());
 
     for i in handles {
         i.join().unwrap();
@@ -284,10 +308,10 @@ pub fn test_star_flood(){
         session_id: 0,
     };
 
-    send_packet(packet, clients.get(0).unwrap().client_send.get(&1).unwrap());
+    send_packet(packet, clients.first().unwrap().client_send.get(&1).unwrap());
 
 
-    let client_receiver = clients.get(0).unwrap().client_recv.clone();
+    let client_receiver = clients.first().unwrap().client_recv.clone();
     // handles.push(listen_handle(_sim_contr.event_recv, client_receiver));
     handles.push(client_only_listen_handle(client_receiver));
 
@@ -313,9 +337,9 @@ pub fn test_butterfly_flood(){
         session_id: 0,
     };
 
-    send_packet(packet, clients.get(0).unwrap().client_send.get(&1).unwrap());
+    send_packet(packet, clients.first().unwrap().client_send.get(&1).unwrap());
 
-    let client_receiver = clients.get(0).unwrap().client_recv.clone();
+    let client_receiver = clients.first().unwrap().client_recv.clone();
     // handles.push(listen_handle(_sim_contr.event_recv, client_receiver));
     handles.push(client_only_listen_handle(client_receiver));
 
@@ -340,9 +364,9 @@ pub fn test_tree_flood(){
         session_id: 0,
     };
 
-    send_packet(packet, clients.get(0).unwrap().client_send.get(&1).unwrap());
+    send_packet(packet, clients.first().unwrap().client_send.get(&1).unwrap());
 
-    let client_receiver = clients.get(0).unwrap().client_recv.clone();
+    let client_receiver = clients.first().unwrap().client_recv.clone();
     // handles.push(listen_handle(_sim_contr.event_recv, client_receiver));
     handles.push(client_only_listen_handle(client_receiver));
 
@@ -438,8 +462,8 @@ pub fn test_busy_network(){
     let packet = create_packet(vec![0,1,4,7,10,3,6,9,2,5,8,1,0]);
 
     for _i in 0..u8::MAX {
-        for (_, s) in &clients.get(0).unwrap().client_send {
-            if let Ok(_) = s.send(packet.clone()) {
+        for s in clients.first().unwrap().client_send.values() {
+            if s.send(packet.clone()).is_ok() {
                 // println!("Packet {:?} sent successfully!", packet);
             } else {
                 println!("Doesn't work");
@@ -447,7 +471,7 @@ pub fn test_busy_network(){
         }
     }
 
-    let client_receiver = clients.get(0).unwrap().client_recv.clone();
+    let client_receiver = clients.first().unwrap().client_recv.clone();
     // handles.push(listen_handle(_sim_contr.event_recv, client_receiver));
     handles.push(client_only_listen_handle(client_receiver));
 
@@ -456,3 +480,2005 @@ pub fn test_busy_network(){
         i.join().unwrap();
     }
 }
+
+
+//A drone with pdr 1.0 would drop every fragment, but without the pdr check in its pipeline it forwards them.
+pub fn test_custom_checks(){
+    let (d1_packet_sender, d1_packet_receiver) = unbounded::<Packet>();
+    let (c0_packet_sender, _c0_packet_receiver) = unbounded::<Packet>();
+    let (c2_packet_sender, c2_packet_receiver) = unbounded::<Packet>();
+    let (sc_sender, _sc_receiver) = unbounded();
+    let (_d1_command_sender, d1_command_receiver) = unbounded::<DroneCommand>();
+
+    let neighbour_d1 = HashMap::from([(0, c0_packet_sender), (2, c2_packet_sender)]);
+
+    let mut drone1 = SkyLinkDrone::new(
+        1,
+        sc_sender,
+        d1_command_receiver,
+        d1_packet_receiver,
+        neighbour_d1,
+        1.0)
+        .with_checks(CheckPipeline::default().without("pdr"));
+
+    thread::spawn(move || {
+        drone1.run();
+    });
+
+    d1_packet_sender.send(create_packet(vec![0,1,2])).unwrap();
+
+    let received = c2_packet_receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(received.routing_header.hop_index, 2);
+    println!("Fragment forwarded without the pdr check!");
+}
+
+//Two drones with the same seed and pdr must drop exactly the same fragments.
+pub fn test_seeded_drops(){
+    fn dropped_fragments(seed: u64) -> Vec<u64> {
+        let (d1_packet_sender, d1_packet_receiver) = unbounded::<Packet>();
+        let (c0_packet_sender, c0_packet_receiver) = unbounded::<Packet>();
+        let (c2_packet_sender, c2_packet_receiver) = unbounded::<Packet>();
+        let (sc_sender, _sc_receiver) = unbounded();
+        let (d1_command_sender, d1_command_receiver) = unbounded::<DroneCommand>();
+
+        let neighbour_d1 = HashMap::from([(0, c0_packet_sender), (2, c2_packet_sender)]);
+        let mut drone1 = SkyLinkDrone::new(
+            1,
+            sc_sender,
+            d1_command_receiver,
+            d1_packet_receiver,
+            neighbour_d1,
+            0.5)
+            .with_seed(seed);
+        let handle = thread::spawn(move || {
+            drone1.run();
+        });
+
+        for i in 0..100 {
+            let mut msg = create_packet(vec![0,1,2]);
+            if let PacketType::MsgFragment(ref mut fragment) = msg.pack_type {
+                fragment.fragment_index = i;
+            }
+            d1_packet_sender.send(msg).unwrap();
+        }
+        //Every fragment is either forwarded to 2 or nacked back to 0.
+        let mut dropped = Vec::new();
+        for _ in 0..100 {
+            select! {
+                recv(c0_packet_receiver) -> packet => {
+                    if let PacketType::Nack(nack) = packet.unwrap().pack_type {
+                        if let NackType::Dropped = nack.nack_type {
+                            dropped.push(nack.fragment_index);
+                        }
+                    }
+                }
+                recv(c2_packet_receiver) -> _packet => {}
+            }
+        }
+
+        d1_command_sender.send(DroneCommand::Crash).unwrap();
+        drop(d1_packet_sender);
+        handle.join().unwrap();
+        dropped
+    }
+
+    let first = dropped_fragments(42);
+    let second = dropped_fragments(42);
+    println!("Dropped fragments: {:?}", first);
+    assert!(!first.is_empty());
+    assert_eq!(first, second);
+}
+
+//A drop must be reported with a PacketDropped event and a NackGenerated, a flooding received while crashing with a PacketDiscarded.
+pub fn test_drop_events(){
+    let (d1_packet_sender, d1_packet_receiver) = unbounded::<Packet>();
+    let (c0_packet_sender, c0_packet_receiver) = unbounded::<Packet>();
+    let (c2_packet_sender, _c2_packet_receiver) = unbounded::<Packet>();
+    let (sc_sender, sc_receiver) = unbounded();
+    let (skylink_sender, skylink_receiver) = unbounded();
+    let (d1_command_sender, d1_command_receiver) = unbounded::<DroneCommand>();
+
+    let neighbour_d1 = HashMap::from([(0, c0_packet_sender), (2, c2_packet_sender)]);
+    let mut drone1 = SkyLinkDrone::new(
+        1,
+        sc_sender,
+        d1_command_receiver,
+        d1_packet_receiver,
+        neighbour_d1,
+        1.0)
+        .with_seed(0)
+        .with_event_channel(skylink_sender);
+    let handle = thread::spawn(move || {
+        drone1.run();
+    });
+
+    //A pdr of 1.0 still lets through 1 fragment out of 101, so I retry until the drop happens.
+    let mut dropped = None;
+    while dropped.is_none() {
+        d1_packet_sender.send(create_packet(vec![0,1,2])).unwrap();
+        c0_packet_receiver.recv_timeout(Duration::from_millis(100)).ok();
+        dropped = sc_receiver.try_iter().find_map(|event| match event {
+            DroneEvent::PacketDropped(packet) => Some(packet),
+            _ => None,
+        });
+    }
+    assert_eq!(dropped.unwrap().routing_header.hop_index, 1);
+    match skylink_receiver.recv_timeout(Duration::from_secs(1)).unwrap() {
+        SkyLinkEvent::NackGenerated { drone, nack_type: NackType::Dropped, .. } => assert_eq!(drone, 1),
+        event => panic!("Unexpected event {:?}", event),
+    }
+
+    d1_command_sender.send(DroneCommand::Crash).unwrap();
+    let flood_request = wg_2024::packet::FloodRequest{
+        flood_id: 1,
+        initiator_id: 0,
+        path_trace: vec![],
+    };
+    d1_packet_sender.send(Packet{
+        pack_type: PacketType::FloodRequest(flood_request),
+        routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+        session_id: 0,
+    }).unwrap();
+    drop(d1_packet_sender);
+    handle.join().unwrap();
+
+    let discarded = skylink_receiver.try_iter().any(|event| matches!(event,
+        SkyLinkEvent::PacketDiscarded { drone: 1, reason: DiscardReason::Crashing, .. }));
+    assert!(discarded);
+    println!("Drop events received!");
+}
+
+//Millions of floodings go through the cache, but it never holds more than its capacity.
+pub fn test_flood_cache_bounded(){
+    let capacity = NonZeroUsize::new(1024).unwrap();
+    let mut cache = FloodCache::new(capacity);
+    let clock = ManualClock::new();
+
+    let initiator = |flood_id: u64| (flood_id % 10) as NodeId;
+    for flood_id in 0..5_000_000u64 {
+        assert!(cache.insert(flood_id, initiator(flood_id), clock.now()));
+        //A flooding still in the cache is recognised.
+        assert!(!cache.insert(flood_id, initiator(flood_id), clock.now()));
+        assert!(cache.len() <= capacity.get());
+    }
+
+    //The newest floodings are remembered, the oldest ones are forgotten.
+    let oldest_kept = 5_000_000 - capacity.get() as u64;
+    assert!(cache.contains(4_999_999, initiator(4_999_999)));
+    assert!(cache.contains(oldest_kept, initiator(oldest_kept)));
+    assert!(!cache.contains(oldest_kept - 1, initiator(oldest_kept - 1)));
+    assert!(!cache.contains(0, 0));
+
+    //The floodings get older with the clock of the drone, not with the time of the system.
+    let mut expiring = FloodCache::new(capacity).with_max_age(Duration::from_millis(50));
+    expiring.insert(1, 0, clock.now());
+    thread::sleep(Duration::from_millis(100));
+    expiring.insert(2, 0, clock.now());
+    assert!(expiring.contains(1, 0));
+    clock.advance(Duration::from_millis(100));
+    expiring.insert(3, 0, clock.now());
+    assert!(!expiring.contains(1, 0));
+    assert!(!expiring.contains(2, 0));
+    assert_eq!(expiring.len(), 1);
+
+    //The capacity can be set in the config, but the cache can't be made to remember nothing.
+    let options = toml::from_str::<SimulationOptions>("flood_cache_capacity = 8\nflood_cache_max_age_ms = 50\n").unwrap();
+    assert_eq!(options.flood_cache().capacity().get(), 8);
+    assert!(toml::from_str::<SimulationOptions>("flood_cache_capacity = 0\n").is_err());
+    println!("Flood cache stayed bounded!");
+}
+
+//A small cache must still recognise a flooding that comes back while it's in flight.
+pub fn test_flood_cache_in_flight(){
+    let (d1_packet_sender, d1_packet_receiver) = unbounded::<Packet>();
+    let (c0_packet_sender, c0_packet_receiver) = unbounded::<Packet>();
+    let (c2_packet_sender, c2_packet_receiver) = unbounded::<Packet>();
+    let (sc_sender, _sc_receiver) = unbounded();
+    let (_d1_command_sender, d1_command_receiver) = unbounded::<DroneCommand>();
+
+    let neighbour_d1 = HashMap::from([(0, c0_packet_sender), (2, c2_packet_sender)]);
+    let mut drone1 = SkyLinkDrone::new(
+        1,
+        sc_sender,
+        d1_command_receiver,
+        d1_packet_receiver,
+        neighbour_d1,
+        0.0)
+        .with_flood_cache(FloodCache::new(NonZeroUsize::new(16).unwrap()));
+    thread::spawn(move || {
+        drone1.run();
+    });
+
+    let flood = |flood_id: u64, path_trace: Vec<(NodeId, NodeType)>| Packet {
+        pack_type: PacketType::FloodRequest(wg_2024::packet::FloodRequest {
+            flood_id,
+            initiator_id: 0,
+            path_trace,
+        }),
+        routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+        session_id: flood_id,
+    };
+
+    //Flooding 0 and some newer ones, less than the capacity, are forwarded to 2.
+    for flood_id in 0..10 {
+        d1_packet_sender.send(flood(flood_id, vec![(0, NodeType::Client)])).unwrap();
+        let forwarded = c2_packet_receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(matches!(forwarded.pack_type, PacketType::FloodRequest(_)));
+    }
+
+    //Flooding 0 comes back from 2: the drone answers instead of forwarding it again.
+    d1_packet_sender.send(flood(0, vec![(0, NodeType::Client), (3, NodeType::Drone), (2, NodeType::Client)])).unwrap();
+    let answer = c2_packet_receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert!(matches!(answer.pack_type, PacketType::FloodResponse(_)));
+    assert!(c0_packet_receiver.try_recv().is_err());
+    println!("In flight flooding recognised!");
+}
+
+//Steps the drones, moving the clock whenever they all wait, until the receiver gets a packet:
+//tells how long it took on the clock.
+fn step_until(drones: &mut [SkyLinkDrone], clock: &ManualClock, receiver: &Receiver<Packet>) -> (Duration, Packet) {
+    let mut elapsed = Duration::ZERO;
+    loop {
+        let mut wait = Duration::MAX;
+        for drone in drones.iter_mut() {
+            match drone.step() {
+                Step::Busy => wait = Duration::ZERO,
+                Step::Idle(idle) => wait = wait.min(idle),
+                Step::Stopped => panic!("Drone {} stopped", drone.get_id()),
+            }
+        }
+        if let Ok(packet) = receiver.try_recv() {
+            return (elapsed, packet);
+        }
+        assert!(wait < Duration::from_secs(10), "Nothing left to do after {:?}", elapsed);
+        clock.advance(wait);
+        elapsed += wait;
+    }
+}
+
+//Fragments on a slow link arrive after the delay, one every transmission time, until the link is changed at runtime.
+pub fn test_link_timing(){
+    //128 bytes at 1280 bytes per second take 100ms to be transmitted.
+    let slow_link = LinkParams {
+        delay: Duration::from_millis(200),
+        jitter: Duration::ZERO,
+        bandwidth: NonZeroU64::new(1280),
+    };
+    let clock = ManualClock::new();
+    let (d1, drone1) = drone_1(&[], |drone| drone.with_clock(clock.clone()).with_links(HashMap::from([(2, slow_link)])));
+    let mut drones = [drone1];
+
+    for _ in 0..3 {
+        d1.packet_send.send(create_packet(vec![0,1,2])).unwrap();
+    }
+    let mut elapsed = Duration::ZERO;
+    for i in 1..=3 {
+        elapsed += step_until(&mut drones, &clock, &d1.neighbours[&2]).0;
+        println!("Fragment {} arrived after {:?}", i, elapsed);
+        assert_eq!(elapsed, Duration::from_millis(200 + 100 * i));
+    }
+
+    //On an instant link, the fragment arrives without the clock moving.
+    d1.skylink_send.send(SkyLinkCommand::SetLink(2, LinkParams::default())).unwrap();
+    assert_eq!(drones[0].step(), Step::Busy);
+    d1.packet_send.send(create_packet(vec![0,1,2])).unwrap();
+    assert_eq!(step_until(&mut drones, &clock, &d1.neighbours[&2]).0, Duration::ZERO);
+
+    //A link whose delay can't be added to the clock is down: the fragment is nacked instead of panicking the drone.
+    let endless_link = LinkParams { delay: Duration::MAX, ..LinkParams::default() };
+    d1.skylink_send.send(SkyLinkCommand::SetLink(2, endless_link)).unwrap();
+    assert_eq!(drones[0].step(), Step::Busy);
+    d1.packet_send.send(create_packet(vec![0,1,2])).unwrap();
+    let (_, nack) = step_until(&mut drones, &clock, &d1.neighbours[&0]);
+    assert!(matches!(nack.pack_type, PacketType::Nack(Nack { nack_type: NackType::ErrorInRouting(2), .. })));
+    assert!(d1.neighbours[&2].try_recv().is_err());
+    println!("Link timing respected!");
+}
+
+//The links declared in the config file slow down a fragment going from client 0 to client 3.
+pub fn test_config_links(){
+    let config = parse_config("inputs/input_links.toml");
+    let options = parse_options("inputs/input_links.toml");
+    assert_eq!(options.seed, Some(42));
+
+    //Drones 1 and 2 as initialize builds them, but on a manual clock, between clients 0 and 3.
+    let mut packet_senders = HashMap::new();
+    let mut packet_receivers = HashMap::new();
+    for id in [0, 1, 2, 3] {
+        let (send, recv) = unbounded::<Packet>();
+        packet_senders.insert(id, send);
+        packet_receivers.insert(id, recv);
+    }
+    let (event_send, _event_recv) = unbounded();
+    let (skylink_event_send, _skylink_event_recv) = unbounded();
+    let clock = ManualClock::new();
+    let mut drones = config.drone.iter().map(|drone| {
+        let channels = DroneChannels {
+            event_send: event_send.clone(),
+            command_recv: never(),
+            packet_recv: packet_receivers.remove(&drone.id).unwrap(),
+            packet_send: drone.connected_node_ids.iter().map(|id| (*id, packet_senders[id].clone())).collect(),
+            skylink_event_send: skylink_event_send.clone(),
+            skylink_command_recv: never(),
+        };
+        build_drone(DroneOrigin::Config(drone), &options, 42, channels).with_clock(clock.clone())
+    }).collect::<Vec<SkyLinkDrone>>();
+
+    packet_senders[&1].send(create_packet(vec![0,1,2,3])).unwrap();
+    let (elapsed, _fragment) = step_until(&mut drones, &clock, &packet_receivers[&3]);
+    //100ms of transmission on the link 1-2, plus 50ms of delay (and up to 10ms of jitter), and 20ms on the link 2-3.
+    println!("Fragment arrived after {:?}", elapsed);
+    assert!(elapsed >= Duration::from_millis(170) && elapsed <= Duration::from_millis(180));
+
+    //A link with no bandwidth at all isn't a valid link.
+    assert!(toml::from_str::<SimulationOptions>("[[link]]\nfrom = 1\nto = 2\nbandwidth = 0\n").is_err());
+    assert!(toml::from_str::<SimulationOptions>("[[link]]\nfrom = 1\nto = 2\nbandwidth = 1\n").is_ok());
+    //Neither is a link that never delivers.
+    let link = |key: &str, value: u64| toml::from_str::<SimulationOptions>(&format!("[[link]]\nfrom = 1\nto = 2\n{} = {}\n", key, value));
+    assert!(link("delay_ms", MAX_LINK_DELAY_MS).is_ok());
+    assert!(link("delay_ms", MAX_LINK_DELAY_MS + 1).is_err());
+    assert!(link("jitter_ms", u64::MAX).is_err());
+}
+
+//Fragments going through a link with pdr 1.0 are dropped, while the other links use the drone's pdr.
+pub fn test_link_pdr(){
+    let (d1_packet_sender, d1_packet_receiver) = unbounded::<Packet>();
+    let (c0_packet_sender, c0_packet_receiver) = unbounded::<Packet>();
+    let (c2_packet_sender, c2_packet_receiver) = unbounded::<Packet>();
+    let (c3_packet_sender, c3_packet_receiver) = unbounded::<Packet>();
+    let (sc_sender, _sc_receiver) = unbounded();
+    let (_d1_command_sender, d1_command_receiver) = unbounded::<DroneCommand>();
+    let (d1_skylink_sender, d1_skylink_receiver) = unbounded::<SkyLinkCommand>();
+
+    let neighbour_d1 = HashMap::from([(0, c0_packet_sender), (2, c2_packet_sender), (3, c3_packet_sender)]);
+    let mut drone1 = SkyLinkDrone::new(
+        1,
+        sc_sender,
+        d1_command_receiver,
+        d1_packet_receiver,
+        neighbour_d1,
+        0.0)
+        .with_seed(7)
+        .with_command_channel(d1_skylink_receiver)
+        .with_link_pdrs(HashMap::from([(2, 1.0)]));
+    thread::spawn(move || {
+        drone1.run();
+    });
+
+    for _ in 0..50 {
+        d1_packet_sender.send(create_packet(vec![0,1,2])).unwrap();
+        d1_packet_sender.send(create_packet(vec![0,1,3])).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    //A pdr of 1.0 still lets through 1 fragment out of 101.
+    assert!(c2_packet_receiver.try_iter().count() <= 2);
+    assert_eq!(c3_packet_receiver.try_iter().count(), 50);
+    assert!(c0_packet_receiver.try_iter().count() >= 48);
+
+    d1_skylink_sender.send(SkyLinkCommand::SetLinkPdr(2, None)).unwrap();
+    for _ in 0..50 {
+        d1_packet_sender.send(create_packet(vec![0,1,2])).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(c2_packet_receiver.try_iter().count(), 50);
+    println!("Link pdr respected!");
+}
+
+//With the scheduler, acks waiting behind fragments are forwarded first, but every 3 acks a fragment gets through.
+pub fn test_priority_scheduler(){
+    let (d1_packet_sender, d1_packet_receiver) = unbounded::<Packet>();
+    let (c0_packet_sender, _c0_packet_receiver) = unbounded::<Packet>();
+    let (c2_packet_sender, c2_packet_receiver) = unbounded::<Packet>();
+    let (sc_sender, _sc_receiver) = unbounded();
+    let (_d1_command_sender, d1_command_receiver) = unbounded::<DroneCommand>();
+
+    //Everything is already waiting when the drone starts.
+    for _ in 0..10 {
+        d1_packet_sender.send(create_packet(vec![0,1,2])).unwrap();
+    }
+    for i in 0..10 {
+        d1_packet_sender.send(Packet {
+            pack_type: PacketType::Ack(Ack { fragment_index: i }),
+            routing_header: SourceRoutingHeader { hop_index: 1, hops: vec![0,1,2] },
+            session_id: 1,
+        }).unwrap();
+    }
+
+    let neighbour_d1 = HashMap::from([(0, c0_packet_sender), (2, c2_packet_sender)]);
+    let mut drone1 = SkyLinkDrone::new(
+        1,
+        sc_sender,
+        d1_command_receiver,
+        d1_packet_receiver,
+        neighbour_d1,
+        0.0)
+        .with_scheduler(PriorityScheduler::new(3));
+    thread::spawn(move || {
+        drone1.run();
+    });
+
+    let mut order = String::new();
+    for _ in 0..20 {
+        match c2_packet_receiver.recv_timeout(Duration::from_secs(1)).unwrap().pack_type {
+            PacketType::MsgFragment(_) => order.push('F'),
+            _ => order.push('A'),
+        }
+    }
+    println!("Forwarding order: {}", order);
+    assert_eq!(order, "AAAFAAAFAAAFAFFFFFFF");
+}
+
+
+//The ends of the channels of drone 1 that the test keeps.
+struct Drone1 {
+    packet_send: Sender<Packet>,
+    //The drone can't work once its controller is gone, so the command channels are kept even if unused.
+    command_send: Sender<DroneCommand>,
+    skylink_send: Sender<SkyLinkCommand>,
+    neighbours: HashMap<NodeId, Receiver<Packet>>,
+    events: Receiver<SkyLinkEvent>,
+    _controller_events: Receiver<DroneEvent>,
+}
+
+//Drone 1 between 0 and 2, and the `extra` neighbours: a new drone with pdr 0, as `configure` makes it.
+fn drone_1(extra: &[NodeId], configure: impl FnOnce(SkyLinkDrone) -> SkyLinkDrone) -> (Drone1, SkyLinkDrone) {
+    drone_1_from(extra, |controller_send, command_recv, packet_recv, packet_send| {
+        configure(SkyLinkDrone::new(1, controller_send, command_recv, packet_recv, packet_send, 0.0))
+    })
+}
+
+//Like drone_1, but `build` makes the drone out of its channels, e.g. to restore it.
+fn drone_1_from(extra: &[NodeId], build: impl FnOnce(Sender<DroneEvent>, Receiver<DroneCommand>, Receiver<Packet>, HashMap<NodeId, Sender<Packet>>) -> SkyLinkDrone) -> (Drone1, SkyLinkDrone) {
+    let (d1_packet_sender, d1_packet_receiver) = unbounded::<Packet>();
+    let (sc_sender, sc_receiver) = unbounded();
+    let (d1_command_sender, d1_command_receiver) = unbounded::<DroneCommand>();
+    let (d1_skylink_sender, d1_skylink_receiver) = unbounded::<SkyLinkCommand>();
+    let (event_sender, event_receiver) = unbounded::<SkyLinkEvent>();
+
+    let mut neighbour_d1 = HashMap::new();
+    let mut receivers = HashMap::new();
+    for id in [0, 2].iter().chain(extra) {
+        let (send, recv) = unbounded::<Packet>();
+        neighbour_d1.insert(*id, send);
+        receivers.insert(*id, recv);
+    }
+    let drone1 = build(sc_sender, d1_command_receiver, d1_packet_receiver, neighbour_d1)
+        .with_command_channel(d1_skylink_receiver)
+        .with_event_channel(event_sender);
+    let d1 = Drone1 {
+        packet_send: d1_packet_sender,
+        command_send: d1_command_sender,
+        skylink_send: d1_skylink_sender,
+        neighbours: receivers,
+        events: event_receiver,
+        _controller_events: sc_receiver,
+    };
+    (d1, drone1)
+}
+
+//Runs the drone on a thread of its own, the receiver tells when it's over.
+fn run_drone(mut drone: SkyLinkDrone) -> Receiver<()> {
+    let (done_sender, done_receiver) = unbounded();
+    thread::spawn(move || {
+        drone.run();
+        let _ = done_sender.send(());
+    });
+    done_receiver
+}
+
+fn expect_fault(event_receiver: &Receiver<SkyLinkEvent>) -> (FaultKind, Packet) {
+    match event_receiver.recv_timeout(Duration::from_secs(1)).unwrap() {
+        SkyLinkEvent::FaultInjected { drone, fault, packet } => {
+            assert_eq!(drone, 1);
+            (fault, packet)
+        },
+        other => panic!("Expected a fault, got {:?}", other),
+    }
+}
+
+fn fragment_with_index(index: u64) -> Packet {
+    let mut packet = create_packet(vec![0,1,2]);
+    if let PacketType::MsgFragment(fragment) = &mut packet.pack_type {
+        fragment.fragment_index = index;
+    }
+    packet
+}
+
+//Every fragment reaches the next hop twice.
+pub fn test_fault_duplicate(){
+    let (d1, drone1) = drone_1(&[3], |drone| drone.with_seed(11).with_fault_profile(FaultProfile { duplicate: 1.0, ..FaultProfile::default() }));
+    run_drone(drone1);
+
+    d1.packet_send.send(create_packet(vec![0,1,2])).unwrap();
+    assert_eq!(expect_fault(&d1.events).0, FaultKind::Duplicated);
+    for _ in 0..2 {
+        let packet = d1.neighbours[&2].recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(matches!(packet.pack_type, PacketType::MsgFragment(_)));
+    }
+    thread::sleep(Duration::from_millis(100));
+    assert!(d1.neighbours[&2].try_recv().is_err());
+    println!("Fragment duplicated!");
+}
+
+//A fragment held back is overtaken by the one sent after it, once the fault is turned off.
+pub fn test_fault_reorder(){
+    let (d1, drone1) = drone_1(&[3], |drone| drone.with_seed(11).with_fault_profile(FaultProfile {
+        reorder: 1.0,
+        reorder_delay: Duration::from_millis(200),
+        ..FaultProfile::default()
+    }));
+    run_drone(drone1);
+
+    d1.packet_send.send(fragment_with_index(0)).unwrap();
+    assert_eq!(expect_fault(&d1.events).0, FaultKind::Reordered);
+    d1.skylink_send.send(SkyLinkCommand::SetFaultProfile(None)).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    d1.packet_send.send(fragment_with_index(1)).unwrap();
+
+    let mut order = Vec::new();
+    for _ in 0..2 {
+        if let PacketType::MsgFragment(fragment) = d1.neighbours[&2].recv_timeout(Duration::from_secs(1)).unwrap().pack_type {
+            order.push(fragment.fragment_index);
+        }
+    }
+    assert_eq!(order, vec![1, 0]);
+    assert!(d1.events.try_recv().is_err());
+    println!("Fragments reordered!");
+}
+
+//The payload that arrives differs from the one sent by a single bit, in the reported position.
+pub fn test_fault_corrupt(){
+    let (d1, drone1) = drone_1(&[3], |drone| drone.with_seed(11).with_fault_profile(FaultProfile { corrupt: 1.0, ..FaultProfile::default() }));
+    run_drone(drone1);
+
+    d1.packet_send.send(create_packet(vec![0,1,2])).unwrap();
+    let (fault, _) = expect_fault(&d1.events);
+    let FaultKind::Corrupted { index } = fault else {
+        panic!("Expected a corruption, got {:?}", fault);
+    };
+    let PacketType::MsgFragment(fragment) = d1.neighbours[&2].recv_timeout(Duration::from_secs(1)).unwrap().pack_type else {
+        panic!("Expected a fragment");
+    };
+    for (i, byte) in fragment.data.iter().enumerate() {
+        if i == index {
+            assert_eq!((byte ^ 1).count_ones(), 1);
+        } else {
+            assert_eq!(*byte, 1);
+        }
+    }
+    println!("Byte {} corrupted!", index);
+}
+
+//The fragment doesn't reach its next hop, but another neighbour, with the routing header untouched.
+pub fn test_fault_misroute(){
+    let (d1, drone1) = drone_1(&[3], |drone| drone.with_seed(11).with_fault_profile(FaultProfile { misroute: 1.0, ..FaultProfile::default() }));
+    run_drone(drone1);
+
+    d1.packet_send.send(create_packet(vec![0,1,2])).unwrap();
+    let (fault, _) = expect_fault(&d1.events);
+    let FaultKind::Misrouted { intended, actual } = fault else {
+        panic!("Expected a misrouting, got {:?}", fault);
+    };
+    assert_eq!(intended, 2);
+    assert_ne!(actual, 2);
+    let packet = d1.neighbours[&actual].recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(packet.routing_header.hops, vec![0,1,2]);
+    assert_eq!(packet.routing_header.hop_index, 2);
+    assert!(d1.neighbours[&2].try_recv().is_err());
+    println!("Fragment misrouted to {}!", actual);
+}
+
+//Acks disappear, while fragments go through as usual.
+pub fn test_fault_drop_acks(){
+    let (d1, drone1) = drone_1(&[3], |drone| drone.with_seed(11).with_fault_profile(FaultProfile { drop_acks: 1.0, ..FaultProfile::default() }));
+    run_drone(drone1);
+
+    d1.packet_send.send(Packet {
+        pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        routing_header: SourceRoutingHeader { hop_index: 1, hops: vec![2,1,0] },
+        session_id: 1,
+    }).unwrap();
+    d1.packet_send.send(create_packet(vec![0,1,2])).unwrap();
+
+    assert_eq!(expect_fault(&d1.events).0, FaultKind::AckDropped);
+    d1.neighbours[&2].recv_timeout(Duration::from_secs(1)).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert!(d1.neighbours[&0].try_recv().is_err());
+    println!("Ack dropped!");
+}
+
+//Nacks reach the next hop only after the delay of the profile.
+pub fn test_fault_delay_nacks(){
+    let (d1, drone1) = drone_1(&[3], |drone| drone.with_seed(11).with_fault_profile(FaultProfile {
+        delay_nacks: 1.0,
+        nack_delay: Duration::from_millis(300),
+        ..FaultProfile::default()
+    }));
+    run_drone(drone1);
+
+    let start = Instant::now();
+    d1.packet_send.send(Packet {
+        pack_type: PacketType::Nack(Nack { fragment_index: 0, nack_type: NackType::Dropped }),
+        routing_header: SourceRoutingHeader { hop_index: 1, hops: vec![2,1,0] },
+        session_id: 1,
+    }).unwrap();
+
+    assert_eq!(expect_fault(&d1.events).0, FaultKind::NackDelayed);
+    let nack = d1.neighbours[&0].recv_timeout(Duration::from_secs(1)).unwrap();
+    assert!(matches!(nack.pack_type, PacketType::Nack(_)));
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    println!("Nack delayed by {:?}!", start.elapsed());
+}
+
+//The statistics gathered from every drone account for the fragments forwarded and dropped, the nacks and the flooding.
+pub fn test_drone_stats(){
+    let (sim_contr, clients, _handles) = test_initialize("inputs/input_generic_nack.toml");
+    let client = clients.first().unwrap();
+
+    for _ in 0..10 {
+        client.client_send[&11].send(create_packet(vec![1,11,12,21])).unwrap();
+    }
+    client.client_send[&11].send(Packet {
+        pack_type: PacketType::FloodRequest(FloodRequest {
+            flood_id: 1,
+            initiator_id: 1,
+            path_trace: vec![(1, NodeType::Client)],
+        }),
+        routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+        session_id: 1,
+    }).unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    //The controller gets the seed of the simulation, and the statistics of both drones.
+    let seed = sim_contr.seed;
+    let mut sim_contr = sim_contr.into_sim_contr();
+    assert_eq!(sim_contr.get_seed(), seed);
+    let stats = sim_contr.collect_stats();
+    assert_eq!(stats.len(), 2);
+    let (d11, d12) = (&stats[&11], &stats[&12]);
+    println!("Drone 11: {:?}", d11);
+    println!("Drone 12: {:?}", d12);
+
+    //Drone 12 has pdr 1.0, so it drops (almost) every fragment, and drone 11 brings the nacks back to the client.
+    let dropped = d12.per_neighbour[&21].dropped;
+    assert_eq!(d11.per_neighbour[&12].forwarded, 10 + 1);
+    assert_eq!(d12.per_kind[&PacketKind::MsgFragment].forwarded + dropped, 10);
+    assert_eq!(d12.per_neighbour[&21].nacked.dropped, dropped);
+    assert_eq!(d12.per_kind[&PacketKind::MsgFragment].nacked.dropped, dropped);
+    assert_eq!(d12.total().nacked.total(), dropped);
+    assert_eq!(d11.per_kind[&PacketKind::Nack].forwarded, dropped);
+    assert_eq!(d11.total().nacked.total(), 0);
+    assert_eq!((d11.floods_seen, d12.floods_seen), (1, 1));
+    assert_eq!(d11.total().shortcut + d12.total().shortcut, 0);
+    println!("Statistics collected!");
+}
+
+fn ack_to_0() -> Packet {
+    Packet {
+        pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        routing_header: SourceRoutingHeader { hop_index: 1, hops: vec![2,1,0] },
+        session_id: 1,
+    }
+}
+
+fn expect_crashed(event_receiver: &Receiver<SkyLinkEvent>) -> bool {
+    loop {
+        if let SkyLinkEvent::Crashed { drone, forced } = event_receiver.recv_timeout(Duration::from_secs(1)).unwrap() {
+            assert_eq!(drone, 1);
+            return forced;
+        }
+    }
+}
+
+//A neighbour that never drops its channel can't keep the crashed drone alive past its deadline,
+//and the ack waiting in the channel is still forwarded.
+pub fn test_crash_deadline(){
+    let (d1, drone1) = drone_1(&[], |drone| drone.with_crash_deadline(Some(Duration::from_millis(300))));
+    let done_receiver = run_drone(drone1);
+
+    let start = Instant::now();
+    d1.command_send.send(DroneCommand::Crash).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    d1.packet_send.send(ack_to_0()).unwrap();
+
+    done_receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    assert!(matches!(d1.neighbours[&0].try_recv().unwrap().pack_type, PacketType::Ack(_)));
+    assert!(expect_crashed(&d1.events));
+    println!("Crashed drone stopped after {:?}!", start.elapsed());
+}
+
+//A neighbour that keeps sending packets to the crashed drone doesn't hold it back either.
+pub fn test_crash_spamming_neighbour(){
+    let (d1, drone1) = drone_1(&[], |drone| drone.with_crash_deadline(Some(Duration::from_millis(300))));
+    let done_receiver = run_drone(drone1);
+
+    let spammer = thread::spawn(move || {
+        //It goes on until the drone is gone.
+        let mut sent = 0;
+        while d1.packet_send.send(ack_to_0()).is_ok() && sent < 1_000_000 {
+            sent += 1;
+        }
+        sent
+    });
+    thread::sleep(Duration::from_millis(50));
+    let start = Instant::now();
+    d1.command_send.send(DroneCommand::Crash).unwrap();
+
+    //The acks already in the channel at the deadline are still forwarded, it takes a while with that many.
+    done_receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    println!("Crashed drone stopped after {:?}, with {} acks forwarded!", start.elapsed(), d1.neighbours[&0].try_iter().count());
+    assert!(expect_crashed(&d1.events));
+    spammer.join().unwrap();
+}
+
+//When every neighbour drops its channel, the drone stops without waiting for the deadline.
+pub fn test_crash_clean(){
+    let (d1, drone1) = drone_1(&[], |drone| drone.with_crash_deadline(Some(Duration::from_secs(10))));
+    let done_receiver = run_drone(drone1);
+
+    d1.command_send.send(DroneCommand::Crash).unwrap();
+    d1.packet_send.send(ack_to_0()).unwrap();
+    drop(d1.packet_send);
+
+    done_receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert!(matches!(d1.neighbours[&0].try_recv().unwrap().pack_type, PacketType::Ack(_)));
+    assert!(!expect_crashed(&d1.events));
+
+    //Without a deadline, the drone waits for its neighbours as long as it takes.
+    let (d1, drone1) = drone_1(&[], |drone| drone.with_crash_deadline(None));
+    let done_receiver = run_drone(drone1);
+    d1.command_send.send(DroneCommand::Crash).unwrap();
+    assert!(done_receiver.recv_timeout(Duration::from_millis(500)).is_err());
+    drop(d1.packet_send);
+    done_receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    println!("Crashed drones stopped!");
+}
+
+//A neighbour that keeps its channel and leaves packets in it at the deadline: the drone still handles them
+//before stopping, so the ack gets through, the fragment is nacked and the flooding is reported as discarded.
+pub fn test_crash_deadline_drain(){
+    let clock = ManualClock::new();
+    let (d1, mut drone1) = drone_1(&[], |drone| drone.with_clock(clock.clone()).with_crash_deadline(Some(Duration::from_millis(100))));
+
+    d1.command_send.send(DroneCommand::Crash).unwrap();
+    drone1.step();
+    assert_eq!(drone1.step(), Step::Idle(Duration::from_millis(100)));
+
+    //Everything is still in the channel when the deadline expires.
+    d1.packet_send.send(ack_to_0()).unwrap();
+    d1.packet_send.send(ack_to_0()).unwrap();
+    d1.packet_send.send(create_packet(vec![2,1,0])).unwrap();
+    d1.packet_send.send(Packet {
+        pack_type: PacketType::FloodRequest(FloodRequest {
+            flood_id: 1,
+            initiator_id: 2,
+            path_trace: vec![(2, NodeType::Drone)],
+        }),
+        routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+        session_id: 1,
+    }).unwrap();
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(drone1.step(), Step::Stopped);
+
+    assert_eq!(d1.neighbours[&0].try_iter().filter(|packet| matches!(packet.pack_type, PacketType::Ack(_))).count(), 2);
+    assert!(matches!(d1.neighbours[&2].try_recv().unwrap().pack_type, PacketType::Nack(Nack { nack_type: NackType::ErrorInRouting(1), .. })));
+    let events = d1.events.try_iter().collect::<Vec<SkyLinkEvent>>();
+    assert!(events.iter().any(|event| matches!(event, SkyLinkEvent::NackGenerated { nack_type: NackType::ErrorInRouting(1), .. })));
+    assert!(events.iter().any(|event| matches!(event, SkyLinkEvent::PacketDiscarded { reason: DiscardReason::Crashing, .. })));
+    assert!(matches!(events.last(), Some(SkyLinkEvent::Crashed { drone: 1, forced: true })));
+    drop(d1.packet_send);
+    println!("Packets left at the deadline handled!");
+}
+
+//The drone can be driven from the test itself, one step at a time, without any thread.
+pub fn test_step(){
+    let (d1, mut drone1) = drone_1(&[], |drone| drone.with_crash_deadline(Some(Duration::from_millis(100))));
+
+    assert_eq!(drone1.step(), Step::Idle(Duration::MAX));
+    d1.packet_send.send(create_packet(vec![0,1,2])).unwrap();
+    assert!(d1.neighbours[&2].try_recv().is_err());
+    assert_eq!(drone1.step(), Step::Busy);
+    assert!(d1.neighbours[&2].try_recv().is_ok());
+    assert_eq!(drone1.step(), Step::Idle(Duration::MAX));
+
+    //Once crashed, the drone tells how long it will wait for its neighbours.
+    d1.command_send.send(DroneCommand::Crash).unwrap();
+    assert_eq!(drone1.step(), Step::Busy);
+    let Step::Idle(wait) = drone1.step() else {
+        panic!("The crashed drone should be waiting");
+    };
+    assert!(wait <= Duration::from_millis(100));
+    thread::sleep(wait);
+    assert_eq!(drone1.step(), Step::Stopped);
+    println!("Drone stepped!");
+}
+
+//A chain of 254 drones between client 0 and client 255, the largest network NodeIds allow.
+//The drones are given to the pool, the channel to the first drone and the one of client 255 are given back.
+fn chain_on_pool(pool: &mut DronePool) -> (Sender<Packet>, Receiver<Packet>, Vec<Sender<DroneCommand>>) {
+    let mut senders = HashMap::new();
+    let mut receivers = HashMap::new();
+    for id in 0..=255u8 {
+        let (send, recv) = unbounded::<Packet>();
+        senders.insert(id, send);
+        receivers.insert(id, recv);
+    }
+    let (sc_sender, _sc_receiver) = unbounded();
+    let mut command_senders = Vec::new();
+    for id in 1..=254u8 {
+        let (command_sender, command_receiver) = unbounded::<DroneCommand>();
+        command_senders.push(command_sender);
+        let neighbours = HashMap::from([(id - 1, senders[&(id - 1)].clone()), (id + 1, senders[&(id + 1)].clone())]);
+        pool.add(SkyLinkDrone::new(
+            id,
+            sc_sender.clone(),
+            command_receiver,
+            receivers.remove(&id).unwrap(),
+            neighbours,
+            0.0)
+            .with_crash_deadline(Some(Duration::from_millis(100))));
+    }
+    (senders[&1].clone(), receivers.remove(&255).unwrap(), command_senders)
+}
+
+//8 networks of 256 nodes run together on 4 threads, then every drone crashes and the pool empties.
+pub fn test_worker_pool(){
+    let mut pool = DronePool::new(4);
+    let chains = (0..8).map(|_| chain_on_pool(&mut pool)).collect::<Vec<_>>();
+
+    let start = Instant::now();
+    let hops = (0..=255u8).collect::<Vec<NodeId>>();
+    for (first_drone, _, _) in chains.iter() {
+        for _ in 0..10 {
+            first_drone.send(create_packet(hops.clone())).unwrap();
+        }
+    }
+    for (_, last_client, _) in chains.iter() {
+        for _ in 0..10 {
+            let packet = last_client.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(packet.routing_header.hop_index, 255);
+        }
+    }
+    println!("80 fragments crossed 254 drones each in {:?}, with {} threads", start.elapsed(), pool.workers());
+
+    for (_, _, command_senders) in chains.iter() {
+        for command_sender in command_senders {
+            command_sender.send(DroneCommand::Crash).unwrap();
+        }
+    }
+    let start = Instant::now();
+    pool.join();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    println!("Every drone of the pool stopped in {:?}!", start.elapsed());
+}
+
+//On a pool of one worker, drone 1 crashes with a fragment on a link of 2s, while drone 5 keeps forwarding:
+//the worker doesn't wait for the link, it goes on with drone 5 and comes back to drone 1 when the fragment arrives.
+pub fn test_pool_crash_links(){
+    let mut pool = DronePool::new(1);
+    let (d1_packet_sender, d1_packet_receiver) = unbounded::<Packet>();
+    let (c0_packet_sender, c0_packet_receiver) = unbounded::<Packet>();
+    let (c2_packet_sender, _c2_packet_receiver) = unbounded::<Packet>();
+    let (d1_command_sender, d1_command_receiver) = unbounded::<DroneCommand>();
+    let (d5_packet_sender, d5_packet_receiver) = unbounded::<Packet>();
+    let (c4_packet_sender, _c4_packet_receiver) = unbounded::<Packet>();
+    let (c6_packet_sender, c6_packet_receiver) = unbounded::<Packet>();
+    let (d5_command_sender, d5_command_receiver) = unbounded::<DroneCommand>();
+    let (sc_sender, _sc_receiver) = unbounded();
+    let (event_sender, event_receiver) = unbounded::<SkyLinkEvent>();
+
+    let mut drone1 = SkyLinkDrone::new(1, sc_sender.clone(), d1_command_receiver, d1_packet_receiver, HashMap::from([(0, c0_packet_sender), (2, c2_packet_sender)]), 0.0)
+        .with_event_channel(event_sender)
+        .with_links(HashMap::from([(0, LinkParams { delay: Duration::from_secs(2), ..LinkParams::default() })]));
+    d1_packet_sender.send(create_packet(vec![2,1,0])).unwrap();
+    assert_eq!(drone1.step(), Step::Busy);
+    d1_command_sender.send(DroneCommand::Crash).unwrap();
+    drop(d1_packet_sender);
+    assert_eq!(drone1.step(), Step::Busy);
+    //Every channel is gone, but the fragment is still on the link: the drone says how long it needs instead of waiting.
+    assert!(matches!(drone1.step(), Step::Idle(wait) if wait > Duration::ZERO && wait <= Duration::from_secs(2)));
+    assert!(matches!(drone1.step(), Step::Idle(_)));
+    assert!(c0_packet_receiver.try_recv().is_err());
+    assert!(!event_receiver.try_iter().any(|event| matches!(event, SkyLinkEvent::Crashed { .. })));
+
+    pool.add(drone1);
+    pool.add(SkyLinkDrone::new(5, sc_sender, d5_command_receiver, d5_packet_receiver, HashMap::from([(4, c4_packet_sender), (6, c6_packet_sender)]), 0.0));
+    for _ in 0..5 {
+        d5_packet_sender.send(create_packet(vec![4,5,6])).unwrap();
+        c6_packet_receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+    //Drone 5 got through before the link of drone 1 delivered, so the worker wasn't sleeping on it.
+    assert!(c0_packet_receiver.try_recv().is_err());
+
+    let packet = c0_packet_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(packet.routing_header.hop_index, 2);
+    assert!(matches!(event_receiver.recv_timeout(Duration::from_secs(1)).unwrap(), SkyLinkEvent::Crashed { drone: 1, forced: false }));
+
+    d5_command_sender.send(DroneCommand::Crash).unwrap();
+    drop(d5_packet_sender);
+    pool.join();
+    println!("Drone 5 kept forwarding while drone 1 emptied its link!");
+}
+
+//Every channel to drone 1 is dropped while it runs on a pool of one worker, without a crash:
+//the drone waits without waking the worker up, drone 5 keeps forwarding, and drone 1 still stops once crashed.
+pub fn test_pool_closed_channel(){
+    let mut pool = DronePool::new(1);
+    let (d1_packet_sender, d1_packet_receiver) = unbounded::<Packet>();
+    let (c0_packet_sender, _c0_packet_receiver) = unbounded::<Packet>();
+    let (d1_command_sender, d1_command_receiver) = unbounded::<DroneCommand>();
+    let (d5_packet_sender, d5_packet_receiver) = unbounded::<Packet>();
+    let (c4_packet_sender, _c4_packet_receiver) = unbounded::<Packet>();
+    let (c6_packet_sender, c6_packet_receiver) = unbounded::<Packet>();
+    let (d5_command_sender, d5_command_receiver) = unbounded::<DroneCommand>();
+    let (sc_sender, _sc_receiver) = unbounded();
+    let (event_sender, event_receiver) = unbounded::<SkyLinkEvent>();
+
+    let mut drone1 = SkyLinkDrone::new(1, sc_sender.clone(), d1_command_receiver, d1_packet_receiver, HashMap::from([(0, c0_packet_sender)]), 0.0)
+        .with_event_channel(event_sender);
+    drop(d1_packet_sender);
+    //The closed channel is noticed once, then the drone has nothing to do until a command arrives.
+    assert_eq!(drone1.step(), Step::Idle(Duration::MAX));
+    assert_eq!(drone1.step(), Step::Idle(Duration::MAX));
+    pool.add(drone1);
+    pool.add(SkyLinkDrone::new(5, sc_sender, d5_command_receiver, d5_packet_receiver, HashMap::from([(4, c4_packet_sender), (6, c6_packet_sender)]), 0.0));
+
+    for _ in 0..5 {
+        d5_packet_sender.send(create_packet(vec![4,5,6])).unwrap();
+        c6_packet_receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+
+    d1_command_sender.send(DroneCommand::Crash).unwrap();
+    assert!(matches!(event_receiver.recv_timeout(Duration::from_secs(1)).unwrap(), SkyLinkEvent::Crashed { drone: 1, forced: false }));
+    d5_command_sender.send(DroneCommand::Crash).unwrap();
+    drop(d5_packet_sender);
+    pool.join();
+    println!("Drone 1 waited without its channel!");
+}
+
+//With `workers` in the config file, the drones run on the pool and the handles are the ones of its threads.
+pub fn test_config_pool(){
+    let (sim_contr, clients, handles) = test_initialize("inputs/input_pool.toml");
+    assert_eq!(sim_contr.pool.as_ref().map(DronePool::workers), Some(2));
+    assert_eq!(handles.len(), 2);
+
+    send_packet(create_packet(vec![0,1,2,3]), clients.first().unwrap().client_send.get(&1).unwrap());
+    let packet = clients.get(1).unwrap().client_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(packet.routing_header.hop_index, 3);
+    println!("Fragment forwarded by the pool!");
+}
+
+//Sends fragments and a flooding from client 5 of inputs/input.toml, whose drones drop some fragments.
+fn des_busy_run(engine: &mut DesEngine) -> Vec<String> {
+    for i in 0..200 {
+        engine.send(Duration::from_millis(i), 5, 1, create_packet(vec![5,1,2,4]));
+    }
+    engine.send(Duration::from_millis(50), 5, 1, Packet {
+        pack_type: PacketType::FloodRequest(FloodRequest {
+            flood_id: 1,
+            initiator_id: 5,
+            path_trace: vec![(5, NodeType::Client)],
+        }),
+        routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+        session_id: 1,
+    });
+    engine.run();
+    engine.trace().iter().map(|entry| format!("{:?}", entry)).collect()
+}
+
+//The same inputs give exactly the same trace, while another seed gives other drops.
+pub fn test_des_deterministic(){
+    let first = des_busy_run(&mut DesEngine::from_file("inputs/input.toml"));
+    let second = des_busy_run(&mut DesEngine::from_file("inputs/input.toml"));
+    println!("{} entries in the trace", first.len());
+    assert_eq!(first, second);
+
+    let options = SimulationOptions { seed: Some(1), ..SimulationOptions::default() };
+    let mut engine = DesEngine::new(parse_config("inputs/input.toml"), &options);
+    assert_eq!(engine.get_seed(), 1);
+    let other_seed = des_busy_run(&mut engine);
+    assert_ne!(first, other_seed);
+    println!("Simulation replayed!");
+}
+
+//The links of the config file take virtual time: 100ms of transmission and 50ms (plus up to 10ms of jitter)
+//of delay on the link 1-2, then 20ms on the link 2-3.
+pub fn test_des_virtual_time(){
+    let mut engine = DesEngine::from_file("inputs/input_links.toml");
+    engine.send(Duration::ZERO, 0, 1, create_packet(vec![0,1,2,3]));
+
+    engine.run_until(Duration::from_millis(169));
+    assert!(engine.delivered_to(3).is_empty());
+    assert_eq!(engine.now(), Duration::from_millis(169));
+    engine.run();
+    assert_eq!(engine.delivered_to(3).len(), 1);
+    println!("Fragment arrived at virtual time {:?}", engine.now());
+    assert!(engine.now() >= Duration::from_millis(170) && engine.now() < Duration::from_millis(180));
+
+    //Once drone 1 drops everything, the next fragment comes back to client 0 as a nack, without taking any time.
+    let now = engine.now();
+    engine.set_pdr(now, 1, 1.0);
+    engine.send(now, 0, 1, create_packet(vec![0,1,2,3]));
+    engine.run();
+    assert!(engine.trace().iter().any(|entry| matches!(entry.record, Record::PdrChanged { drone: 1, .. })));
+    let delivered = engine.delivered_to(0);
+    assert_eq!(delivered.len(), 1);
+    assert!(matches!(&delivered[0].pack_type, PacketType::Nack(nack) if nack.nack_type == NackType::Dropped));
+    assert_eq!(engine.delivered_to(3).len(), 1);
+    assert_eq!(engine.now(), now);
+}
+
+//Once drone 2 crashed, drone 1 can't reach it anymore and sends a nack back to client 0.
+pub fn test_des_crash(){
+    let mut engine = DesEngine::from_file("inputs/input_generic_fragment_forward.toml");
+    engine.crash(Duration::ZERO, 2);
+    engine.send(Duration::from_millis(1), 0, 1, create_packet(vec![0,1,2,3]));
+    engine.run();
+
+    assert!(engine.trace().iter().any(|entry| matches!(entry.record, Record::SkyLink(SkyLinkEvent::Crashed { drone: 2, forced: false }))));
+    let delivered = engine.delivered_to(0);
+    assert_eq!(delivered.len(), 1);
+    match &delivered[0].pack_type {
+        PacketType::Nack(nack) => assert!(matches!(nack.nack_type, NackType::ErrorInRouting(_))),
+        other => panic!("Expected a nack, got {:?}", other),
+    }
+    assert!(engine.delivered_to(3).is_empty());
+    println!("Crash simulated!");
+}
+
+//The Ack drone 2 can't route goes through the engine, which doesn't hand it back to drone 2, its last hop.
+pub fn test_des_shortcut_to_drone(){
+    let mut engine = DesEngine::from_file("inputs/input_generic_fragment_forward.toml");
+    engine.send(Duration::ZERO, 0, 1, Packet {
+        pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        routing_header: SourceRoutingHeader { hop_index: 1, hops: vec![0, 1, 2] },
+        session_id: 1,
+    });
+    engine.run();
+
+    let lost = engine.trace().iter().filter(|entry| matches!(entry.record, Record::Lost { to: 2, from: 2, .. })).count();
+    assert_eq!(lost, 1);
+    let shortcuts = engine.trace().iter().filter(|entry| matches!(entry.record, Record::Event { event: DroneEvent::ControllerShortcut(_), .. })).count();
+    assert_eq!(shortcuts, 1);
+    assert_eq!(engine.now(), Duration::ZERO);
+    println!("Shortcut to a drone simulated!");
+}
+
+//The same flooding comes back to drone 1 after 100ms of virtual time: with a max age of 50ms the drones forgot it
+//and forward it to 3 again, without one they answer it.
+pub fn test_des_flood_cache_age(){
+    let flooded_twice = |options: &SimulationOptions| {
+        let mut engine = DesEngine::new(parse_config("inputs/input_generic_fragment_forward.toml"), options);
+        for at in [Duration::ZERO, Duration::from_millis(100)] {
+            engine.send(at, 0, 1, Packet {
+                pack_type: PacketType::FloodRequest(FloodRequest { flood_id: 1, initiator_id: 0, path_trace: vec![(0, NodeType::Client)] }),
+                routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+                session_id: 1,
+            });
+        }
+        engine.run();
+        engine.delivered_to(3).iter().filter(|packet| matches!(packet.pack_type, PacketType::FloodRequest(_))).count()
+    };
+    assert_eq!(flooded_twice(&SimulationOptions::default()), 1);
+    assert_eq!(flooded_twice(&SimulationOptions { flood_cache_max_age_ms: Some(50), ..SimulationOptions::default() }), 2);
+    println!("Floodings forgotten in virtual time!");
+}
+
+//Runs drone 1, between 0 and 2 with pdr 0.5 and a flood limit, capturing it in the given file while it gets fragments, acks,
+//a flooding and some commands (one of them only for SkyLink drones), then crashes it.
+fn captured_drone(path: &std::path::Path) {
+    let (d1_packet_sender, d1_packet_receiver) = unbounded::<Packet>();
+    let (c0_packet_sender, _c0_packet_receiver) = unbounded::<Packet>();
+    let (c2_packet_sender, _c2_packet_receiver) = unbounded::<Packet>();
+    let (c3_packet_sender, _c3_packet_receiver) = unbounded::<Packet>();
+    let (d1_event_sender, _d1_event_receiver) = unbounded::<DroneEvent>();
+    let (d1_command_sender, d1_command_receiver) = unbounded::<DroneCommand>();
+    let (d1_skylink_sender, d1_skylink_receiver) = unbounded::<SkyLinkCommand>();
+
+    let mut senders = HashMap::new();
+    senders.insert(0, c0_packet_sender);
+    senders.insert(2, c2_packet_sender);
+    let mut drone = SkyLinkDrone::new(1, d1_event_sender, d1_command_receiver, d1_packet_receiver, senders, 0.5)
+        .with_seed(7)
+        .with_command_channel(d1_skylink_receiver)
+        .with_flood_limit(Some(2))
+        .with_capture(capture_file(path).unwrap());
+    let handle = thread::spawn(move || drone.run());
+
+    for index in 0..20 {
+        d1_packet_sender.send(fragment_with_index(index)).unwrap();
+    }
+    d1_packet_sender.send(ack_to_0()).unwrap();
+    d1_packet_sender.send(Packet {
+        pack_type: PacketType::FloodRequest(FloodRequest {
+            flood_id: 1,
+            initiator_id: 0,
+            path_trace: vec![(0, NodeType::Client)],
+        }),
+        routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+        session_id: 1,
+    }).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    d1_command_sender.send(SetPacketDropRate(0.0)).unwrap();
+    d1_command_sender.send(DroneCommand::AddSender(3, c3_packet_sender)).unwrap();
+    d1_skylink_sender.send(SkyLinkCommand::SetLinkPdr(2, Some(1.0))).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    for index in 20..25 {
+        d1_packet_sender.send(fragment_with_index(index)).unwrap();
+    }
+    thread::sleep(Duration::from_millis(50));
+    d1_command_sender.send(DroneCommand::Crash).unwrap();
+    d1_packet_sender.send(ack_to_0()).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    drop(d1_packet_sender);
+    handle.join().unwrap();
+}
+
+//A drone replayed from its capture sends exactly what it sent the first time.
+pub fn test_capture_replay(){
+    let path = std::env::temp_dir().join("skylink_test_capture.jsonl");
+    captured_drone(&path);
+
+    let capture = Capture::read_from(&path).unwrap();
+    assert_eq!(capture.header.snapshot.id, 1);
+    assert_eq!(capture.header.snapshot.pdr, 50);
+    assert_eq!(capture.header.snapshot.seed, 7);
+    assert_eq!(capture.header.snapshot.flood_limit, Some(2));
+    assert_eq!(capture.header.snapshot.neighbours, vec![0, 2]);
+    assert!(capture.records.iter().any(|record| record.captured == Captured::Command(CapturedCommand::AddSender(3))));
+    assert!(capture.records.iter().any(|record| record.captured == Captured::Command(CapturedCommand::SkyLink(CapturedSkyLinkCommand::SetLinkPdr(2, Some(1.0))))));
+    assert!(matches!(capture.outputs().last().unwrap().captured, Captured::Event(CapturedEvent::SkyLink(SkyLinkEvent::Crashed { drone: 1, forced: false }))));
+
+    let report = replay(&capture, |drone| drone);
+    for difference in report.differences.iter() {
+        println!("{:?}", difference);
+    }
+    assert!(report.is_identical());
+    assert_eq!(report.replayed.outputs().len(), capture.outputs().len());
+    let _ = std::fs::remove_file(&path);
+    println!("Replayed {} records, identical!", capture.records.len());
+}
+
+//Replaying with a different seed, the drone drops other fragments, and the replay tells where it diverges.
+pub fn test_capture_replay_diverges(){
+    let path = std::env::temp_dir().join("skylink_test_capture_diverges.jsonl");
+    captured_drone(&path);
+
+    let capture = Capture::read_from(&path).unwrap();
+    let report = replay(&capture, |drone| drone.with_seed(8));
+    assert!(!report.is_identical());
+    let first = &report.differences[0];
+    println!("First difference, at output {}: expected {:?}, got {:?}", first.index, first.expected, first.actual);
+    let _ = std::fs::remove_file(&path);
+    println!("Divergence found!");
+}
+
+//The drones trace every check they make, with the fields of the packet, and the filter can be changed while they run.
+//The subscriber is only used by this thread, so the other tests aren't traced.
+pub fn test_tracing(){
+    let path = std::env::temp_dir().join("skylink_test_trace.jsonl");
+    let (tracing, dispatch) = Tracing::scoped(&TraceOptions {
+        filter: Some("warn".to_string()),
+        console: false,
+        json_file: Some(path.to_string_lossy().to_string()),
+        gui: true,
+    }).unwrap();
+    let gui = tracing.gui().unwrap().clone();
+
+    //The subscriber only sees this thread, so drone 11 is stepped here.
+    let (d11_packet_sender, d11_packet_receiver) = unbounded::<Packet>();
+    let (c1_packet_sender, _c1_packet_receiver) = unbounded::<Packet>();
+    let (d12_packet_sender, d12_packet_receiver) = unbounded::<Packet>();
+    let (sc_sender, _sc_receiver) = unbounded();
+    let (_d11_command_sender, d11_command_receiver) = unbounded::<DroneCommand>();
+    let mut drone11 = SkyLinkDrone::new(11, sc_sender, d11_command_receiver, d11_packet_receiver, HashMap::from([(1, c1_packet_sender), (12, d12_packet_sender)]), 0.0);
+
+    tracing::dispatcher::with_default(&dispatch, || {
+        d11_packet_sender.send(create_packet(vec![1,11,12,21])).unwrap();
+        while drone11.step() == Step::Busy {}
+        assert!(gui.lines().is_empty());
+
+        tracing.set_filter("warn,SkyLinksProg::skylink_drone=trace").unwrap();
+        assert!(tracing.set_filter("SkyLinksProg=loud").is_err());
+        for _ in 0..5 {
+            d11_packet_sender.send(create_packet(vec![1,11,12,21])).unwrap();
+        }
+        while drone11.step() == Step::Busy {}
+    });
+    assert_eq!(d12_packet_receiver.try_iter().count(), 6);
+    assert!(!gui.lines().is_empty());
+
+    //Every check of drone 11 is traced inside the span of the packet, with its decision.
+    let json = std::fs::read_to_string(&path).unwrap();
+    let events = json.lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()).collect::<Vec<_>>();
+    let checks = events.iter().filter(|event| {
+        let span = &event["span"];
+        event["fields"]["check"] == "id_hop_match" && span["drone"] == 11 && span["hop_index"] == 1 && span["kind"] == "MsgFragment"
+    }).count();
+    assert_eq!(checks, 5);
+    assert!(events.iter().any(|event| event["fields"]["decision"] == "forward" && event["fields"]["next_hop"] == 12));
+    let _ = std::fs::remove_file(&path);
+    println!("Tracing checked!");
+}
+
+//Floods the network of the file from client 0 (connected to drone 1) in virtual time, and counts
+//the FloodRequests received by the drones and the FloodResponses back at the client.
+fn flood_amplification(file: &str, max_path_trace: Option<usize>) -> (usize, usize) {
+    let options = SimulationOptions {
+        seed: Some(1),
+        max_path_trace,
+        ..SimulationOptions::default()
+    };
+    let mut engine = DesEngine::new(parse_config(file), &options);
+    engine.send(Duration::ZERO, 0, 1, Packet {
+        pack_type: PacketType::FloodRequest(FloodRequest {
+            flood_id: 1,
+            initiator_id: 0,
+            path_trace: vec![(0, NodeType::Client)],
+        }),
+        routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+        session_id: 0,
+    });
+    engine.run();
+
+    let requests = engine.trace().iter().filter(|entry| matches!(&entry.record,
+        Record::Received { packet: Packet { pack_type: PacketType::FloodRequest(_), .. }, .. })).count();
+    let responses = engine.delivered_to(0).into_iter().filter(|packet| {
+        match &packet.pack_type {
+            PacketType::FloodResponse(response) => {
+                //No path is longer than the limit.
+                assert!(max_path_trace.is_none_or(|limit| response.path_trace.len() <= limit));
+                true
+            },
+            _ => false,
+        }
+    }).count();
+    (requests, responses)
+}
+
+//With a limit on the path_trace, a flooding of the butterfly and of the tree takes fewer FloodRequests,
+//and the client still gets responses.
+pub fn test_flood_limit(){
+    for file in ["inputs/input_butterfly.toml", "inputs/input_tree.toml"] {
+        let (requests, responses) = flood_amplification(file, None);
+        println!("{}: {} FloodRequests, {} FloodResponses without limit", file, requests, responses);
+        for limit in [5, 4, 3] {
+            let (limited_requests, limited_responses) = flood_amplification(file, Some(limit));
+            println!("{}: {} FloodRequests, {} FloodResponses with paths of {} nodes at most", file, limited_requests, limited_responses, limit);
+            assert!(limited_requests < requests);
+            assert!(limited_responses > 0);
+        }
+    }
+    println!("Flooding limited!");
+}
+
+//A drone told to limit its floodings at runtime answers with a FloodResponse, and counts it.
+pub fn test_flood_limit_command(){
+    let (sim_contr, clients, _handles) = test_initialize("inputs/input_double_chain_flood.toml");
+    let client = clients.first().unwrap();
+    let first = *client.client_send.keys().next().unwrap();
+    sim_contr.skylink_command_send[&first].send(SkyLinkCommand::SetFloodLimit(Some(2))).unwrap();
+
+    client.client_send[&first].send(Packet {
+        pack_type: PacketType::FloodRequest(FloodRequest {
+            flood_id: 1,
+            initiator_id: client.id,
+            path_trace: vec![(client.id, NodeType::Client)],
+        }),
+        routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+        session_id: 0,
+    }).unwrap();
+
+    let response = client.client_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+    match response.pack_type {
+        PacketType::FloodResponse(response) => assert_eq!(response.path_trace, vec![(client.id, NodeType::Client), (first, NodeType::Drone)]),
+        other => panic!("Expected a FloodResponse, got {:?}", other),
+    }
+    let stats = collect_stats(&sim_contr.skylink_command_send, Duration::from_secs(1));
+    assert_eq!(stats[&first].floods_limited, 1);
+    assert!(stats.values().filter(|stats| stats.drone != first).all(|stats| stats.floods_seen == 0));
+    println!("Flooding stopped at drone {}!", first);
+}
+
+//Waits for the battery to be empty, returning the levels reported before.
+fn expect_depleted(event_receiver: &Receiver<SkyLinkEvent>) -> Vec<u32> {
+    let mut levels = Vec::new();
+    loop {
+        match event_receiver.recv_timeout(Duration::from_secs(1)).unwrap() {
+            SkyLinkEvent::BatteryLevel { drone: 1, percentage, .. } => levels.push(percentage),
+            SkyLinkEvent::BatteryDepleted { drone: 1 } => return levels,
+            _ => {},
+        }
+    }
+}
+
+//The drone forwards as many fragments as its battery allows, reporting the charge on the way, then it crashes
+//and nacks the other fragments.
+pub fn test_battery_depletion(){
+    let (d1, drone1) = drone_1(&[], |drone| drone.with_crash_deadline(Some(Duration::from_millis(300))).with_battery(BatteryModel {
+        initial_charge: 10.0,
+        per_packet: 1.0,
+        per_byte: 0.0,
+        idle_drain: 0.0,
+    }));
+    let done_receiver = run_drone(drone1);
+
+    for _ in 0..10 {
+        d1.packet_send.send(create_packet(vec![0,1,2])).unwrap();
+    }
+    assert_eq!(expect_depleted(&d1.events), vec![90, 80, 70, 60, 50, 40, 30, 20, 10]);
+    for _ in 0..2 {
+        d1.packet_send.send(create_packet(vec![0,1,2])).unwrap();
+    }
+    for _ in 0..2 {
+        let nack = d1.neighbours[&0].recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(matches!(nack.pack_type, PacketType::Nack(Nack { nack_type: NackType::ErrorInRouting(1), .. })));
+    }
+    assert_eq!(d1.neighbours[&2].try_iter().count(), 10);
+
+    //The controller would now remove the drone from its neighbours, here the only neighbour with a channel to it is the test.
+    drop(d1.packet_send);
+    assert!(!expect_crashed(&d1.events));
+    done_receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    println!("Drone out of battery!");
+}
+
+//A fragment costs more the longer its payload.
+pub fn test_battery_cost(){
+    let (d1, mut drone1) = drone_1(&[], |drone| drone.with_battery(BatteryModel {
+        initial_charge: 1000.0,
+        per_packet: 1.0,
+        per_byte: 0.5,
+        idle_drain: 0.0,
+    }));
+
+    let mut short = create_packet(vec![0,1,2]);
+    if let PacketType::MsgFragment(fragment) = &mut short.pack_type {
+        fragment.length = 10;
+    }
+    d1.packet_send.send(create_packet(vec![0,1,2])).unwrap();
+    while drone1.step() == Step::Busy {}
+    assert_eq!(drone1.get_battery(), Some(1000.0 - 1.0 - 64.0));
+    d1.packet_send.send(short).unwrap();
+    while drone1.step() == Step::Busy {}
+    assert_eq!(drone1.get_battery(), Some(935.0 - 1.0 - 5.0));
+    d1.packet_send.send(ack_to_0()).unwrap();
+    while drone1.step() == Step::Busy {}
+    assert_eq!(drone1.get_battery(), Some(929.0 - 1.0));
+    assert_eq!(drone1.get_stats().battery, Some(928.0));
+    println!("Battery spent: {:?}", drone1.get_battery());
+}
+
+//A flooding sent to two neighbours costs two packets, but the battery is checked (and reported) once, after the whole flooding.
+pub fn test_battery_once_per_packet(){
+    let (d1, mut drone1) = drone_1(&[3], |drone| drone.with_battery(BatteryModel {
+        initial_charge: 10.0,
+        per_packet: 1.0,
+        per_byte: 0.0,
+        idle_drain: 0.0,
+    }));
+
+    d1.packet_send.send(Packet {
+        pack_type: PacketType::FloodRequest(FloodRequest { flood_id: 1, initiator_id: 0, path_trace: vec![(0, NodeType::Client)] }),
+        routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+        session_id: 1,
+    }).unwrap();
+    while drone1.step() == Step::Busy {}
+    assert_eq!(drone1.get_battery(), Some(8.0));
+    let levels = d1.events.try_iter().filter_map(|event| match event {
+        SkyLinkEvent::BatteryLevel { drone: 1, percentage, .. } => Some(percentage),
+        _ => None,
+    }).collect::<Vec<u32>>();
+    assert_eq!(levels, vec![80]);
+    println!("Battery checked once!");
+}
+
+//A drone that does nothing still runs out of battery: on its clock alone, it wakes up at every level to report it,
+//and crashes right when the battery is empty.
+pub fn test_battery_idle_drain(){
+    let clock = ManualClock::new();
+    let (event_sender, event_receiver) = unbounded::<SkyLinkEvent>();
+    let mut drone = SkyLinkDrone::detached(1, RecordingTransport::new([0, 2]), 0.0)
+        .with_clock(clock.clone())
+        .with_event_channel(event_sender)
+        .with_battery(BatteryModel {
+            initial_charge: 100.0,
+            per_packet: 1.0,
+            per_byte: 0.0,
+            idle_drain: 10.0,
+        });
+
+    //The drain starts with the drone, and every second takes a level: the drone wakes up for each of them.
+    let mut elapsed = Duration::ZERO;
+    let Step::Idle(mut wait) = drone.step() else {
+        panic!("The drone should be waiting");
+    };
+    for percentage in (10..=90).rev().step_by(10) {
+        clock.advance(wait);
+        elapsed += wait;
+        let Step::Idle(next) = drone.step() else {
+            panic!("The drone stopped at {}%", percentage);
+        };
+        assert!(!drone.is_crashing());
+        let events = event_receiver.try_iter().collect::<Vec<SkyLinkEvent>>();
+        assert!(matches!(&events[..], [SkyLinkEvent::BatteryLevel { drone: 1, percentage: level, .. }] if *level == percentage), "{:?}", events);
+        wait = next;
+    }
+
+    //The tenth wake-up empties the battery, after 10 seconds (give or take the rounding of the floats).
+    clock.advance(wait);
+    elapsed += wait;
+    drone.step();
+    assert!(elapsed.abs_diff(Duration::from_secs(10)) < Duration::from_millis(1), "{:?}", elapsed);
+    assert_eq!(drone.get_battery(), Some(0.0));
+    assert!(drone.is_crashing());
+    assert!(matches!(&event_receiver.try_iter().collect::<Vec<SkyLinkEvent>>()[..], [SkyLinkEvent::BatteryDepleted { drone: 1 }]));
+    println!("Battery drained in {:?}!", elapsed);
+}
+
+//Sends 20 fragments through drone 1, returning the ones that weren't dropped.
+fn forwarded_fragments(d1_packet_sender: &Sender<Packet>, c0_packet_receiver: &Receiver<Packet>, c2_packet_receiver: &Receiver<Packet>) -> Vec<u64> {
+    for index in 0..20 {
+        d1_packet_sender.send(fragment_with_index(index)).unwrap();
+    }
+    let mut forwarded = Vec::new();
+    let mut dropped = 0;
+    while forwarded.len() + dropped < 20 {
+        select! {
+            recv(c2_packet_receiver) -> packet => {
+                if let PacketType::MsgFragment(fragment) = packet.unwrap().pack_type {
+                    forwarded.push(fragment.fragment_index);
+                }
+            },
+            recv(c0_packet_receiver) -> _nack => dropped += 1,
+            default(Duration::from_secs(1)) => panic!("Drone 1 didn't handle every fragment"),
+        }
+    }
+    forwarded
+}
+
+fn flood_from_0() -> Packet {
+    Packet {
+        pack_type: PacketType::FloodRequest(FloodRequest {
+            flood_id: 1,
+            initiator_id: 0,
+            path_trace: vec![(0, NodeType::Client)],
+        }),
+        routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+        session_id: 1,
+    }
+}
+
+//A drone restored from its snapshot remembers its floodings and counters, and drops the same fragments
+//the original drone drops after the snapshot: two drones restored from the same snapshot behave the same.
+pub fn test_snapshot_restore(){
+    let (d1, drone1) = drone_1_from(&[], |controller_send, command_recv, packet_recv, packet_send| {
+        SkyLinkDrone::new(1, controller_send, command_recv, packet_recv, packet_send, 0.5)
+            .with_seed(5)
+            .with_flood_limit(Some(4))
+    });
+    run_drone(drone1);
+
+    d1.packet_send.send(flood_from_0()).unwrap();
+    assert!(matches!(d1.neighbours[&2].recv_timeout(Duration::from_secs(1)).unwrap().pack_type, PacketType::FloodRequest(_)));
+    let before = forwarded_fragments(&d1.packet_send, &d1.neighbours[&0], &d1.neighbours[&2]);
+
+    let (reply_sender, reply_receiver) = unbounded();
+    d1.skylink_send.send(SkyLinkCommand::GetSnapshot(reply_sender)).unwrap();
+    let snapshot = reply_receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!((snapshot.id, snapshot.pdr, snapshot.flood_limit), (1, 50, Some(4)));
+    assert_eq!(snapshot.neighbours, vec![0, 2]);
+    assert_eq!(snapshot.flood_ids.floodings, vec![(1, 0)]);
+    assert_eq!(snapshot.stats.per_neighbour[&2].forwarded, before.len() as u64 + 1);
+    //The snapshot survives a trip through JSON.
+    let snapshot: DroneSnapshot = serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+    //But not with a flood cache that remembers nothing.
+    let mut empty_cache = serde_json::to_value(&snapshot).unwrap();
+    empty_cache["flood_ids"]["capacity"] = 0.into();
+    assert!(serde_json::from_value::<DroneSnapshot>(empty_cache).is_err());
+    let expected = forwarded_fragments(&d1.packet_send, &d1.neighbours[&0], &d1.neighbours[&2]);
+    println!("Fragments forwarded after the snapshot: {:?}", expected);
+
+    for fork in ["a", "b"] {
+        let (d1, drone1) = drone_1_from(&[], |controller_send, command_recv, packet_recv, packet_send| {
+            SkyLinkDrone::restore(&snapshot, controller_send, command_recv, packet_recv, packet_send)
+        });
+        run_drone(drone1);
+        assert_eq!(forwarded_fragments(&d1.packet_send, &d1.neighbours[&0], &d1.neighbours[&2]), expected, "fork {}", fork);
+
+        //The flooding was already met before the snapshot.
+        d1.packet_send.send(flood_from_0()).unwrap();
+        assert!(matches!(d1.neighbours[&0].recv_timeout(Duration::from_secs(1)).unwrap().pack_type, PacketType::FloodResponse(_)));
+        let stats = collect_stats(&HashMap::from([(1, d1.skylink_send)]), Duration::from_secs(1));
+        assert_eq!((stats[&1].floods_seen, stats[&1].floods_repeated), (1, 1));
+        assert_eq!(stats[&1].per_neighbour[&2].forwarded, (before.len() + expected.len()) as u64 + 1);
+    }
+    println!("Drone restored twice!");
+}
+
+//The controller checkpoints the network, and resumes it twice: each resumed network goes on by itself.
+pub fn test_checkpoint_resume(){
+    let (mut sim_contr, _handles) = initialize("inputs/input_generic_nack.toml");
+    assert_eq!(sim_contr.set_flood_limit(11, Some(3)), Ok(()));
+    thread::sleep(Duration::from_millis(100));
+    let checkpoint = sim_contr.checkpoint().unwrap();
+    assert_eq!(checkpoint.drones.len(), 2);
+    assert_eq!(checkpoint.drones[&11].flood_limit, Some(3));
+    assert_eq!(checkpoint.drones[&12].pdr, 100);
+
+    let path = std::env::temp_dir().join("skylink_test_checkpoint.json");
+    checkpoint.write_to(&path).unwrap();
+    let checkpoint = Checkpoint::read_from(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    //With the same options, the network is resumed as it was; with others, the drones get the new crash deadline.
+    let (mut resumed_a, _handles_a) = resume(&checkpoint, &parse_options("inputs/input_generic_nack.toml"));
+    let options_b = SimulationOptions { workers: Some(1), crash_deadline_ms: Some(50), ..Default::default() };
+    let (mut resumed_b, _handles_b) = resume(&checkpoint, &options_b);
+    assert_eq!(resumed_a.checkpoint().unwrap(), checkpoint);
+    assert_eq!(resumed_b.set_flood_limit(11, None), Ok(()));
+    thread::sleep(Duration::from_millis(100));
+    let checkpoint_b = resumed_b.checkpoint().unwrap();
+    assert_eq!(checkpoint_b.drones[&11].flood_limit, None);
+    assert_eq!(checkpoint_b.drones[&12].crash_deadline, Some(Duration::from_millis(50)));
+    assert_eq!(checkpoint_b.drones[&12].pdr, checkpoint.drones[&12].pdr);
+    assert_eq!(resumed_a.checkpoint().unwrap().drones[&11].flood_limit, Some(3));
+
+    //A snapshot of a drone that isn't in the network isn't resumed.
+    let mut without_12 = checkpoint.clone();
+    without_12.network_graph.remove(&12);
+    for neighbours in without_12.network_graph.values_mut() {
+        neighbours.retain(|id| *id != 12);
+    }
+    let (mut resumed_c, _handles_c) = resume(&without_12, &parse_options("inputs/input_generic_nack.toml"));
+    assert!(resumed_c.get_node(12).is_none());
+    assert_eq!(resumed_c.set_flood_limit(12, None), Err(TopologyError::UnknownNode(12)));
+    assert_eq!(resumed_c.checkpoint().unwrap().drones.keys().copied().collect::<Vec<NodeId>>(), vec![11]);
+    println!("Network resumed twice!");
+}
+
+//A drone the controller thinks is running, but which never answers, makes the checkpoint fail instead of leaving it out.
+pub fn test_checkpoint_no_answer(){
+    let (event_send, event_recv) = unbounded();
+    let (skylink_event_send, skylink_event_recv) = unbounded();
+    let (skylink_send, _skylink_recv) = unbounded();
+    let mut sim_contr = SimulationControl::new(HashMap::new(), event_recv, event_send, HashMap::new(), HashMap::from([(5, vec![])]))
+        .with_skylink_channels(HashMap::from([(5, skylink_send)]), skylink_event_recv, skylink_event_send.clone())
+        .with_nodes(HashMap::from([(5, NodeInfo::new(NodeType::Drone, Some(0.0)))]));
+    assert_eq!(sim_contr.checkpoint(), Err(CheckpointError::NoAnswer(vec![5])));
+
+    //Once the controller knows it stopped, it's left out.
+    skylink_event_send.send(SkyLinkEvent::Crashed { drone: 5, forced: false }).unwrap();
+    let checkpoint = sim_contr.checkpoint().unwrap();
+    assert!(checkpoint.drones.is_empty());
+    assert!(checkpoint.network_graph.is_empty());
+    println!("Checkpoint refused while a drone didn't answer!");
+}
+
+//A drone without channels nor threads: the test gives it the packets, moves its clock, and reads exactly what it sent.
+pub fn test_detached_drone(){
+    //Drawing the lowest numbers, a drone with pdr 0.5 drops the fragment and nacks it.
+    let transport = RecordingTransport::new([0, 2]);
+    let mut drone = SkyLinkDrone::detached(1, transport.clone(), 0.5)
+        .with_random_source(FixedRandom { at: 0.0 });
+    let fragment = create_packet(vec![0,1,2]);
+    drone.handle_packet(fragment.clone());
+    let sent = transport.take_packets();
+    assert_eq!(sent.len(), 1);
+    assert!(matches!(&sent[0], (0, Packet { pack_type: PacketType::Nack(Nack { nack_type: NackType::Dropped, .. }), .. })));
+    let events = transport.take_events();
+    assert!(matches!(&events[..], [DroneEvent::PacketDropped(dropped), DroneEvent::PacketSent(_)] if *dropped == fragment));
+
+    //A flooding goes to every other neighbour, and the second time it's answered.
+    drone.handle_packet(flood_from_0());
+    let mut forwarded = flood_from_0();
+    if let PacketType::FloodRequest(flood_request) = &mut forwarded.pack_type {
+        flood_request.path_trace.push((1, NodeType::Drone));
+    }
+    assert_eq!(transport.take_packets(), vec![(2, forwarded)]);
+    drone.handle_packet(flood_from_0());
+    assert!(matches!(&transport.take_packets()[..], [(0, Packet { pack_type: PacketType::FloodResponse(_), .. })]));
+
+    //Drawing the highest numbers, nothing is dropped, and the fragment crosses a link of 50ms on the manual clock.
+    let transport = RecordingTransport::new([0, 2]);
+    let clock = ManualClock::new();
+    let mut drone = SkyLinkDrone::detached(1, transport.clone(), 0.5)
+        .with_random_source(FixedRandom { at: 1.0 })
+        .with_clock(clock.clone())
+        .with_links(HashMap::from([(2, LinkParams { delay: Duration::from_millis(50), ..LinkParams::default() })]));
+    drone.handle_packet(fragment.clone());
+    assert!(transport.take_packets().is_empty());
+    assert_eq!(drone.step(), Step::Idle(Duration::from_millis(50)));
+    clock.advance(Duration::from_millis(49));
+    assert_eq!(drone.step(), Step::Idle(Duration::from_millis(1)));
+    assert!(transport.take_packets().is_empty());
+    clock.advance(Duration::from_millis(1));
+    drone.step();
+    let mut expected = fragment;
+    expected.routing_header.hop_index = 2;
+    assert_eq!(transport.take_packets(), vec![(2, expected.clone())]);
+    assert!(matches!(&transport.take_events()[..], [DroneEvent::PacketSent(sent)] if *sent == expected));
+    assert_eq!(drone.step(), Step::Idle(Duration::MAX));
+    println!("Detached drone tested!");
+}
+
+//The events sent to a Simulation Controller reach its log through the pump, while the controller is shared, as the GUI does.
+pub fn test_event_pump(){
+    let (event_send, event_recv) = unbounded::<DroneEvent>();
+    let (skylink_event_send, skylink_event_recv) = unbounded::<SkyLinkEvent>();
+    let sim_contr = SimulationControl::new(HashMap::new(), event_recv, event_send.clone(), HashMap::new(), HashMap::new())
+        .with_skylink_channels(HashMap::new(), skylink_event_recv, skylink_event_send.clone());
+
+    //Without a pump, the events wait until they're asked for.
+    let mut sim_contr = sim_contr;
+    event_send.send(DroneEvent::PacketDropped(create_packet(vec![0,12,2]))).unwrap();
+    assert_eq!(sim_contr.get_dropped_packets(12), 0);
+    assert_eq!(sim_contr.handle_pending_events(), 1);
+    assert_eq!(sim_contr.get_dropped_packets(12), 1);
+    assert_eq!(sim_contr.handle_pending_events(), 0);
+
+    //A sent packet names the drone before its hop_index, a FloodRequest the last one of its path_trace.
+    let mut sent = create_packet(vec![0,12,2]);
+    sent.routing_header.hop_index = 2;
+    event_send.send(DroneEvent::PacketSent(sent)).unwrap();
+    event_send.send(DroneEvent::PacketSent(Packet {
+        pack_type: PacketType::FloodRequest(FloodRequest {
+            flood_id: 1,
+            initiator_id: 0,
+            path_trace: vec![(0, NodeType::Client), (13, NodeType::Drone)],
+        }),
+        routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+        session_id: 2,
+    })).unwrap();
+    assert_eq!(sim_contr.handle_pending_events(), 2);
+    assert!(sim_contr.log.iter().any(|line| line.starts_with("Drone 12 sent fragment 1 ")));
+    assert!(sim_contr.log.iter().any(|line| line.starts_with("Drone 13 sent fragment 2 ")));
+
+    let sim_contr = Arc::new(Mutex::new(sim_contr));
+    let pump = EventPump::start(sim_contr.clone());
+    //A PacketSent without hops doesn't tell which drone sent it, but mustn't stop the pump.
+    let mut no_hops = create_packet(vec![]);
+    no_hops.routing_header.hop_index = 0;
+    event_send.send(DroneEvent::PacketSent(no_hops)).unwrap();
+    event_send.send(DroneEvent::PacketDropped(create_packet(vec![0,12,2]))).unwrap();
+    skylink_event_send.send(SkyLinkEvent::BatteryLevel { drone: 12, percentage: 40, charge: 400.0 }).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    loop {
+        {
+            let sim_contr = sim_contr.lock().unwrap();
+            if sim_contr.get_dropped_packets(12) == 2 && sim_contr.get_battery(12).is_some() {
+                assert_eq!(sim_contr.get_battery(12), Some(400.0));
+                assert!(sim_contr.log.iter().any(|line| line == "Drone 12 has 40% of its battery left"));
+                assert_eq!(sim_contr.log.iter().filter(|line| line.starts_with("Drone 12 dropped fragment")).count(), 2);
+                break;
+            }
+        }
+        assert!(Instant::now() < deadline, "the pump didn't handle the events");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    //Once the pump is dropped, the events are left to the controller again.
+    drop(pump);
+    event_send.send(DroneEvent::PacketDropped(create_packet(vec![0,12,2]))).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    let mut sim_contr = sim_contr.lock().unwrap();
+    assert_eq!(sim_contr.get_dropped_packets(12), 2);
+    assert_eq!(sim_contr.handle_pending_events(), 1);
+    assert_eq!(sim_contr.get_dropped_packets(12), 3);
+    println!("Event pump tested!");
+}
+
+//Acks and Nacks the drones can't route reach their destination through the Simulation Controller,
+//unless it's not in the network, it doesn't receive anymore, or it's a drone.
+pub fn test_controller_shortcut(){
+    let (my_sim_contr, mut clients, _handles) = test_initialize("inputs/input_generic_nack.toml");
+    let mut sim_contr = my_sim_contr.into_sim_contr();
+    let client_1 = clients.remove(0);
+    let client_21 = clients.remove(0);
+
+    //Drone 11 isn't connected to client 21, so the Ack of client 1 goes through the controller.
+    let ack = Packet {
+        pack_type: PacketType::Ack(Ack { fragment_index: 3 }),
+        routing_header: SourceRoutingHeader { hop_index: 1, hops: vec![1, 11, 21] },
+        session_id: 7,
+    };
+    send_packet(ack.clone(), &client_1.client_send[&11]);
+    wait_shortcuts(&mut sim_contr, 21, 1);
+    let delivered = client_21.client_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(delivered.pack_type, ack.pack_type);
+    assert_eq!(delivered.session_id, 7);
+    assert_eq!(delivered.routing_header, SourceRoutingHeader { hop_index: 2, hops: vec![1, 11, 21] });
+
+    //Nobody has id 5.
+    let nack = Packet {
+        pack_type: PacketType::Nack(Nack { fragment_index: 0, nack_type: NackType::Dropped }),
+        routing_header: SourceRoutingHeader { hop_index: 1, hops: vec![21, 12, 11, 5] },
+        session_id: 8,
+    };
+    send_packet(nack, &client_21.client_send[&12]);
+    wait_shortcuts(&mut sim_contr, 5, 1);
+
+    //Client 1 stops receiving, drone 12 isn't connected to it either.
+    drop(client_1);
+    let ack = Packet {
+        pack_type: PacketType::Ack(Ack { fragment_index: 4 }),
+        routing_header: SourceRoutingHeader { hop_index: 1, hops: vec![21, 12, 1] },
+        session_id: 9,
+    };
+    send_packet(ack, &client_21.client_send[&12]);
+    wait_shortcuts(&mut sim_contr, 1, 1);
+
+    //Drone 11 is the last hop, it would send the Ack back to the controller if it got it.
+    let ack = Packet {
+        pack_type: PacketType::Ack(Ack { fragment_index: 5 }),
+        routing_header: SourceRoutingHeader { hop_index: 1, hops: vec![21, 12, 11] },
+        session_id: 10,
+    };
+    send_packet(ack, &client_21.client_send[&12]);
+    wait_shortcuts(&mut sim_contr, 11, 1);
+    thread::sleep(Duration::from_millis(100));
+    sim_contr.handle_pending_events();
+
+    assert_eq!(sim_contr.get_shortcuts(21), ShortcutCounters { delivered: 1, undeliverable: 0 });
+    assert_eq!(sim_contr.get_shortcuts(5), ShortcutCounters { delivered: 0, undeliverable: 1 });
+    assert_eq!(sim_contr.get_shortcuts(1), ShortcutCounters { delivered: 0, undeliverable: 1 });
+    //Handled once, not bounced back and forth.
+    assert_eq!(sim_contr.get_shortcuts(11), ShortcutCounters { delivered: 0, undeliverable: 1 });
+    assert!(sim_contr.log.iter().any(|line| line == "Can't deliver Ack of session 10 to node 11: it's a drone"));
+    assert!(sim_contr.log.iter().any(|line| line == "Delivered Ack of session 7 to node 21 through the controller"));
+    assert!(sim_contr.log.iter().any(|line| line == "Can't deliver Nack of session 8 to node 5: it isn't in the network"));
+    assert!(sim_contr.log.iter().any(|line| line == "Can't deliver Ack of session 9 to node 1: it doesn't receive anymore"));
+    println!("Controller shortcuts tested!");
+}
+
+//Handles the events until `count` shortcuts to `destination` were handled, delivered or not.
+fn wait_shortcuts(sim_contr: &mut SimulationControl, destination: NodeId, count: u64) {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    loop {
+        sim_contr.handle_pending_events();
+        let counters = sim_contr.get_shortcuts(destination);
+        if counters.delivered + counters.undeliverable >= count {
+            return;
+        }
+        assert!(Instant::now() < deadline, "no shortcut to {} was handled", destination);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+//The links change on both sides, in the graph of the controller and in the drones, and the wrong changes are refused.
+pub fn test_topology_changes(){
+    let (sim_contr, _handles) = initialize("inputs/input_generic_nack.toml");
+    let (endpoint_send, endpoint_recv) = endpoint_channels(&[1, 21]);
+    let mut sim_contr = sim_contr.with_endpoint_channels(endpoint_send);
+    let neighbours = |sim_contr: &mut SimulationControl| {
+        thread::sleep(Duration::from_millis(100));
+        sim_contr.checkpoint().unwrap().drones.into_iter().map(|(id, snapshot)| (id, snapshot.neighbours)).collect::<HashMap<NodeId, Vec<NodeId>>>()
+    };
+
+    assert_eq!(sim_contr.add_link(11, 21), Ok(()));
+    assert!(matches!(endpoint_recv[&21].try_recv(), Ok(DroneCommand::AddSender(11, _))));
+    assert_eq!(sim_contr.add_link(21, 11), Err(TopologyError::AlreadyLinked(21, 11)));
+    assert_eq!(sim_contr.add_link(11, 11), Err(TopologyError::SelfLink(11)));
+    assert_eq!(sim_contr.add_link(11, 99), Err(TopologyError::UnknownNode(99)));
+    assert_eq!(sim_contr.remove_link(11, 12), Ok(()));
+    assert_eq!(sim_contr.remove_link(12, 11), Err(TopologyError::NotLinked(12, 11)));
+    assert_eq!(neighbours(&mut sim_contr), HashMap::from([(11, vec![1, 21]), (12, vec![21])]));
+    assert!(sim_contr.network_graph[&21].contains(&11));
+    assert!(!sim_contr.network_graph[&12].contains(&11));
+
+    assert_eq!(sim_contr.set_pdr(1, 0.5), Err(TopologyError::NotADrone(1)));
+    assert_eq!(sim_contr.set_pdr(11, 1.5), Err(TopologyError::InvalidPdr(1.5)));
+    assert_eq!(sim_contr.set_pdr(12, 0.25), Ok(()));
+    assert_eq!(sim_contr.set_flood_limit(1, Some(3)), Err(TopologyError::NotADrone(1)));
+    assert_eq!(sim_contr.set_fault_profile(99, None), Err(TopologyError::UnknownNode(99)));
+    assert_eq!(sim_contr.set_link(11, 99, LinkParams::default()), Err(TopologyError::UnknownNode(99)));
+    assert_eq!(sim_contr.set_link_pdr(12, 11, Some(0.5)), Err(TopologyError::NotLinked(12, 11)));
+    assert_eq!(sim_contr.set_link_pdr(11, 21, Some(2.0)), Err(TopologyError::InvalidPdr(2.0)));
+    assert_eq!(sim_contr.set_link_pdr(11, 21, Some(0.5)), Ok(()));
+
+    //The new drone takes the first free id, the ones of clients and servers are taken too.
+    assert_eq!(sim_contr.spawn_drone(0.5, vec![11, 99]).err(), Some(TopologyError::UnknownNode(99)));
+    assert_eq!(sim_contr.spawn_drone(0.5, vec![11, 11]).err(), Some(TopologyError::DuplicateNeighbour(11)));
+    let new_id = sim_contr.spawn_drone(0.0, vec![11, 1]).unwrap();
+    assert_eq!(new_id, 0);
+    assert!(matches!(endpoint_recv[&1].try_recv(), Ok(DroneCommand::AddSender(0, _))));
+    assert_eq!(sim_contr.network_graph[&1], vec![11, 0]);
+    let network = neighbours(&mut sim_contr);
+    assert_eq!(network[&0], vec![1, 11]);
+    assert_eq!(network[&11], vec![0, 1, 21]);
+    assert_eq!(sim_contr.checkpoint().unwrap().drones[&12].pdr, 25);
+
+    //A crashed drone isn't in the network anymore.
+    assert_eq!(sim_contr.crash_drone(12), Ok(()));
+    assert!(!sim_contr.network_graph.contains_key(&12));
+    assert!(!sim_contr.network_graph[&21].contains(&12));
+    assert_eq!(sim_contr.add_link(12, 11), Err(TopologyError::UnknownNode(12)));
+    assert_eq!(sim_contr.set_flood_limit(12, None), Err(TopologyError::Unreachable(12)));
+    assert!(sim_contr.log.iter().any(|line| line == "topology change refused: node 12 isn't in the network"));
+    println!("Topology changes tested!");
+}
+
+//Command channels for the given clients and servers: the test reads them in place of the nodes.
+fn endpoint_channels(ids: &[NodeId]) -> (HashMap<NodeId, Sender<DroneCommand>>, HashMap<NodeId, Receiver<DroneCommand>>) {
+    let mut endpoint_send = HashMap::new();
+    let mut endpoint_recv = HashMap::new();
+    for id in ids {
+        let (send, recv) = unbounded::<DroneCommand>();
+        endpoint_send.insert(*id, send);
+        endpoint_recv.insert(*id, recv);
+    }
+    (endpoint_send, endpoint_recv)
+}
+
+//Client 1 of inputs/input_generic_nack.toml gets a link to drone 12, so the packets cross it both ways,
+//until the link is removed. Client 21 has no command channel, so its links can't change.
+pub fn test_client_link(){
+    let (my_sim_contr, mut clients, _handles) = test_initialize("inputs/input_generic_nack.toml");
+    let (endpoint_send, endpoint_recv) = endpoint_channels(&[1]);
+    let mut sim_contr = my_sim_contr.into_sim_contr().with_endpoint_channels(endpoint_send);
+    let mut client_1 = clients.remove(0);
+    let client_21 = clients.remove(0);
+
+    assert_eq!(sim_contr.add_link(21, 11), Err(TopologyError::NoCommandChannel(21)));
+    assert!(!sim_contr.network_graph[&11].contains(&21));
+
+    //Client 1 does what the command says, as a client of the protocol would.
+    assert_eq!(sim_contr.add_link(1, 12), Ok(()));
+    match endpoint_recv[&1].try_recv() {
+        Ok(DroneCommand::AddSender(id, sender)) => {
+            client_1.client_send.insert(id, sender);
+        },
+        command => panic!("Unexpected command {:?}", command),
+    }
+    let ack = |hops: Vec<NodeId>| Packet {
+        pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        routing_header: SourceRoutingHeader { hop_index: 1, hops },
+        session_id: 1,
+    };
+    client_1.client_send[&12].send(ack(vec![1,12,21])).unwrap();
+    assert_eq!(client_21.client_recv.recv_timeout(Duration::from_secs(1)).unwrap().routing_header.hops, vec![1,12,21]);
+    client_21.client_send[&12].send(ack(vec![21,12,1])).unwrap();
+    assert_eq!(client_1.client_recv.recv_timeout(Duration::from_secs(1)).unwrap().routing_header.hops, vec![21,12,1]);
+
+    assert_eq!(sim_contr.remove_link(1, 12), Ok(()));
+    assert!(matches!(endpoint_recv[&1].try_recv(), Ok(DroneCommand::RemoveSender(12))));
+    println!("Packets crossed the link between client and drone!");
+}
+
+//In inputs/input.toml client 4 has drones 2 and 3, client 5 has drone 1, and server 6 has drones 2 and 3:
+//the controller refuses the changes that would cut them off, and tells why.
+pub fn test_unsafe_changes(){
+    let (sim_contr, _handles) = initialize("inputs/input.toml");
+    let (endpoint_send, _endpoint_recv) = endpoint_channels(&[4, 5, 6]);
+    let mut sim_contr = sim_contr.with_endpoint_channels(endpoint_send);
+    assert_eq!(sim_contr.crash_drone(1), Err(TopologyError::Unsafe(Violation::ClientDegree { client: 5, drones: 0 })));
+    assert_eq!(sim_contr.crash_drone(2), Err(TopologyError::Unsafe(Violation::ServerDegree { server: 6, drones: 1 })));
+    assert_eq!(sim_contr.crash_drone(4), Err(TopologyError::NotADrone(4)));
+    assert_eq!(sim_contr.remove_link(5, 1), Err(TopologyError::Unsafe(Violation::ClientDegree { client: 5, drones: 0 })));
+    assert_eq!(sim_contr.add_link(4, 1), Err(TopologyError::Unsafe(Violation::ClientDegree { client: 4, drones: 3 })));
+    assert_eq!(sim_contr.remove_link(1, 2), Ok(()));
+    assert_eq!(sim_contr.remove_link(1, 3), Err(TopologyError::Unsafe(Violation::Partition { client: 5, server: 6 })));
+    assert!(sim_contr.log.iter().any(|line| line == "topology change refused: client 5 couldn't reach server 6"));
+
+    //With a third drone, the server can lose one.
+    let new_id = sim_contr.spawn_drone(0.0, vec![1, 6]).unwrap();
+    assert_eq!(sim_contr.spawn_drone(0.0, vec![4]).err(), Some(TopologyError::Unsafe(Violation::ClientDegree { client: 4, drones: 3 })));
+    assert_eq!(sim_contr.crash_drone(2), Ok(()));
+    assert_eq!(sim_contr.crash_drone(3), Err(TopologyError::Unsafe(Violation::ClientDegree { client: 4, drones: 0 })));
+    assert!(sim_contr.network_graph[&6].contains(&new_id));
+    println!("Unsafe changes refused!");
+}
+
+//The controller knows what every node of inputs/input.toml is, gives the new drones ids nobody has,
+//and follows a drone it spawned until it stops.
+pub fn test_node_registry(){
+    let (sim_contr, _handles) = initialize("inputs/input.toml");
+    let (endpoint_send, _endpoint_recv) = endpoint_channels(&[6]);
+    let mut sim_contr = sim_contr.with_endpoint_channels(endpoint_send);
+    assert_eq!(sim_contr.get_node(4).map(|node| node.kind), Some(NodeType::Client));
+    assert_eq!(sim_contr.get_node(6).map(|node| node.kind), Some(NodeType::Server));
+    assert_eq!(sim_contr.get_node(1).and_then(|node| node.pdr), Some(0.05));
+    assert_eq!(sim_contr.set_pdr(1, 0.5), Ok(()));
+    assert_eq!(sim_contr.get_node(1).and_then(|node| node.pdr), Some(0.5));
+    assert_eq!(sim_contr.get_node(6).and_then(|node| node.pdr), None);
+
+    //0 is free, then 4, 5 and 6 are taken by the clients and the server.
+    assert_eq!(sim_contr.spawn_drone(0.2, vec![1, 6]), Ok(0));
+    assert_eq!(sim_contr.spawn_drone(0.2, vec![0, 2]), Ok(7));
+    assert_eq!(sim_contr.get_node(7).and_then(|node| node.pdr), Some(0.2));
+
+    assert_eq!(sim_contr.crash_drone(7), Ok(()));
+    assert_eq!(sim_contr.get_node(7).map(|node| node.state), Some(NodeState::Crashing));
+    assert_eq!(sim_contr.crash_drone(7), Err(TopologyError::Unreachable(7)));
+    assert_eq!(sim_contr.set_pdr(7, 0.1), Err(TopologyError::Unreachable(7)));
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while sim_contr.get_node(7).map(|node| node.state) != Some(NodeState::Stopped) {
+        assert!(Instant::now() < deadline, "drone 7 didn't stop");
+        sim_contr.handle_pending_events();
+        thread::sleep(Duration::from_millis(10));
+    }
+    sim_contr.take_handle(7).unwrap().join().unwrap();
+    assert!(sim_contr.take_handle(7).is_none());
+
+    //The id of a crashed drone isn't given again.
+    assert_eq!(sim_contr.spawn_drone(0.2, vec![0]), Ok(8));
+    println!("Node registry tested!");
+}
+
+//Client 0, drones 1 and 2 and server 3 in a chain, all tasks of the same single-threaded executor:
+//the server answers every fragment with an Ack, then drone 1 crashes and stops.
+#[cfg(feature = "async")]
+pub fn test_async_drones(){
+    use tokio::sync::mpsc::unbounded_channel;
+    use crate::skylink_drone::async_driver::AsyncDrone;
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+    runtime.block_on(async {
+        let (c0_send, mut c0_recv) = unbounded_channel::<Packet>();
+        let (d1_send, d1_recv) = unbounded_channel::<Packet>();
+        let (d2_send, d2_recv) = unbounded_channel::<Packet>();
+        let (s3_send, mut s3_recv) = unbounded_channel::<Packet>();
+        let (event_send, mut event_recv) = unbounded_channel::<DroneEvent>();
+        let (d1_command_send, d1_command_recv) = unbounded_channel::<DroneCommand>();
+        let (d2_command_send, d2_command_recv) = unbounded_channel::<DroneCommand>();
+        let (skylink_event_send, skylink_event_recv) = unbounded::<SkyLinkEvent>();
+
+        let drone1 = AsyncDrone::new(1, event_send.clone(), d1_command_recv, d1_recv, HashMap::from([(0, c0_send), (2, d2_send.clone())]), 0.0)
+            .configure(|drone| drone.with_event_channel(skylink_event_send.clone()).with_crash_deadline(Some(Duration::from_secs(1))));
+        let drone2 = AsyncDrone::new(2, event_send, d2_command_recv, d2_recv, HashMap::from([(1, d1_send.clone()), (3, s3_send)]), 0.0)
+            .configure(|drone| drone.with_event_channel(skylink_event_send.clone()).with_crash_deadline(Some(Duration::from_millis(200))));
+        let drone1 = tokio::spawn(drone1.run());
+        let drone2 = tokio::spawn(drone2.run());
+
+        let server = tokio::spawn(async move {
+            let mut acked = 0;
+            while let Some(packet) = s3_recv.recv().await {
+                if let PacketType::MsgFragment(fragment) = packet.pack_type {
+                    let mut hops = packet.routing_header.hops.clone();
+                    hops.reverse();
+                    let _ = d2_send.send(Packet {
+                        pack_type: PacketType::Ack(Ack { fragment_index: fragment.fragment_index }),
+                        routing_header: SourceRoutingHeader { hop_index: 1, hops },
+                        session_id: packet.session_id,
+                    });
+                    acked += 1;
+                }
+            }
+            acked
+        });
+
+        for index in 0..5 {
+            let mut packet = fragment_with_index(index);
+            packet.routing_header.hops = vec![0,1,2,3];
+            d1_send.send(packet).unwrap();
+        }
+        for index in 0..5 {
+            let ack = tokio::time::timeout(Duration::from_secs(1), c0_recv.recv()).await.unwrap().unwrap();
+            assert!(matches!(ack.pack_type, PacketType::Ack(Ack { fragment_index }) if fragment_index == index));
+        }
+        //Every hop of the fragments and of the acks reached the controller.
+        let mut sent = 0;
+        while let Ok(DroneEvent::PacketSent(_)) = event_recv.try_recv() {
+            sent += 1;
+        }
+        assert_eq!(sent, 5 * 2 * 2);
+
+        //Drone 1 crashes, and its neighbours let it go: it stops without waiting for the deadline.
+        d1_command_send.send(DroneCommand::Crash).unwrap();
+        d2_command_send.send(DroneCommand::RemoveSender(1)).unwrap();
+        drop(d1_send);
+        tokio::time::timeout(Duration::from_millis(500), drone1).await.unwrap().unwrap();
+        assert!(skylink_event_recv.try_iter().any(|event| event == SkyLinkEvent::Crashed { drone: 1, forced: false }));
+
+        d2_command_send.send(DroneCommand::Crash).unwrap();
+        drop(d2_command_send);
+        //Drone 2 keeps a channel to the server, which keeps one to drone 2: only the deadline stops it.
+        tokio::time::timeout(Duration::from_secs(1), drone2).await.unwrap().unwrap();
+        assert!(skylink_event_recv.try_iter().any(|event| event == SkyLinkEvent::Crashed { drone: 2, forced: true }));
+        //Then the server sees its channel closed.
+        assert_eq!(server.await.unwrap(), 5);
+    });
+    println!("Async drones ran on one thread!");
+}
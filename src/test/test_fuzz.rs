@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::thread;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use wg_2024::controller::{DroneCommand, DroneEvent};
+use wg_2024::drone::Drone;
+use wg_2024::network::{NodeId, SourceRoutingHeader};
+use wg_2024::packet::{Ack, FloodRequest, FloodResponse, Fragment, Nack, NackType, NodeType, Packet, PacketType};
+use crate::skylink_drone::drone::SkyLinkDrone;
+use crate::skylink_drone::scheduler::PriorityScheduler;
+
+//Property-based tests: the drone receives arbitrary packets and commands, generated from a seed,
+//so a failing case can be replayed by running it again with the printed seed.
+
+const DRONE_ID: NodeId = 1;
+const CASES: u64 = 200;
+const PACKETS_PER_CASE: usize = 500;
+
+//Ids are picked from a small range, so that they often match the drone and its neighbours.
+fn arbitrary_id(rng: &mut fastrand::Rng) -> NodeId {
+    rng.u8(0..6)
+}
+
+fn arbitrary_node_type(rng: &mut fastrand::Rng) -> NodeType {
+    match rng.u8(0..3) {
+        0 => NodeType::Client,
+        1 => NodeType::Drone,
+        _ => NodeType::Server,
+    }
+}
+
+fn arbitrary_nack_type(rng: &mut fastrand::Rng) -> NackType {
+    match rng.u8(0..4) {
+        0 => NackType::ErrorInRouting(arbitrary_id(rng)),
+        1 => NackType::DestinationIsDrone,
+        2 => NackType::Dropped,
+        _ => NackType::UnexpectedRecipient(arbitrary_id(rng)),
+    }
+}
+
+fn arbitrary_path_trace(rng: &mut fastrand::Rng) -> Vec<(NodeId, NodeType)> {
+    (0..rng.usize(0..5)).map(|_| (arbitrary_id(rng), arbitrary_node_type(rng))).collect()
+}
+
+pub fn arbitrary_packet(rng: &mut fastrand::Rng) -> Packet {
+    let hops: Vec<NodeId> = (0..rng.usize(0..6)).map(|_| arbitrary_id(rng)).collect();
+    //Most of the times the index is inside the header, sometimes it's anywhere.
+    let hop_index = if rng.u8(0..4) == 0 { rng.usize(..) } else { rng.usize(0..hops.len() + 2) };
+    let pack_type = match rng.u8(0..5) {
+        0 => PacketType::MsgFragment(Fragment {
+            fragment_index: rng.u64(..),
+            total_n_fragments: rng.u64(..),
+            length: rng.u8(..),
+            data: [rng.u8(..); 128],
+        }),
+        1 => PacketType::Ack(Ack { fragment_index: rng.u64(..) }),
+        2 => PacketType::Nack(Nack { fragment_index: rng.u64(..), nack_type: arbitrary_nack_type(rng) }),
+        3 => PacketType::FloodRequest(FloodRequest {
+            flood_id: rng.u64(0..4),
+            initiator_id: arbitrary_id(rng),
+            path_trace: arbitrary_path_trace(rng),
+        }),
+        _ => PacketType::FloodResponse(FloodResponse {
+            flood_id: rng.u64(0..4),
+            path_trace: arbitrary_path_trace(rng),
+        }),
+    };
+    Packet {
+        pack_type,
+        routing_header: SourceRoutingHeader { hop_index, hops },
+        session_id: rng.u64(..),
+    }
+}
+
+fn arbitrary_command(rng: &mut fastrand::Rng, neighbours: &HashMap<NodeId, Sender<Packet>>) -> DroneCommand {
+    match rng.u8(0..3) {
+        0 => DroneCommand::SetPacketDropRate(rng.f32() * 1.5 - 0.25),
+        1 => {
+            let id = arbitrary_id(rng);
+            match neighbours.get(&id) {
+                Some(sender) => DroneCommand::AddSender(id, sender.clone()),
+                None => DroneCommand::RemoveSender(id),
+            }
+        },
+        _ => DroneCommand::RemoveSender(arbitrary_id(rng)),
+    }
+}
+
+//Every packet the drone sends to a neighbour (except FloodRequests) must have that neighbour as current hop.
+fn check_received(neighbour: NodeId, receiver: &Receiver<Packet>, seed: u64) {
+    for packet in receiver.try_iter() {
+        if let PacketType::FloodRequest(_) = packet.pack_type {
+            continue;
+        }
+        assert_eq!(
+            packet.routing_header.hops.get(packet.routing_header.hop_index),
+            Some(&neighbour),
+            "seed {}: node {} received {:?}", seed, neighbour, packet
+        );
+    }
+}
+
+fn run_case(seed: u64) {
+    let mut rng = fastrand::Rng::with_seed(seed);
+
+    let (packet_sender, packet_receiver) = unbounded::<Packet>();
+    let (command_sender, command_receiver) = unbounded::<DroneCommand>();
+    let (event_sender, event_receiver) = unbounded::<DroneEvent>();
+
+    //Neighbours 0 and 2 are listening, neighbour 3 is already gone.
+    let mut receivers = HashMap::new();
+    let mut neighbours = HashMap::new();
+    for id in [0, 2, 3] {
+        let (send, recv) = unbounded::<Packet>();
+        neighbours.insert(id, send);
+        receivers.insert(id, recv);
+    }
+    receivers.remove(&3);
+
+    let mut drone = SkyLinkDrone::new(
+        DRONE_ID,
+        event_sender,
+        command_receiver,
+        packet_receiver,
+        neighbours.clone(),
+        rng.f32())
+        .with_seed(seed);
+    if rng.bool() {
+        drone = drone.with_scheduler(PriorityScheduler::new(rng.u32(1..5)));
+    }
+    let handle = thread::spawn(move || {
+        drone.run();
+    });
+
+    //Sometimes the Sim Contr goes away while the drone is still working.
+    let mut event_receiver = Some(event_receiver);
+    for _ in 0..PACKETS_PER_CASE {
+        if rng.u8(0..20) == 0 {
+            command_sender.send(arbitrary_command(&mut rng, &neighbours)).unwrap();
+        }
+        if rng.u16(0..1000) == 0 {
+            event_receiver = None;
+        }
+        packet_sender.send(arbitrary_packet(&mut rng)).unwrap();
+    }
+
+    command_sender.send(DroneCommand::Crash).unwrap();
+    for _ in 0..rng.usize(0..20) {
+        packet_sender.send(arbitrary_packet(&mut rng)).unwrap();
+    }
+    drop(packet_sender);
+
+    assert!(handle.join().is_ok(), "seed {}: the drone panicked", seed);
+    for (id, receiver) in receivers.iter() {
+        check_received(*id, receiver, seed);
+    }
+    if let Some(event_receiver) = event_receiver {
+        for event in event_receiver.try_iter() {
+            if let DroneEvent::PacketDropped(packet) = event {
+                assert_eq!(packet.routing_header.hops.get(packet.routing_header.hop_index), Some(&DRONE_ID), "seed {}", seed);
+            }
+        }
+    }
+}
+
+/// Feeds arbitrary packets and commands to a drone: it must never panic and never send a packet to the wrong node.
+pub fn test_arbitrary_packets() {
+    for seed in 0..CASES {
+        run_case(seed);
+    }
+    println!("{} cases of {} arbitrary packets passed!", CASES, PACKETS_PER_CASE);
+}
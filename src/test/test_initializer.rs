@@ -6,19 +6,30 @@ use wg_2024::config::Config;
 use wg_2024::controller::{DroneCommand, DroneEvent};
 use wg_2024::drone::Drone;
 use wg_2024::network::{NodeId};
-use wg_2024::packet::{Packet};
-use crate::skylink_drone::drone::SkyLinkDrone;
+use wg_2024::packet::{NodeType, Packet};
+use crate::initializer::{build_drone, parse_options, DroneChannels, DroneOrigin};
+use crate::sim_control::{NodeInfo, SimulationControl};
+use crate::skylink_drone::event::SkyLinkEvent;
+use crate::skylink_drone::command::SkyLinkCommand;
+use crate::skylink_drone::pool::DronePool;
 
 pub fn test_initialize(file: &str) -> (MySimContr, Vec<MyClient>, Vec<JoinHandle<()>>) {
     let config = parse_config(file);
+    let options = parse_options(file);
+    let seed = options.seed.unwrap_or_else(|| fastrand::u64(..));
+    let mut pool = options.workers.map(DronePool::new);
+    println!("Simulation seed: {}", seed);
     let mut handles = Vec::new();
     //I'll return the handles of the threads, and join them to the main thread.
 
     let mut command_send = HashMap::new();
+    let mut skylink_command_send = HashMap::new();
     //This will be given to the Sim Contr to command the drones.
     let (event_send, event_recv) = unbounded();
     //I create the channel, the 'send' will be given to every drone,
     //while the 'recv' will go to the Sim contr.
+    let (skylink_event_send, skylink_event_recv) = unbounded();
+    //Same for the events that only our drones send.
 
     let mut packet_senders = HashMap::new();
     let mut packet_receivers = HashMap::new();
@@ -35,30 +46,47 @@ pub fn test_initialize(file: &str) -> (MySimContr, Vec<MyClient>, Vec<JoinHandle
     }
 
 
-    for drone in config.drone.into_iter() {
+    for drone in config.drone.iter() {
         //Adding the sender to this drone to the senders of the Sim Contr.
         let (contr_send, contr_recv) = unbounded();
         command_send.insert(drone.id, contr_send);
-
-        //Give the drone a copy of the sender of events to the Sim Contr.
-        let node_event_send = event_send.clone();
+        let (skylink_contr_send, skylink_contr_recv) = unbounded();
+        skylink_command_send.insert(drone.id, skylink_contr_send);
 
         //Take the channels necessary to this drone.
-        let drone_recv = packet_receivers.remove(&drone.id).unwrap();
-        let drone_send = drone
-            .connected_node_ids
-            .into_iter()
-            .map(|id| (id, packet_senders[&id].clone()))
-            .collect();
-
-        //println!("Drone {} - channels:\n{:?}",drone.id, drone_send);
-
+        let channels = DroneChannels {
+            event_send: event_send.clone(),
+            command_recv: contr_recv,
+            packet_recv: packet_receivers.remove(&drone.id).unwrap(),
+            packet_send: drone.connected_node_ids.iter().map(|id| (*id, packet_senders[id].clone())).collect(),
+            skylink_event_send: skylink_event_send.clone(),
+            skylink_command_recv: skylink_contr_recv,
+        };
+        //println!("Drone {} - channels:\n{:?}",drone.id, channels.packet_send);
+        let mut drone = build_drone(DroneOrigin::Config(drone), &options, seed, channels);
+
+        match pool.as_mut() {
+            Some(pool) => pool.add(drone),
+            //create the thread of the drone, and add it to a Vec to be pushed afterward
+            None => handles.push(thread::spawn(move || {
+                drone.run();
+            })),
+        }
+    }
+    if let Some(pool) = pool.as_mut() {
+        handles.extend(pool.take_handles());
+    }
 
-        //create the thread of the drone, and add it to a Vec to be pushed afterward
-        handles.push(thread::spawn(move || {
-            let mut drone = SkyLinkDrone::new(drone.id, node_event_send, contr_recv, drone_recv, drone_send, drone.pdr);
-            drone.run();
-        }));
+    //The servers have no channels in the test network, so they aren't part of it.
+    let mut network_graph = HashMap::new();
+    let mut node_kinds = HashMap::new();
+    for drone in config.drone.iter() {
+        network_graph.insert(drone.id, drone.connected_node_ids.clone());
+        node_kinds.insert(drone.id, NodeType::Drone);
+    }
+    for client in config.client.iter() {
+        network_graph.insert(client.id, client.connected_drone_ids.clone());
+        node_kinds.insert(client.id, NodeType::Client);
     }
 
     let mut my_clients = Vec::new();
@@ -80,10 +108,16 @@ pub fn test_initialize(file: &str) -> (MySimContr, Vec<MyClient>, Vec<JoinHandle
 
     let sim_contr = MySimContr {
         command_send,
+        skylink_command_send,
         event_recv,
-        // event_send,
-        // packet_senders,
-        // network_graph
+        skylink_event_recv,
+        seed,
+        pool,
+        event_send,
+        skylink_event_send,
+        packet_senders,
+        network_graph,
+        node_kinds,
     };
 
 
@@ -103,8 +137,30 @@ pub struct MyClient {
 }
 pub struct MySimContr {
     pub command_send: HashMap<NodeId,Sender<DroneCommand>>,
+    pub skylink_command_send: HashMap<NodeId, Sender<SkyLinkCommand>>,
     pub event_recv: Receiver<DroneEvent>,
-    // pub event_send: Sender<DroneEvent>,
-    // pub packet_senders,
-    // pub network_graph
+    pub skylink_event_recv: Receiver<SkyLinkEvent>,
+    pub seed: u64,
+    pub pool: Option<DronePool>, //The workers leave only once it's dropped.
+    pub event_send: Sender<DroneEvent>,
+    pub skylink_event_send: Sender<SkyLinkEvent>,
+    pub packet_senders: HashMap<NodeId, Sender<Packet>>, //Of the drones and the clients.
+    pub network_graph: HashMap<NodeId, Vec<NodeId>>,
+    pub node_kinds: HashMap<NodeId, NodeType>,
+}
+
+impl MySimContr {
+    /// A Simulation Controller over the channels of the test network, which knows its graph and its nodes
+    /// as the one of `initialize` does.
+    pub fn into_sim_contr(self) -> SimulationControl {
+        let nodes = self.node_kinds.iter().map(|(id, kind)| (*id, NodeInfo::new(*kind, None))).collect();
+        let mut sim_contr = SimulationControl::new(self.command_send, self.event_recv, self.event_send, self.packet_senders, self.network_graph)
+            .with_seed(self.seed)
+            .with_skylink_channels(self.skylink_command_send, self.skylink_event_recv, self.skylink_event_send)
+            .with_nodes(nodes);
+        if let Some(pool) = self.pool {
+            sim_contr = sim_contr.with_pool(pool);
+        }
+        sim_contr
+    }
 }
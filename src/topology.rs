@@ -0,0 +1,113 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use wg_2024::network::NodeId;
+use wg_2024::packet::NodeType;
+
+//Rules of the WGL protocol on the shape of the network: the Simulation Controller can't break them
+//by crashing a drone or by changing a link.
+
+/// Most drones a client can be linked to.
+pub const MAX_CLIENT_DRONES: usize = 2;
+/// Fewest drones a server can be linked to.
+pub const MIN_SERVER_DRONES: usize = 2;
+
+/// A rule of the protocol the network doesn't follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// The client can't reach the server through the drones.
+    Partition { client: NodeId, server: NodeId },
+    /// A client must be linked to 1 or 2 drones.
+    ClientDegree { client: NodeId, drones: usize },
+    /// A server must be linked to at least 2 drones.
+    ServerDegree { server: NodeId, drones: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Partition { client, server } => write!(f, "client {} couldn't reach server {}", client, server),
+            Violation::ClientDegree { client, drones } => write!(f, "client {} would be linked to {} drones instead of 1 or {}", client, drones, MAX_CLIENT_DRONES),
+            Violation::ServerDegree { server, drones } => write!(f, "server {} would be linked to {} drones instead of at least {}", server, drones, MIN_SERVER_DRONES),
+        }
+    }
+}
+
+/// Every rule the network breaks, client by client and then server by server.
+/// A link counts if either of its nodes has it, since the configs don't always list it on both sides.
+/// The nodes whose kind isn't known are taken as drones.
+pub fn violations(network_graph: &HashMap<NodeId, Vec<NodeId>>, kinds: &HashMap<NodeId, NodeType>) -> Vec<Violation> {
+    let kind_of = |id: &NodeId| kinds.get(id).copied().unwrap_or(NodeType::Drone);
+    let links = both_ways(network_graph);
+    let drones_of = |id: &NodeId| links[id].iter().filter(|neighbour| kind_of(neighbour) == NodeType::Drone).count();
+
+    let mut clients = network_graph.keys().filter(|id| kind_of(id) == NodeType::Client).copied().collect::<Vec<NodeId>>();
+    let mut servers = network_graph.keys().filter(|id| kind_of(id) == NodeType::Server).copied().collect::<Vec<NodeId>>();
+    clients.sort();
+    servers.sort();
+
+    let mut violations = Vec::new();
+    for client in clients.iter() {
+        let drones = drones_of(client);
+        if drones == 0 || drones > MAX_CLIENT_DRONES {
+            violations.push(Violation::ClientDegree { client: *client, drones });
+        }
+        let reached = reachable(&links, *client, &kind_of);
+        for server in servers.iter() {
+            if !reached.contains(server) {
+                violations.push(Violation::Partition { client: *client, server: *server });
+            }
+        }
+    }
+    for server in servers.iter() {
+        let drones = drones_of(server);
+        if drones < MIN_SERVER_DRONES {
+            violations.push(Violation::ServerDegree { server: *server, drones });
+        }
+    }
+    violations
+}
+
+/// The first rule the change would break, among the ones the network follows now:
+/// a network that already breaks a rule can still be changed, as long as it doesn't get worse.
+pub fn check_change(
+    network_graph: &HashMap<NodeId, Vec<NodeId>>,
+    kinds: &HashMap<NodeId, NodeType>,
+    change: impl FnOnce(&mut HashMap<NodeId, Vec<NodeId>>),
+) -> Result<(), Violation> {
+    let before = violations(network_graph, kinds);
+    let mut changed = network_graph.clone();
+    change(&mut changed);
+    match violations(&changed, kinds).into_iter().find(|violation| !before.contains(violation)) {
+        Some(violation) => Err(violation),
+        None => Ok(()),
+    }
+}
+
+//The links of every node of the graph, in both directions, without the nodes that aren't in the graph.
+fn both_ways(network_graph: &HashMap<NodeId, Vec<NodeId>>) -> HashMap<NodeId, HashSet<NodeId>> {
+    let mut links = network_graph.keys().map(|id| (*id, HashSet::new())).collect::<HashMap<NodeId, HashSet<NodeId>>>();
+    for (id, neighbours) in network_graph.iter() {
+        for neighbour in neighbours.iter().filter(|neighbour| network_graph.contains_key(neighbour) && *neighbour != id) {
+            links.get_mut(id).unwrap().insert(*neighbour);
+            links.get_mut(neighbour).unwrap().insert(*id);
+        }
+    }
+    links
+}
+
+//Nodes a packet from `start` can reach, passing only through drones.
+fn reachable(links: &HashMap<NodeId, HashSet<NodeId>>, start: NodeId, kind_of: &impl Fn(&NodeId) -> NodeType) -> HashSet<NodeId> {
+    let mut reached = HashSet::from([start]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(id) = queue.pop_front() {
+        if id != start && kind_of(&id) != NodeType::Drone {
+            continue;
+        }
+        for neighbour in links[&id].iter() {
+            if reached.insert(*neighbour) {
+                queue.push_back(*neighbour);
+            }
+        }
+    }
+    reached
+}